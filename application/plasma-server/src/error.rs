@@ -1,3 +1,4 @@
+use std::time::Duration;
 use bson::document::ValueAccessError;
 use thiserror::Error;
 
@@ -12,6 +13,20 @@ pub enum Error {
     BodyError(&'static str),
     #[error("Invalid claim data: {0}")]
     InvalidClaimData(&'static str),
+    #[error("Forbidden: {0}")]
+    Forbidden(&'static str),
+    #[error("Device not approved")]
+    DeviceNotApproved,
+    #[error("Blocked: {0}")]
+    Blocked(&'static str),
+    #[error("Email address not verified")]
+    Unverified,
+    #[error("No one-time prekeys left")]
+    PrekeysExhausted,
+    #[error("Rate limited for {0:?}")]
+    RateLimited(Duration),
+    #[error(transparent)]
+    MailError( #[from] crate::mail::MailError ),
     #[error("Internal error")]
     InternalError,
 }
@@ -36,5 +51,20 @@ pub enum AuthorizationError {
     InvalidCredentials(&'static str),
     #[error("Missing authorization header")]
     MissingAuthHeader,
+    #[error("Session revoked")]
+    Revoked,
 }
 
+#[derive(Error, Debug)]
+pub enum ValidationError {
+    #[error("Invalid username: {0}")]
+    InvalidUsername(&'static str),
+    #[error("Invalid email address")]
+    InvalidEmail,
+    #[error("Password too weak: {0}")]
+    WeakPassword(&'static str),
+    #[error("Email already in use")]
+    EmailTaken,
+    #[error("Username already in use")]
+    UsernameTaken,
+}
@@ -0,0 +1,138 @@
+use std::path::PathBuf;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor, message::Mailbox, transport::smtp::authentication::Credentials};
+use thiserror::Error;
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+
+const MAILER: &str = "MAILER";
+const MAIL_FROM: &str = "MAIL_FROM";
+const MAIL_FILE: &str = "MAIL_FILE";
+const SMTP_HOST: &str = "SMTP_HOST";
+const SMTP_PORT: &str = "SMTP_PORT";
+const SMTP_USER: &str = "SMTP_USER";
+const SMTP_PASSWORD: &str = "SMTP_PASSWORD";
+const SMTP_TLS: &str = "SMTP_TLS";
+
+const DEFAULT_FROM: &str = "Plasma <no-reply@localhost>";
+const DEFAULT_FILE: &str = "mail.log";
+
+#[derive(Error, Debug)]
+pub enum MailError {
+    #[error("Mailer not configured: {0}")]
+    Config(&'static str),
+    #[error(transparent)]
+    Address(#[from] lettre::address::AddressError),
+    #[error(transparent)]
+    Message(#[from] lettre::error::Error),
+    #[error(transparent)]
+    Smtp(#[from] lettre::transport::smtp::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl Mail {
+    /// Plain text form written by the file and log backends.
+    fn to_text(&self) -> String {
+        format!("To: {}\nSubject: {}\n\n{}\n", self.to, self.subject, self.body)
+    }
+}
+
+/// Delivers account mails. `MAILER` picks the backend: `smtp` sends through
+/// `SMTP_HOST`, `file` appends to `MAIL_FILE` and `log`, the default, only logs
+/// them, so local setups need no mail server.
+pub enum Mailer {
+    Smtp {
+        transport: Box<AsyncSmtpTransport<Tokio1Executor>>,
+        from: Mailbox,
+    },
+    File(PathBuf),
+    Log,
+}
+
+impl Mailer {
+    pub fn from_env() -> Result<Self, MailError> {
+        let mailer = match dotenv::var(MAILER).as_deref() {
+            Ok("smtp") => Self::smtp_from_env()?,
+            Ok("file") => {
+                let path = dotenv::var(MAIL_FILE)
+                    .unwrap_or_else(|_| String::from(DEFAULT_FILE));
+                Mailer::File(PathBuf::from(path))
+            },
+            Ok("log") | Err(_) => Mailer::Log,
+            Ok(_) => return Err(MailError::Config("MAILER must be smtp, file or log")),
+        };
+        Ok(mailer)
+    }
+
+    fn smtp_from_env() -> Result<Self, MailError> {
+        let host = dotenv::var(SMTP_HOST)
+            .map_err(|_| MailError::Config("SMTP_HOST is not set"))?;
+        let mut builder = match dotenv::var(SMTP_TLS).as_deref() {
+            Ok("false") => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&host),
+            _ => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&host)?,
+        };
+        if let Some(port) = dotenv::var(SMTP_PORT).ok().and_then(|port| port.parse().ok()) {
+            builder = builder.port(port);
+        }
+        if let (Ok(user), Ok(password)) = (dotenv::var(SMTP_USER), dotenv::var(SMTP_PASSWORD)) {
+            builder = builder.credentials(Credentials::new(user, password));
+        }
+        let from = dotenv::var(MAIL_FROM)
+            .unwrap_or_else(|_| String::from(DEFAULT_FROM))
+            .parse()?;
+        Ok(Mailer::Smtp { transport: Box::new(builder.build()), from })
+    }
+
+    pub async fn send(&self, mail: &Mail) -> Result<(), MailError> {
+        match self {
+            Mailer::Smtp { transport, from } => {
+                let message = Message::builder()
+                    .from(from.clone())
+                    .to(mail.to.parse()?)
+                    .subject(&mail.subject)
+                    .body(mail.body.clone())?;
+                transport.send(message).await?;
+            },
+            Mailer::File(path) => {
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path).await?;
+                file.write_all(format!("{}\n", mail.to_text()).as_bytes()).await?;
+                file.flush().await?;
+            },
+            Mailer::Log => info!("Mail not sent, no mailer configured:\n{}", mail.to_text()),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod mail_test {
+    use super::{Mail, Mailer};
+
+    #[tokio::test]
+    async fn file_mailer_appends() {
+        let path = std::env::temp_dir()
+            .join(format!("plasma-mail-{}", rand::random::<u64>()));
+        let mailer = Mailer::File(path.clone());
+        for subject in ["first", "second"] {
+            let mail = Mail {
+                to: String::from("user@example.com"),
+                subject: String::from(subject),
+                body: String::from("token"),
+            };
+            mailer.send(&mail).await.unwrap();
+        }
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert!(written.starts_with("To: user@example.com\nSubject: first\n\ntoken\n"));
+        assert!(written.contains("Subject: second"));
+        std::fs::remove_file(path).unwrap();
+    }
+}
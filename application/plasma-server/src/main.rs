@@ -7,12 +7,19 @@ mod rest;
 mod ws;
 mod security;
 mod error;
+mod validation;
+mod mail;
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 use tokio::sync::RwLock;
 use warp::Filter;
 
 type ClientsHandle = Arc<RwLock<ws::clients::Clients>>;
+type LimitsHandle = Arc<security::rate_limit::Limits>;
+type MailerHandle = Arc<mail::Mailer>;
+
+const LIMITS_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+const EXPIRED_CLEANUP_INTERVAL: Duration = Duration::from_secs(30);
 
 #[tokio::main]
 async fn main() {
@@ -23,16 +30,47 @@ async fn main() {
     info!("Successfully connected to db.");
 
     let clients = Arc::new(RwLock::new(ws::clients::Clients::new()));
+    let limits = Arc::new(security::rate_limit::Limits::from_env());
+    let mailer = Arc::new(mail::Mailer::from_env()
+        .expect("Invalid mailer configuration"));
+    spawn_limits_pruning(limits.clone());
+    spawn_expired_cleanup(db.clone());
 
     let cors = warp::cors()
         .allow_any_origin();
 
     let log = warp::log("server::plasma");
 
-    let routes = server::routes(db.clone(), clients.clone())
+    let routes = server::routes(db.clone(), clients.clone(), limits.clone(), mailer)
         .with(cors)
         .with(log);
 
     warp::serve(routes)
         .run(([0, 0, 0, 0], 8000)).await;
 }
+
+fn spawn_limits_pruning(limits: LimitsHandle) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(LIMITS_PRUNE_INTERVAL);
+        loop {
+            interval.tick().await;
+            limits.prune();
+        }
+    });
+}
+
+/// Disappearing messages are filtered out of history once expired, this
+/// removes them from the database for good.
+fn spawn_expired_cleanup(db: Arc<model::Db>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(EXPIRED_CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            match model::message::Message::delete_expired(&db).await {
+                Ok(0) => {},
+                Ok(count) => debug!("Deleted {} expired messages", count),
+                Err(e) => error!("Failed to delete expired messages: {}", e),
+            }
+        }
+    });
+}
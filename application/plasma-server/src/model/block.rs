@@ -0,0 +1,140 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use bson::doc;
+use bson::oid::ObjectId;
+use mongodb::{IndexModel, options::{IndexOptions, ReplaceOptions}};
+use serde::{Serialize, Deserialize};
+use crate::model::{Db, db, Error};
+use crate::error;
+use super::{from_document, BsonError, DATABASE};
+
+const COLLECTION: &str = "block";
+
+/// `blocked_id` may no longer start a chat with `blocker_id`, message it or
+/// fetch its key bundles, and neither may the blocker while the block lasts.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Block {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    id: Option<ObjectId>,
+    blocker_id: ObjectId,
+    blocked_id: ObjectId,
+    created_at: u64,
+}
+
+impl Block {
+    pub fn new(blocker_id: ObjectId, blocked_id: ObjectId) -> Self {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs();
+        Block {
+            id: None,
+            blocker_id,
+            blocked_id,
+            created_at,
+        }
+    }
+
+    pub fn blocked_id(&self) -> &ObjectId {
+        &self.blocked_id
+    }
+
+    /// Stores the block, blocking the same user again keeps a single record.
+    pub async fn add_to_db(db: &Db, block: &Block) -> Result<(), Error> {
+        let bs = bson::to_bson(block)
+            .map_err(BsonError::from)?;
+        let document = bs.as_document()
+            .ok_or(Error::BsonConvError(error::BsonError::ConversionError))?;
+
+        let query = doc!{
+            "blocker_id": block.blocker_id,
+            "blocked_id": block.blocked_id,
+        };
+        let options = ReplaceOptions::builder()
+            .upsert(true)
+            .build();
+        db.database(DATABASE)
+            .collection::<mongodb::bson::Document>(COLLECTION)
+            .replace_one(query, document.to_owned(), options).await
+            .map_err(|_| Error::DbError("insert block", format!("{:?}", block)))?;
+
+        Ok(())
+    }
+
+    pub async fn remove(db: &Db, blocker_id: &ObjectId, blocked_id: &ObjectId) -> Result<(), Error> {
+        let query = doc!{
+            "blocker_id": blocker_id,
+            "blocked_id": blocked_id,
+        };
+        let result = db.database(DATABASE)
+            .collection::<Block>(COLLECTION)
+            .delete_one(query, None).await
+            .map_err(|_| Error::DbError("delete block", format!("{}", blocked_id)))?;
+        if result.deleted_count == 0 {
+            return Err(Error::NotFound("block"));
+        }
+        Ok(())
+    }
+
+    pub async fn get_by_blocker(db: &Db, blocker_id: &ObjectId) -> Result<Vec<Block>, Error> {
+        let filter = doc!{
+            "blocker_id": blocker_id
+        };
+
+        let documents = db::get_all_in_vec(db, filter, None, COLLECTION).await?;
+        let mut blocks = Vec::with_capacity(documents.len());
+        for doc in documents {
+            blocks.push(from_document(doc)?);
+        }
+
+        Ok(blocks)
+    }
+
+    /// True when either user blocked any of `others`.
+    pub async fn between(db: &Db, user_id: &ObjectId, others: &[ObjectId]) -> Result<bool, Error> {
+        let others: Vec<&ObjectId> = others.iter()
+            .filter(|other| *other != user_id)
+            .collect();
+        if others.is_empty() {
+            return Ok(false);
+        }
+        let filter = doc!{
+            "$or": [
+                {"blocker_id": user_id, "blocked_id": {"$in": &others}},
+                {"blocked_id": user_id, "blocker_id": {"$in": &others}},
+            ]
+        };
+        let found = db::get_by(db, &filter, &String::from(COLLECTION)).await?;
+        Ok(found.is_some())
+    }
+
+    /// Drops blocks made by or against the user.
+    pub async fn remove_all(db: &Db, user_id: &ObjectId) -> Result<(), Error> {
+        let query = doc!{
+            "$or": [{"blocker_id": user_id}, {"blocked_id": user_id}],
+        };
+        db.database(DATABASE)
+            .collection::<Block>(COLLECTION)
+            .delete_many(query, None).await
+            .map_err(|_| Error::DbError("delete blocks of", format!("{}", user_id)))?;
+        Ok(())
+    }
+
+    pub async fn create_indexes(db: &Db) -> Result<(), Error> {
+        let indexes = vec![
+            IndexModel::builder()
+                .keys(doc!{"blocker_id": 1, "blocked_id": 1})
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+            IndexModel::builder()
+                .keys(doc!{"blocked_id": 1})
+                .build(),
+        ];
+
+        db.database(DATABASE)
+            .collection::<mongodb::bson::Document>(COLLECTION)
+            .create_indexes(indexes, None).await
+            .map_err(|_| Error::DbError("create indexes on", String::from(COLLECTION)))?;
+
+        Ok(())
+    }
+}
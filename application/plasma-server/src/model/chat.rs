@@ -36,6 +36,10 @@ impl Chat {
         &self.users
     }
 
+    pub fn is_member(&self, user_id: &ObjectId) -> bool {
+        self.users.contains(user_id)
+    }
+
     pub async fn add_to_db(db: &Db, chat: &Chat) -> Result<ObjectId, Error> {
         let bs = bson::to_bson(&chat)
             .map_err(|err| BsonError::from(err))?;
@@ -60,7 +64,7 @@ impl Chat {
 
         let document = db::get_by(db, &filter, &String::from("chat"))
             .await?
-            .ok_or(Error::DbError("find", id.to_string()))?;
+            .ok_or(Error::NotFound("chat"))?;
 
         let chat = from_document(document)?;
         
@@ -88,16 +92,53 @@ impl Chat {
 
         let document = db::get_by(db, &filter, &String::from("chat"))
             .await?
-            .ok_or(Error::DbError("find", id1.to_string()))?;
+            .ok_or(Error::NotFound("chat"))?;
 
         let chat = from_document(document)?;
         
         Ok(chat)
     }
 
+    /// Takes the user out of every chat, the other members keep the history.
+    /// Chats nobody is left in are dropped.
+    pub async fn leave_all(db: &Db, user_id: &ObjectId) -> Result<(), Error> {
+        let query = doc!{
+            "users": user_id,
+        };
+        let update = doc!{
+            "$pull": {
+                "users": user_id
+            },
+        };
+        db.database(DATABASE)
+            .collection::<Chat>(COLLECTION)
+            .update_many(query, update, None).await
+            .map_err(|_| Error::DbError("update chat, leave", format!("{}", user_id)))?;
+        db.database(DATABASE)
+            .collection::<Chat>(COLLECTION)
+            .delete_many(doc!{"users": {"$size": 0}}, None).await
+            .map_err(|_| Error::DbError("delete empty", String::from(COLLECTION)))?;
+        Ok(())
+    }
+
+    pub async fn delete_many(db: &Db, ids: &[ObjectId]) -> Result<(), Error> {
+        let query = doc!{
+            "_id": {"$in": ids},
+        };
+        db.database(DATABASE)
+            .collection::<Chat>(COLLECTION)
+            .delete_many(query, None).await
+            .map_err(|_| Error::DbError("delete", String::from(COLLECTION)))?;
+        Ok(())
+    }
+
     pub async fn get_users_chats(db: Arc<Db>, id: &str) -> Result<Vec<Chat>, Error> {
         let id = objectid_from_str(id)
             .map_err(|_| Error::InvalidOID)?;
+        Chat::get_users_chats_by_oid(&db, &id).await
+    }
+
+    pub async fn get_users_chats_by_oid(db: &Db, id: &ObjectId) -> Result<Vec<Chat>, Error> {
         let filter = doc!{
             "users": {
                 "$elemMatch": {
@@ -106,7 +147,7 @@ impl Chat {
             }
         };
         
-        let documents = db::get_all_in_vec(&db, filter, None, COLLECTION).await?;
+        let documents = db::get_all_in_vec(db, filter, None, COLLECTION).await?;
         let mut chats: Vec<Chat> = vec![];
         for doc in documents {
             let list = from_document(doc.clone())?;
@@ -0,0 +1,119 @@
+use bson::doc;
+use bson::oid::ObjectId;
+use mongodb::{IndexModel, options::{IndexOptions, ReplaceOptions}};
+use serde::{Serialize, Deserialize};
+use crate::model::{Db, db, Error};
+use crate::error;
+use super::{from_document, BsonError, DATABASE};
+
+const COLLECTION: &str = "contact";
+
+/// User kept in the contact list of `owner_id`, optionally under a nickname
+/// only the owner sees.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Contact {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    id: Option<ObjectId>,
+    owner_id: ObjectId,
+    contact_id: ObjectId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nickname: Option<String>,
+}
+
+impl Contact {
+    pub fn new(owner_id: ObjectId, contact_id: ObjectId, nickname: Option<String>) -> Self {
+        Contact {
+            id: None,
+            owner_id,
+            contact_id,
+            nickname,
+        }
+    }
+
+    pub fn contact_id(&self) -> &ObjectId {
+        &self.contact_id
+    }
+
+    pub fn nickname(&self) -> Option<&String> {
+        self.nickname.as_ref()
+    }
+
+    /// Adds the contact, replacing nickname of one already in the list.
+    pub async fn add_to_db(db: &Db, contact: &Contact) -> Result<(), Error> {
+        let bs = bson::to_bson(contact)
+            .map_err(BsonError::from)?;
+        let document = bs.as_document()
+            .ok_or(Error::BsonConvError(error::BsonError::ConversionError))?;
+
+        let query = doc!{
+            "owner_id": contact.owner_id,
+            "contact_id": contact.contact_id,
+        };
+        let options = ReplaceOptions::builder()
+            .upsert(true)
+            .build();
+        db.database(DATABASE)
+            .collection::<mongodb::bson::Document>(COLLECTION)
+            .replace_one(query, document.to_owned(), options).await
+            .map_err(|_| Error::DbError("insert contact", format!("{:?}", contact)))?;
+
+        Ok(())
+    }
+
+    pub async fn get_by_owner(db: &Db, owner_id: &ObjectId) -> Result<Vec<Contact>, Error> {
+        let filter = doc!{
+            "owner_id": owner_id
+        };
+
+        let documents = db::get_all_in_vec(db, filter, None, COLLECTION).await?;
+        let mut contacts = Vec::with_capacity(documents.len());
+        for doc in documents {
+            contacts.push(from_document(doc)?);
+        }
+
+        Ok(contacts)
+    }
+
+    pub async fn remove(db: &Db, owner_id: &ObjectId, contact_id: &ObjectId) -> Result<(), Error> {
+        let query = doc!{
+            "owner_id": owner_id,
+            "contact_id": contact_id,
+        };
+        let result = db.database(DATABASE)
+            .collection::<Contact>(COLLECTION)
+            .delete_one(query, None).await
+            .map_err(|_| Error::DbError("delete contact", format!("{}", contact_id)))?;
+        if result.deleted_count == 0 {
+            return Err(Error::NotFound("contact"));
+        }
+        Ok(())
+    }
+
+    /// Drops the user's contact list and every entry listing the user.
+    pub async fn remove_all(db: &Db, user_id: &ObjectId) -> Result<(), Error> {
+        let query = doc!{
+            "$or": [{"owner_id": user_id}, {"contact_id": user_id}],
+        };
+        db.database(DATABASE)
+            .collection::<Contact>(COLLECTION)
+            .delete_many(query, None).await
+            .map_err(|_| Error::DbError("delete contacts of", format!("{}", user_id)))?;
+        Ok(())
+    }
+
+    pub async fn create_indexes(db: &Db) -> Result<(), Error> {
+        let indexes = vec![
+            IndexModel::builder()
+                .keys(doc!{"owner_id": 1, "contact_id": 1})
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        ];
+
+        db.database(DATABASE)
+            .collection::<mongodb::bson::Document>(COLLECTION)
+            .create_indexes(indexes, None).await
+            .map_err(|_| Error::DbError("create indexes on", String::from(COLLECTION)))?;
+
+        Ok(())
+    }
+}
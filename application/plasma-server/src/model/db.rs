@@ -1,13 +1,14 @@
-use mongodb::{Client, options::{ClientOptions, FindOptions}};
+use mongodb::{Client, options::{ClientOptions, FindOptions}, error::{ErrorKind, WriteFailure}};
 use dotenv;
 use bson::{doc, Document};
-use super::{DATABASE, Error};
+use super::{DATABASE, Error, user::User, message::Message, device::Device, contact::Contact, block::Block, report::Report, mail_token::MailToken, keys::{RegisterBundle, InitialMessage}};
 use futures::TryStreamExt;
 
 const MONGO_USER: &str = "MONGO_USER";
 const MONGO_PW: &str = "MONGO_PW";
 const MONGO_HOST: &str = "MONGO_HOST";
 const MONGO_PORT: &str = "MONGO_PORT";
+const DUPLICATE_KEY_CODE: i32 = 11000;
 
 pub type Db = Client;
 
@@ -16,10 +17,65 @@ pub async fn init_db() -> Db {
     let options = ClientOptions::parse(&client_uri).await.unwrap();
     let client = Client::with_options(options).unwrap();
     check_db_conn(&client).await.unwrap();
+    migrate(&client).await.unwrap();
+    if let Err(err) = create_indexes(&client).await {
+        if report_duplicates(&client).await {
+            panic!("Failed creating indexes: users differing only in case");
+        }
+        panic!("Failed creating indexes: {}", err);
+    }
 
     client
 }
 
+/// Removes key material stored before devices existed, it has to be gone
+/// before the per device unique indexes can be built.
+async fn migrate(db: &Db) -> Result<(), Error> {
+    let bundles = RegisterBundle::remove_legacy(db).await?;
+    if bundles > 0 {
+        info!("Deleted {} register bundles without a device", bundles);
+    }
+    let messages = InitialMessage::remove_legacy(db).await?;
+    if messages > 0 {
+        info!("Deleted {} initial messages without a device pair", messages);
+    }
+    Ok(())
+}
+
+/// Users differing only in case keep the unique indexes from being built,
+/// lists them so they can be renamed or removed before starting again.
+/// Returns whether any were found.
+async fn report_duplicates(db: &Db) -> bool {
+    match User::find_case_duplicates(db).await {
+        Ok(groups) => {
+            if !groups.is_empty() {
+                error!("Emails or usernames differing only in case, rename or remove all but one user of each group:");
+            }
+            for users in &groups {
+                error!("  {}", users.join(", "));
+            }
+            !groups.is_empty()
+        },
+        Err(err) => {
+            error!("Failed looking for duplicated users: {}", err);
+            false
+        },
+    }
+}
+
+async fn create_indexes(db: &Db) -> Result<(), Error> {
+    User::create_indexes(db).await?;
+    Message::create_indexes(db).await?;
+    Device::create_indexes(db).await?;
+    Contact::create_indexes(db).await?;
+    Block::create_indexes(db).await?;
+    Report::create_indexes(db).await?;
+    MailToken::create_indexes(db).await?;
+    RegisterBundle::create_indexes(db).await?;
+    InitialMessage::create_indexes(db).await?;
+    Ok(())
+}
+
 fn make_client_uri() -> Result<String, Box<dyn std::error::Error>> {
     dotenv::dotenv()?;
 
@@ -67,3 +123,9 @@ pub async fn get_all_in_vec(db: &Db, filter: Document, options: impl Into<Option
     Ok(results)
 }
 
+pub fn is_duplicate_key(err: &mongodb::error::Error) -> bool {
+    match err.kind.as_ref() {
+        ErrorKind::Write(WriteFailure::WriteError(e)) => e.code == DUPLICATE_KEY_CODE,
+        _ => false,
+    }
+}
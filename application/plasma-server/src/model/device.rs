@@ -0,0 +1,150 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use bson::doc;
+use bson::oid::ObjectId;
+use mongodb::IndexModel;
+use serde::{Serialize, Deserialize};
+use crate::model::{Db, db, Error};
+use crate::error;
+use super::{from_document, BsonError, DATABASE};
+
+const COLLECTION: &str = "device";
+
+/// Single client installation of an account, each with its own identity key.
+/// Devices other than the first one stay unapproved until an approved device links them.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Device {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    id: Option<ObjectId>,
+    user_id: ObjectId,
+    name: String,
+    approved: bool,
+    created_at: u64,
+}
+
+impl Device {
+    pub fn new(user_id: ObjectId, name: &str, approved: bool) -> Self {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs();
+        Device {
+            id: None,
+            user_id,
+            name: name.to_owned(),
+            approved,
+            created_at,
+        }
+    }
+
+    pub fn id(&self) -> Option<&ObjectId> {
+        self.id.as_ref()
+    }
+
+    pub fn user_id(&self) -> &ObjectId {
+        &self.user_id
+    }
+
+    pub fn is_approved(&self) -> bool {
+        self.approved
+    }
+
+    pub async fn add_to_db(db: &Db, device: &mut Device) -> Result<ObjectId, Error> {
+        let bs = bson::to_bson(&device)
+            .map_err(BsonError::from)?;
+        let document = bs.as_document()
+            .ok_or(Error::BsonConvError(error::BsonError::ConversionError))?;
+
+        let result = db.database(DATABASE)
+            .collection::<mongodb::bson::Document>(COLLECTION)
+            .insert_one(document.to_owned(), None).await
+            .map_err(|_| Error::DbError("insert", format!("{:?}", device)))?;
+        let id = result.inserted_id.as_object_id()
+            .ok_or(Error::BsonConvError(error::BsonError::ConversionError))?;
+        device.id = Some(id);
+
+        Ok(id)
+    }
+
+    pub async fn get_by_id(db: &Db, id: &ObjectId) -> Result<Device, Error> {
+        let filter = doc!{
+            "_id": id
+        };
+
+        let document = db::get_by(db, &filter, &String::from(COLLECTION))
+            .await?
+            .ok_or(Error::NotFound("device"))?;
+
+        let device = from_document(document)?;
+
+        Ok(device)
+    }
+
+    /// Devices of a user, only the linked ones when `approved_only` is set.
+    pub async fn get_by_user(db: &Db, user_id: &ObjectId, approved_only: bool) -> Result<Vec<Device>, Error> {
+        let filter = match approved_only {
+            true => doc!{"user_id": user_id, "approved": true},
+            false => doc!{"user_id": user_id},
+        };
+
+        let documents = db::get_all_in_vec(db, filter, None, COLLECTION).await?;
+        let mut devices = Vec::with_capacity(documents.len());
+        for doc in documents {
+            devices.push(from_document(doc)?);
+        }
+
+        Ok(devices)
+    }
+
+    pub async fn approve(db: &Db, id: &ObjectId) -> Result<(), Error> {
+        let query = doc!{
+            "_id": id,
+        };
+        let update = doc!{
+            "$set": {
+                "approved": true
+            },
+        };
+        db.database(DATABASE)
+            .collection::<Device>(COLLECTION)
+            .update_one(query, update, None).await
+            .map_err(|_| Error::DbError("update device, approve", format!("{}", id)))?;
+        Ok(())
+    }
+
+    pub async fn remove(db: &Db, id: &ObjectId) -> Result<(), Error> {
+        let query = doc!{
+            "_id": id,
+        };
+        db.database(DATABASE)
+            .collection::<Device>(COLLECTION)
+            .delete_one(query, None).await
+            .map_err(|_| Error::DbError("delete device", format!("{}", id)))?;
+        Ok(())
+    }
+
+    pub async fn remove_by_user(db: &Db, user_id: &ObjectId) -> Result<(), Error> {
+        let query = doc!{
+            "user_id": user_id,
+        };
+        db.database(DATABASE)
+            .collection::<Device>(COLLECTION)
+            .delete_many(query, None).await
+            .map_err(|_| Error::DbError("delete devices of", format!("{}", user_id)))?;
+        Ok(())
+    }
+
+    pub async fn create_indexes(db: &Db) -> Result<(), Error> {
+        let indexes = vec![
+            IndexModel::builder()
+                .keys(doc!{"user_id": 1})
+                .build(),
+        ];
+
+        db.database(DATABASE)
+            .collection::<mongodb::bson::Document>(COLLECTION)
+            .create_indexes(indexes, None).await
+            .map_err(|_| Error::DbError("create indexes on", String::from(COLLECTION)))?;
+
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 use bson::{oid::ObjectId, doc};
+use mongodb::{IndexModel, options::{IndexOptions, ReplaceOptions}};
 use serde::{Serialize, Deserialize};
 use x3dh::handshake;
 use crate::error::BsonError;
@@ -13,6 +14,7 @@ pub struct RegisterBundle {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
     pub user_id: ObjectId,
+    pub device_id: ObjectId,
     pub bundle: handshake::RegisterBundleBinary,
 }
 
@@ -20,22 +22,27 @@ pub struct RegisterBundle {
 pub struct InitialMessage {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
-    pub chat_id: ObjectId,
+    pub from_device: ObjectId,
+    pub to_device: ObjectId,
+    /// Both devices in ascending order, a pair of devices shares exactly one secret.
+    pub pair: String,
     pub message: handshake::InitialMessageBinary,
 }
 
 impl RegisterBundle {
-    pub fn new(user_id: &str, bundle: handshake::RegisterBundleBinary) -> Result<RegisterBundle, Error> {
+    pub fn new(user_id: &str, device_id: ObjectId, bundle: handshake::RegisterBundleBinary) -> Result<RegisterBundle, Error> {
         let user_id = objectid_from_str(user_id)
             .map_err(|_| Error::InvalidOID)?;
         let rb = RegisterBundle {
             id: None,
             user_id,
+            device_id,
             bundle,
         };
         Ok(rb)
     }
 
+    /// Stores bundle of a device, replacing the one it uploaded before.
     pub async fn add_to_db(db: &Db, bundle: &RegisterBundle) -> Result<(), Error> {
         let bs = bson::to_bson(bundle)
             .map_err(|err| BsonError::from(err))?;
@@ -46,20 +53,26 @@ impl RegisterBundle {
             .database(DATABASE)
             .collection::<mongodb::bson::Document>(BUNDLE_COLLECTION);
 
-        bundledb.insert_one(document.to_owned(), None).await
-            .map_err(|_| Error::DbError("insert bundle", format!("{:?}", bundle.user_id)))?;
+        let query = doc!{
+            "device_id": bundle.device_id
+        };
+        let options = ReplaceOptions::builder()
+            .upsert(true)
+            .build();
+        bundledb.replace_one(query, document.to_owned(), options).await
+            .map_err(|_| Error::DbError("insert bundle", format!("{:?}", bundle.device_id)))?;
 
         Ok(())
     }
 
-    pub async fn get_by_user(db: &Db, user_id: &ObjectId) -> Result<RegisterBundle, Error> {
+    pub async fn get_by_device(db: &Db, device_id: &ObjectId) -> Result<RegisterBundle, Error> {
         let filter = doc!{
-            "user_id": user_id
+            "device_id": device_id
         };
 
         let document = db::get_by(db, &filter, &String::from(BUNDLE_COLLECTION))
             .await?
-            .ok_or(Error::DbError("get bundle", user_id.to_string()))?;
+            .ok_or(Error::NotFound("bundle"))?;
 
         let bundle = from_document(document)?;
         
@@ -82,13 +95,79 @@ impl RegisterBundle {
             .map_err(|_| Error::DbError("update bundle, pop onetime", format!("{}", bundle_id)))?;
         Ok(())
     }
+
+    pub async fn remove_by_device(db: &Db, device_id: &ObjectId) -> Result<(), Error> {
+        let query = doc!{
+            "device_id": device_id,
+        };
+        db.database(DATABASE)
+            .collection::<RegisterBundle>(BUNDLE_COLLECTION)
+            .delete_many(query, None).await
+            .map_err(|_| Error::DbError("delete bundle", format!("{}", device_id)))?;
+        Ok(())
+    }
+
+    pub async fn get_by_user(db: &Db, user_id: &ObjectId) -> Result<Vec<RegisterBundle>, Error> {
+        let filter = doc!{
+            "user_id": user_id
+        };
+
+        let documents = db::get_all_in_vec(db, filter, None, BUNDLE_COLLECTION).await?;
+        let mut bundles = Vec::with_capacity(documents.len());
+        for doc in documents {
+            bundles.push(from_document(doc)?);
+        }
+
+        Ok(bundles)
+    }
+
+    pub async fn remove_by_user(db: &Db, user_id: &ObjectId) -> Result<(), Error> {
+        let query = doc!{
+            "user_id": user_id,
+        };
+        db.database(DATABASE)
+            .collection::<RegisterBundle>(BUNDLE_COLLECTION)
+            .delete_many(query, None).await
+            .map_err(|_| Error::DbError("delete bundles of", format!("{}", user_id)))?;
+        Ok(())
+    }
+
+    /// Bundles uploaded before devices existed can't be addressed, clients upload
+    /// new ones once they register their device. Returns how many were deleted.
+    pub async fn remove_legacy(db: &Db) -> Result<u64, Error> {
+        let result = db.database(DATABASE)
+            .collection::<mongodb::bson::Document>(BUNDLE_COLLECTION)
+            .delete_many(doc!{"device_id": {"$exists": false}}, None).await
+            .map_err(|_| Error::DbError("delete legacy", String::from(BUNDLE_COLLECTION)))?;
+        Ok(result.deleted_count)
+    }
+
+    pub async fn create_indexes(db: &Db) -> Result<(), Error> {
+        let bundledb = db
+            .database(DATABASE)
+            .collection::<mongodb::bson::Document>(BUNDLE_COLLECTION);
+
+        let index = IndexModel::builder()
+            .keys(doc!{"device_id": 1})
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        bundledb.create_index(index, None).await
+            .map_err(|_| Error::DbError("create indexes on", String::from(BUNDLE_COLLECTION)))?;
+
+        Ok(())
+    }
 }
 
 impl InitialMessage {
-    pub fn new(chat_id: ObjectId, message: handshake::InitialMessageBinary) -> InitialMessage {
+    pub fn new(from_device: ObjectId, to_device: ObjectId, message: handshake::InitialMessageBinary) -> InitialMessage {
+        let mut devices = [from_device.to_hex(), to_device.to_hex()];
+        devices.sort();
+        let pair = devices.join(":");
         InitialMessage {
             id: None,
-            chat_id,
+            from_device,
+            to_device,
+            pair,
             message,
         }
     }
@@ -104,14 +183,19 @@ impl InitialMessage {
             .collection::<mongodb::bson::Document>(INITIAL_MESSAGE_COLLECTION);
 
         messagedb.insert_one(document.to_owned(), None).await
-            .map_err(|_| Error::DbError("insert message", format!("{:?}", message.chat_id)))?;
+            .map_err(|err| match db::is_duplicate_key(&err) {
+                true => Error::NotUnique("session between devices"),
+                false => Error::DbError("insert message", format!("{:?}", message.pair)),
+            })?;
 
         Ok(())
     }
 
-    pub async fn get_by_chat(db: &Db, chat_id: &ObjectId) -> Result<Option<InitialMessage>, Error> {
+    /// Initial message sent to `to_device` by `from_device`, if that side started the session.
+    pub async fn get_for(db: &Db, to_device: &ObjectId, from_device: &ObjectId) -> Result<Option<InitialMessage>, Error> {
         let filter = doc!{
-            "chat_id": chat_id
+            "to_device": to_device,
+            "from_device": from_device,
         };
 
         let document = db::get_by(db, &filter, &String::from(INITIAL_MESSAGE_COLLECTION))
@@ -122,4 +206,57 @@ impl InitialMessage {
             None => Ok(None),
         }
     }
+
+    /// Initial messages sent from or to any of `devices`.
+    pub async fn get_by_devices(db: &Db, devices: &[ObjectId]) -> Result<Vec<InitialMessage>, Error> {
+        let documents = db::get_all_in_vec(db, by_devices(devices), None, INITIAL_MESSAGE_COLLECTION).await?;
+        let mut messages = Vec::with_capacity(documents.len());
+        for doc in documents {
+            messages.push(from_document(doc)?);
+        }
+
+        Ok(messages)
+    }
+
+    pub async fn remove_by_devices(db: &Db, devices: &[ObjectId]) -> Result<(), Error> {
+        db.database(DATABASE)
+            .collection::<InitialMessage>(INITIAL_MESSAGE_COLLECTION)
+            .delete_many(by_devices(devices), None).await
+            .map_err(|_| Error::DbError("delete", String::from(INITIAL_MESSAGE_COLLECTION)))?;
+        Ok(())
+    }
+
+    /// Messages from before devices existed are keyed by chat and can't be used anymore.
+    /// Returns how many were deleted.
+    pub async fn remove_legacy(db: &Db) -> Result<u64, Error> {
+        let result = db.database(DATABASE)
+            .collection::<mongodb::bson::Document>(INITIAL_MESSAGE_COLLECTION)
+            .delete_many(doc!{"pair": {"$exists": false}}, None).await
+            .map_err(|_| Error::DbError("delete legacy", String::from(INITIAL_MESSAGE_COLLECTION)))?;
+        Ok(result.deleted_count)
+    }
+
+    pub async fn create_indexes(db: &Db) -> Result<(), Error> {
+        let messagedb = db
+            .database(DATABASE)
+            .collection::<mongodb::bson::Document>(INITIAL_MESSAGE_COLLECTION);
+
+        let index = IndexModel::builder()
+            .keys(doc!{"pair": 1})
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        messagedb.create_index(index, None).await
+            .map_err(|_| Error::DbError("create indexes on", String::from(INITIAL_MESSAGE_COLLECTION)))?;
+
+        Ok(())
+    }
+}
+
+fn by_devices(devices: &[ObjectId]) -> bson::Document {
+    doc!{
+        "$or": [
+            {"from_device": {"$in": devices}},
+            {"to_device": {"$in": devices}},
+        ]
+    }
 }
@@ -0,0 +1,170 @@
+use std::time::{Duration, SystemTime};
+use bson::{doc, Document};
+use bson::oid::ObjectId;
+use mongodb::{IndexModel, options::IndexOptions};
+use serde::{Serialize, Deserialize};
+use crate::model::{Db, Error};
+use crate::error;
+use crate::security::hash::hashed_token;
+use super::{BsonError, DATABASE};
+
+const COLLECTION: &str = "mail_token";
+
+/// What a mailed token lets its holder do.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Purpose {
+    PasswordReset,
+    Verification,
+}
+
+impl Purpose {
+    pub fn lifetime(&self) -> Duration {
+        match self {
+            Purpose::PasswordReset => Duration::from_secs(30 * 60),
+            Purpose::Verification => Duration::from_secs(2 * 24 * 60 * 60),
+        }
+    }
+}
+
+/// Token mailed to a user, only its hash is stored. Mongo drops records once
+/// `expires_at` passes.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MailToken {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    id: Option<ObjectId>,
+    user_id: ObjectId,
+    purpose: Purpose,
+    token_hash: String,
+    expires_at: bson::DateTime,
+}
+
+impl MailToken {
+    /// New token of `user_id` together with its plain form to mail.
+    pub fn new(user_id: ObjectId, purpose: Purpose) -> (Self, String) {
+        let token = new_token();
+        let mail_token = MailToken {
+            id: None,
+            user_id,
+            purpose,
+            token_hash: hashed_token(&token),
+            expires_at: bson::DateTime::from(SystemTime::now() + purpose.lifetime()),
+        };
+        (mail_token, token)
+    }
+
+    pub fn user_id(&self) -> &ObjectId {
+        &self.user_id
+    }
+
+    pub async fn add_to_db(db: &Db, mail_token: &MailToken) -> Result<(), Error> {
+        let bs = bson::to_bson(mail_token)
+            .map_err(BsonError::from)?;
+        let document = bs.as_document()
+            .ok_or(Error::BsonConvError(error::BsonError::ConversionError))?;
+
+        db.database(DATABASE)
+            .collection::<mongodb::bson::Document>(COLLECTION)
+            .insert_one(document.to_owned(), None).await
+            .map_err(|_| Error::DbError("insert token of", format!("{}", mail_token.user_id)))?;
+
+        Ok(())
+    }
+
+    /// Token matching `token`, left in place for when the request may still be rejected.
+    pub async fn get(db: &Db, token: &str, purpose: Purpose) -> Result<MailToken, Error> {
+        db.database(DATABASE)
+            .collection::<MailToken>(COLLECTION)
+            .find_one(valid_filter(token, purpose)?, None).await
+            .map_err(|_| Error::DbError("find", String::from(COLLECTION)))?
+            .ok_or(Error::NotFound("token"))
+    }
+
+    /// Removes the token matching `token`, a token works only once and only until it expires.
+    pub async fn take(db: &Db, token: &str, purpose: Purpose) -> Result<MailToken, Error> {
+        db.database(DATABASE)
+            .collection::<MailToken>(COLLECTION)
+            .find_one_and_delete(valid_filter(token, purpose)?, None).await
+            .map_err(|_| Error::DbError("delete", String::from(COLLECTION)))?
+            .ok_or(Error::NotFound("token"))
+    }
+
+    pub async fn remove_by_user(db: &Db, user_id: &ObjectId, purpose: Purpose) -> Result<(), Error> {
+        let query = doc!{
+            "user_id": user_id,
+            "purpose": bson::to_bson(&purpose).map_err(BsonError::from)?,
+        };
+        db.database(DATABASE)
+            .collection::<MailToken>(COLLECTION)
+            .delete_many(query, None).await
+            .map_err(|_| Error::DbError("delete tokens of", format!("{}", user_id)))?;
+        Ok(())
+    }
+
+    /// Drops every token of the user, whatever it was for.
+    pub async fn remove_all(db: &Db, user_id: &ObjectId) -> Result<(), Error> {
+        let query = doc!{
+            "user_id": user_id,
+        };
+        db.database(DATABASE)
+            .collection::<MailToken>(COLLECTION)
+            .delete_many(query, None).await
+            .map_err(|_| Error::DbError("delete tokens of", format!("{}", user_id)))?;
+        Ok(())
+    }
+
+    pub async fn create_indexes(db: &Db) -> Result<(), Error> {
+        let indexes = vec![
+            IndexModel::builder()
+                .keys(doc!{"token_hash": 1})
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+            IndexModel::builder()
+                .keys(doc!{"expires_at": 1})
+                .options(IndexOptions::builder().expire_after(Duration::ZERO).build())
+                .build(),
+        ];
+
+        db.database(DATABASE)
+            .collection::<mongodb::bson::Document>(COLLECTION)
+            .create_indexes(indexes, None).await
+            .map_err(|_| Error::DbError("create indexes on", String::from(COLLECTION)))?;
+
+        Ok(())
+    }
+}
+
+fn valid_filter(token: &str, purpose: Purpose) -> Result<Document, Error> {
+    let filter = doc!{
+        "token_hash": hashed_token(token),
+        "purpose": bson::to_bson(&purpose).map_err(BsonError::from)?,
+        "expires_at": {"$gt": bson::DateTime::now()},
+    };
+    Ok(filter)
+}
+
+/// Random token as hex, long enough that guessing one is hopeless.
+fn new_token() -> String {
+    rand::random::<[u8; 32]>()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod mail_token_test {
+    use bson::oid::ObjectId;
+    use crate::security::hash::hashed_token;
+    use super::{MailToken, Purpose};
+
+    #[test]
+    fn stores_only_hash() {
+        let (mail_token, token) = MailToken::new(ObjectId::new(), Purpose::Verification);
+        let document = bson::to_document(&mail_token).unwrap();
+
+        assert_eq!(token.len(), 64);
+        assert_eq!(document.get_str("token_hash").unwrap(), hashed_token(&token));
+        assert_eq!(document.get_str("purpose").unwrap(), "verification");
+        assert!(!document.values().any(|value| value.as_str() == Some(token.as_str())));
+    }
+}
@@ -1,66 +1,387 @@
-use bson::doc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use bson::{doc, Document};
 use bson::oid::ObjectId;
-use mongodb::options::FindOptions;
+use mongodb::{options::{FindOptions, IndexOptions}, IndexModel};
 use futures::TryStreamExt;
 use serde::{Serialize, Deserialize};
-use crate::model::{Db, Error};
+use crate::model::{Db, db, Error};
 use crate::error;
-use super::{BsonError, DATABASE};
+use super::{from_document, BsonError, DATABASE};
 
 const COLLECTION: &'static str  = "message";
+pub const DEFAULT_PAGE_SIZE: i64 = 50;
+pub const MAX_PAGE_SIZE: i64 = 200;
+/// Longest disappearing messages timer, in microseconds like message timestamps.
+const MAX_TIMER: u64 = 4 * 7 * 24 * 60 * 60 * 1_000_000;
 
+/// Timestamps are set by clients and can repeat, paging through them takes a
+/// `Position`, the timestamp and id of the last message of the previous page.
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Cursor {
+    Id(ObjectId),
+    Timestamp(u64),
+    Position { timestamp: u64, id: ObjectId },
+}
+
+impl Cursor {
+    fn field(&self) -> &'static str {
+        match self {
+            Cursor::Id(_) => "_id",
+            Cursor::Timestamp(_) | Cursor::Position { .. } => "timestamp",
+        }
+    }
+
+    fn bound(&self, op: &str) -> Document {
+        match self {
+            Cursor::Id(id) => doc!{"_id": {op: id}},
+            Cursor::Timestamp(ts) => doc!{"timestamp": {op: *ts as i64}},
+            Cursor::Position { timestamp, id } => doc!{"$or": [
+                {"timestamp": {op: *timestamp as i64}},
+                {"timestamp": *timestamp as i64, "_id": {op: id}},
+            ]},
+        }
+    }
+}
+
+pub struct Page {
+    pub messages: Vec<Message>,
+    pub has_more: bool,
+}
+
+/// Message content encrypted for a single device.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Payload {
+    pub device_id: ObjectId,
+    pub content: Vec<u8>,
+}
+
+/// Messages stored before devices existed deserialize with no sender device and
+/// no payloads, clients show them as unreadable. An edit is a message of its own
+/// with `edit_of` pointing at the original, deleted ones keep no payloads.
+/// Messages with `expires_at` are dropped once it passes.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Message {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     id: Option<ObjectId>,
     chat_id: ObjectId,
     sender_id: ObjectId,
-    message: Vec<u8>,
+    #[serde(default)]
+    sender_device: Option<ObjectId>,
+    #[serde(default)]
+    payloads: Vec<Payload>,
     timestamp: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    edit_of: Option<ObjectId>,
+    #[serde(default)]
+    deleted: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
 }
 
 impl Message {
-    pub fn new(chat_id: ObjectId, sender_id: ObjectId, message: Vec<u8>, timestamp: u64) -> Self {
+    pub fn new(chat_id: ObjectId, sender_id: ObjectId, sender_device: ObjectId, payloads: Vec<Payload>, timestamp: u64) -> Self {
         Message {
             id: None,
             chat_id,
             sender_id,
-            message,
+            sender_device: Some(sender_device),
+            payloads,
             timestamp,
+            edit_of: None,
+            deleted: false,
+            expires_at: None,
         }
     }
 
-    pub async fn add_to_db(db: &Db, message: &Message) -> Result<(), Error> {
+    /// Drops the message at `expires_at`, no later than the longest timer from now.
+    pub fn expiring(mut self, expires_at: Option<u64>) -> Self {
+        self.expires_at = expires_at.map(|at| at.min(now() + MAX_TIMER));
+        self
+    }
+
+    pub fn expires_at(&self) -> Option<u64> {
+        self.expires_at
+    }
+
+    /// Marks this message as replacement content of `original`.
+    pub fn editing(mut self, original: Option<ObjectId>) -> Self {
+        self.edit_of = original;
+        self
+    }
+
+    pub fn id(&self) -> Option<&ObjectId> {
+        self.id.as_ref()
+    }
+
+    pub fn edit_of(&self) -> Option<&ObjectId> {
+        self.edit_of.as_ref()
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.deleted
+    }
+
+    pub fn chat_id(&self) -> &ObjectId {
+        &self.chat_id
+    }
+
+    pub fn sender_id(&self) -> &ObjectId {
+        &self.sender_id
+    }
+
+    pub fn sender_device(&self) -> Option<&ObjectId> {
+        self.sender_device.as_ref()
+    }
+
+    pub fn payloads(&self) -> &[Payload] {
+        &self.payloads
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    /// Inserts the message, filling in the id it was stored under.
+    pub async fn add_to_db(db: &Db, message: &mut Message) -> Result<(), Error> {
         let bs = bson::to_bson(&message)
             .map_err(|err| BsonError::from(err))?;
         let document = bs.as_document()
             .ok_or(Error::BsonConvError(error::BsonError::ConversionError))?;
 
-        db.database(DATABASE)
+        let result = db.database(DATABASE)
             .collection::<mongodb::bson::Document>(COLLECTION)
             .insert_one(document.to_owned(), None).await
             .map_err(|_| Error::DbError("insert", format!("{:?}", message)))?;
+        message.id = result.inserted_id.as_object_id();
+
+        Ok(())
+    }
 
+    pub async fn get_by_id(db: &Db, id: &ObjectId) -> Result<Message, Error> {
+        let filter = doc!{
+            "_id": id
+        };
+        db.database(DATABASE)
+            .collection::<Message>(COLLECTION)
+            .find_one(filter, None).await
+            .map_err(|_| Error::DbError("find", format!("{}", id)))?
+            .ok_or(Error::NotFound("message"))
+    }
+
+    /// Drops ciphertexts of the message and every edit of it, leaving
+    /// tombstones so clients syncing later learn about the deletion.
+    pub async fn delete(db: &Db, id: &ObjectId) -> Result<(), Error> {
+        let query = doc!{
+            "$or": [{"_id": id}, {"edit_of": id}],
+        };
+        let update = doc!{
+            "$set": {
+                "deleted": true,
+                "payloads": [],
+            },
+        };
+        db.database(DATABASE)
+            .collection::<Message>(COLLECTION)
+            .update_many(query, update, None).await
+            .map_err(|_| Error::DbError("update message, delete", format!("{}", id)))?;
         Ok(())
     }
 
-    pub async fn get_messages_from_chat(db: &Db, chat_id: ObjectId) -> Result<Vec<Message>, Error> {
+    pub async fn get_by_sender(db: &Db, sender_id: &ObjectId) -> Result<Vec<Message>, Error> {
         let filter = doc!{
-            "chat_id": chat_id
+            "sender_id": sender_id
         };
+
+        let documents = db::get_all_in_vec(db, filter, None, COLLECTION).await?;
+        let mut messages = Vec::with_capacity(documents.len());
+        for doc in documents {
+            messages.push(from_document(doc)?);
+        }
+
+        Ok(messages)
+    }
+
+    pub async fn delete_by_sender(db: &Db, sender_id: &ObjectId) -> Result<(), Error> {
+        let query = doc!{
+            "sender_id": sender_id,
+        };
+        db.database(DATABASE)
+            .collection::<Message>(COLLECTION)
+            .delete_many(query, None).await
+            .map_err(|_| Error::DbError("delete messages of", format!("{}", sender_id)))?;
+        Ok(())
+    }
+
+    pub async fn delete_by_chats(db: &Db, chat_ids: &[ObjectId]) -> Result<(), Error> {
+        let query = doc!{
+            "chat_id": {"$in": chat_ids},
+        };
+        db.database(DATABASE)
+            .collection::<Message>(COLLECTION)
+            .delete_many(query, None).await
+            .map_err(|_| Error::DbError("delete messages", String::from(COLLECTION)))?;
+        Ok(())
+    }
+
+    /// Removes every message whose timer ran out, returns how many there were.
+    pub async fn delete_expired(db: &Db) -> Result<u64, Error> {
+        let query = doc!{
+            "expires_at": {"$lte": now() as i64},
+        };
+        let result = db.database(DATABASE)
+            .collection::<Message>(COLLECTION)
+            .delete_many(query, None).await
+            .map_err(|_| Error::DbError("delete expired", String::from(COLLECTION)))?;
+        Ok(result.deleted_count)
+    }
+
+    /// Returns up to `limit` messages of a chat in ascending order.
+    /// With `after` set the page starts right after the cursor, otherwise it ends
+    /// right before `before` (or at the newest message when no cursor is given).
+    pub async fn get_page(db: &Db, chat_id: ObjectId, before: Option<Cursor>, after: Option<Cursor>, limit: i64) -> Result<Page, Error> {
+        let limit = limit.clamp(1, MAX_PAGE_SIZE);
+        let mut conditions = vec![
+            doc!{"chat_id": chat_id},
+            doc!{"$or": [{"expires_at": {"$exists": false}}, {"expires_at": {"$gt": now() as i64}}]},
+        ];
+        if let Some(cursor) = before {
+            conditions.push(cursor.bound("$lt"));
+        }
+        if let Some(cursor) = after {
+            conditions.push(cursor.bound("$gt"));
+        }
+        let filter = doc!{"$and": conditions};
+
+        let ascending = after.is_some() && before.is_none();
+        let field = after.or(before)
+            .map(|c| c.field())
+            .unwrap_or("_id");
+        let direction = if ascending { 1 } else { -1 };
         let options = FindOptions::builder()
-            .sort(doc!{"_id": 1})
+            .sort(doc!{field: direction, "_id": direction})
+            .limit(limit + 1)
             .build();
 
         let db = db
             .database(DATABASE)
             .collection::<Message>(COLLECTION);
-        
+
         let cursor = db.find(filter.clone(), options).await
             .map_err(|_| Error::DbError("find", filter.to_string()))?;
-        let results: Vec<Message> = cursor.try_collect().await
+        let mut messages: Vec<Message> = cursor.try_collect().await
             .map_err(|_| Error::DbError("find", filter.to_string()))?;
 
-        Ok(results)
+        let has_more = messages.len() as i64 > limit;
+        messages.truncate(limit as usize);
+        if !ascending {
+            messages.reverse();
+        }
+
+        Ok(Page { messages, has_more })
+    }
+
+    pub async fn create_indexes(db: &Db) -> Result<(), Error> {
+        let indexes = vec![
+            IndexModel::builder()
+                .keys(doc!{"chat_id": 1, "_id": 1})
+                .build(),
+            IndexModel::builder()
+                .keys(doc!{"chat_id": 1, "timestamp": 1})
+                .build(),
+            IndexModel::builder()
+                .keys(doc!{"expires_at": 1})
+                .options(IndexOptions::builder().sparse(true).build())
+                .build(),
+        ];
+
+        db.database(DATABASE)
+            .collection::<mongodb::bson::Document>(COLLECTION)
+            .create_indexes(indexes, None).await
+            .map_err(|_| Error::DbError("create indexes on", String::from(COLLECTION)))?;
+
+        Ok(())
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_micros() as u64
+}
+
+#[cfg(test)]
+mod message_test {
+    use bson::{doc, oid::ObjectId};
+    use super::{Cursor, Message};
+
+    #[test]
+    fn cursor_bound_by_id() {
+        let id = ObjectId::new();
+        let cursor = Cursor::Id(id);
+
+        assert_eq!(cursor.field(), "_id");
+        assert_eq!(cursor.bound("$lt"), doc!{"_id": {"$lt": id}});
+    }
+
+    #[test]
+    fn cursor_bound_by_timestamp() {
+        let cursor = Cursor::Timestamp(42);
+
+        assert_eq!(cursor.field(), "timestamp");
+        assert_eq!(cursor.bound("$gt"), doc!{"timestamp": {"$gt": 42i64}});
+    }
+
+    #[test]
+    fn cursor_bound_by_position() {
+        let id = ObjectId::new();
+        let cursor = Cursor::Position { timestamp: 42, id };
+
+        assert_eq!(cursor.field(), "timestamp");
+        assert_eq!(cursor.bound("$gt"), doc!{"$or": [
+            {"timestamp": {"$gt": 42i64}},
+            {"timestamp": 42i64, "_id": {"$gt": id}},
+        ]});
+    }
+
+    #[test]
+    fn cursor_deserialize() {
+        let cursor: Cursor = serde_json::from_str(r#"{"timestamp": 7}"#).unwrap();
+
+        assert!(matches!(cursor, Cursor::Timestamp(7)));
+    }
+
+    #[test]
+    fn legacy_message_is_plain() {
+        let document = doc!{"chat_id": ObjectId::new(), "sender_id": ObjectId::new(), "timestamp": 1i64};
+        let message: Message = bson::from_document(document).unwrap();
+
+        assert!(!message.is_deleted());
+        assert!(message.edit_of().is_none());
+        assert!(message.expires_at().is_none());
+    }
+
+    #[test]
+    fn expiry_is_capped() {
+        let far = u64::MAX / 2;
+        let message = Message::new(ObjectId::new(), ObjectId::new(), ObjectId::new(), vec![], 1)
+            .expiring(Some(far));
+        let soon = Message::new(ObjectId::new(), ObjectId::new(), ObjectId::new(), vec![], 1)
+            .expiring(Some(5));
+
+        assert!(message.expires_at().unwrap() < far);
+        assert_eq!(soon.expires_at(), Some(5));
+    }
+
+    #[test]
+    fn edit_serializes_reference() {
+        let original = ObjectId::new();
+        let message = Message::new(ObjectId::new(), ObjectId::new(), ObjectId::new(), vec![], 2)
+            .editing(Some(original));
+        let document = bson::to_document(&message).unwrap();
+
+        assert_eq!(document.get_object_id("edit_of").unwrap(), original);
+        assert!(!document.contains_key("_id"));
     }
 }
@@ -3,6 +3,11 @@ pub mod user;
 pub mod chat;
 pub mod message;
 pub mod keys;
+pub mod device;
+pub mod contact;
+pub mod block;
+pub mod report;
+pub mod mail_token;
 
 pub use db::Db;
 
@@ -22,6 +27,8 @@ pub enum Error {
     DbError(&'static str, String),
     #[error("No user with such email")]
     NoUserWithSuchEmail,
+    #[error("Not found: {0}")]
+    NotFound(&'static str),
     #[error("Failed connecting to db")]
     CouldNotConnectToDB,
     #[error("Invalid ObjectId")]
@@ -0,0 +1,126 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use bson::doc;
+use bson::oid::ObjectId;
+use mongodb::IndexModel;
+use serde::{Serialize, Deserialize};
+use crate::model::{Db, db, Error};
+use crate::error;
+use super::{from_document, BsonError, DATABASE};
+
+const COLLECTION: &str = "report";
+
+/// Abuse report waiting for a moderator. Messages are end-to-end encrypted, so
+/// `excerpt` is whatever decrypted text the reporter chose to share.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Report {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    id: Option<ObjectId>,
+    reporter_id: ObjectId,
+    reported_id: ObjectId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    chat_id: Option<ObjectId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    message_id: Option<ObjectId>,
+    reason: String,
+    excerpt: String,
+    created_at: u64,
+    #[serde(default)]
+    reviewed: bool,
+}
+
+impl Report {
+    pub fn new(reporter_id: ObjectId, reported_id: ObjectId, reason: String, excerpt: String) -> Self {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs();
+        Report {
+            id: None,
+            reporter_id,
+            reported_id,
+            chat_id: None,
+            message_id: None,
+            reason,
+            excerpt,
+            created_at,
+            reviewed: false,
+        }
+    }
+
+    /// Points the report at the reported message, the server keeps only its ciphertext.
+    pub fn about(mut self, chat_id: Option<ObjectId>, message_id: Option<ObjectId>) -> Self {
+        self.chat_id = chat_id;
+        self.message_id = message_id;
+        self
+    }
+
+    pub async fn add_to_db(db: &Db, report: &mut Report) -> Result<ObjectId, Error> {
+        let bs = bson::to_bson(&report)
+            .map_err(BsonError::from)?;
+        let document = bs.as_document()
+            .ok_or(Error::BsonConvError(error::BsonError::ConversionError))?;
+
+        let result = db.database(DATABASE)
+            .collection::<mongodb::bson::Document>(COLLECTION)
+            .insert_one(document.to_owned(), None).await
+            .map_err(|_| Error::DbError("insert", format!("{:?}", report)))?;
+        let id = result.inserted_id.as_object_id()
+            .ok_or(Error::BsonConvError(error::BsonError::ConversionError))?;
+        report.id = Some(id);
+
+        Ok(id)
+    }
+
+    pub async fn get_by_reporter(db: &Db, reporter_id: &ObjectId) -> Result<Vec<Report>, Error> {
+        let filter = doc!{
+            "reporter_id": reporter_id
+        };
+
+        let documents = db::get_all_in_vec(db, filter, None, COLLECTION).await?;
+        let mut reports = Vec::with_capacity(documents.len());
+        for doc in documents {
+            reports.push(from_document(doc)?);
+        }
+
+        Ok(reports)
+    }
+
+    pub async fn create_indexes(db: &Db) -> Result<(), Error> {
+        let indexes = vec![
+            IndexModel::builder()
+                .keys(doc!{"reviewed": 1, "created_at": 1})
+                .build(),
+            IndexModel::builder()
+                .keys(doc!{"reported_id": 1})
+                .build(),
+        ];
+
+        db.database(DATABASE)
+            .collection::<mongodb::bson::Document>(COLLECTION)
+            .create_indexes(indexes, None).await
+            .map_err(|_| Error::DbError("create indexes on", String::from(COLLECTION)))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod report_test {
+    use bson::oid::ObjectId;
+    use super::Report;
+
+    #[test]
+    fn report_serializes_target() {
+        let (chat, message) = (ObjectId::new(), ObjectId::new());
+        let bare = Report::new(ObjectId::new(), ObjectId::new(), String::new(), String::from("spam"));
+        let about = Report::new(ObjectId::new(), ObjectId::new(), String::new(), String::from("spam"))
+            .about(Some(chat), Some(message));
+
+        let document = bson::to_document(&bare).unwrap();
+        assert!(!document.contains_key("message_id"));
+        assert!(!document.get_bool("reviewed").unwrap());
+        let document = bson::to_document(&about).unwrap();
+        assert_eq!(document.get_object_id("chat_id").unwrap(), chat);
+        assert_eq!(document.get_object_id("message_id").unwrap(), message);
+    }
+}
@@ -1,12 +1,18 @@
 use bson::doc;
 use serde::{Serialize, Deserialize};
-use mongodb::bson::oid::ObjectId;
+use futures::TryStreamExt;
+use mongodb::{bson::oid::ObjectId, IndexModel, options::{Collation, CollationStrength, FindOneAndUpdateOptions, FindOneOptions, FindOptions, IndexOptions, ReturnDocument}};
 use crate::model::{Db, db, Error};
 use crate::error;
 use super::{objectid_from_str, from_document, BsonError};
 use super::DATABASE;
 
 const COLLECTION: &'static str  = "user";
+/// Most users returned by a single search.
+pub const SEARCH_LIMIT: usize = 10;
+/// Users fetched from the database before ranking, a little over the limit so
+/// closer matches found later still make it into the results.
+const SEARCH_CANDIDATES: i64 = 50;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct User {
@@ -15,6 +21,16 @@ pub struct User {
     pub email: String,
     pub username: String,
     password: String,
+    #[serde(default)]
+    hide_presence: bool,
+    #[serde(default)]
+    hide_from_search: bool,
+    /// Accounts from before verification existed count as verified.
+    #[serde(default = "legacy_verified")]
+    verified: bool,
+    /// Part of every token, bumping it signs out all sessions.
+    #[serde(default)]
+    token_version: u32,
 }
 
 impl User {
@@ -24,6 +40,10 @@ impl User {
             email: email.clone(),
             username: username.clone(),
             password: password.clone(),
+            hide_presence: false,
+            hide_from_search: false,
+            verified: false,
+            token_version: 0,
         }
     }
 
@@ -35,11 +55,27 @@ impl User {
         &self.username
     }
 
+    pub fn hides_presence(&self) -> bool {
+        self.hide_presence
+    }
+
+    pub fn hides_from_search(&self) -> bool {
+        self.hide_from_search
+    }
+
+    pub fn is_verified(&self) -> bool {
+        self.verified
+    }
+
+    pub fn token_version(&self) -> u32 {
+        self.token_version
+    }
+
     pub fn password_matches(self: &Self, hashed_password: &String) -> bool {
         self.password.eq(hashed_password)
     }
 
-    pub async fn add_to_db(db: &Db, user: &User) -> Result<(), Error> {
+    pub async fn add_to_db(db: &Db, user: &User) -> Result<ObjectId, Error> {
         let bs = bson::to_bson(&user)
             .map_err(|err| BsonError::from(err))?;
         let document = bs.as_document()
@@ -49,35 +85,231 @@ impl User {
             .database(DATABASE)
             .collection::<mongodb::bson::Document>(COLLECTION);
 
-        userdb.insert_one(document.to_owned(), None).await
-            .map_err(|_| Error::DbError("insert", format!("{:?}", user)))?;
+        let result = userdb.insert_one(document.to_owned(), None).await
+            .map_err(|err| match db::is_duplicate_key(&err) {
+                true => Error::NotUnique("email or username"),
+                false => Error::DbError("insert", format!("{:?}", user)),
+            })?;
+        let id = result.inserted_id.as_object_id()
+            .ok_or(Error::BsonConvError(error::BsonError::ConversionError))?;
+
+        Ok(id)
+    }
+
+    pub async fn delete(db: &Db, id: &ObjectId) -> Result<(), Error> {
+        let query = doc!{
+            "_id": id,
+        };
+        let result = db.database(DATABASE)
+            .collection::<User>(COLLECTION)
+            .delete_one(query, None).await
+            .map_err(|_| Error::DbError("delete user", format!("{}", id)))?;
+        if result.deleted_count == 0 {
+            return Err(Error::NotFound("user"));
+        }
+        Ok(())
+    }
 
+    /// Replaces the password and revokes every token issued so far, returns
+    /// the updated user to issue a new one.
+    pub async fn set_password(db: &Db, id: &ObjectId, hashed_password: &String) -> Result<User, Error> {
+        let query = doc!{
+            "_id": id,
+        };
+        let update = doc!{
+            "$set": {
+                "password": hashed_password
+            },
+            "$inc": {
+                "token_version": 1
+            },
+        };
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        db.database(DATABASE)
+            .collection::<User>(COLLECTION)
+            .find_one_and_update(query, update, options).await
+            .map_err(|_| Error::DbError("update user, password", format!("{}", id)))?
+            .ok_or(Error::NotFound("user"))
+    }
+
+    pub async fn set_verified(db: &Db, id: &ObjectId) -> Result<(), Error> {
+        let query = doc!{
+            "_id": id,
+        };
+        let update = doc!{
+            "$set": {
+                "verified": true
+            },
+        };
+        db.database(DATABASE)
+            .collection::<User>(COLLECTION)
+            .update_one(query, update, None).await
+            .map_err(|_| Error::DbError("update user, verified", format!("{}", id)))?;
         Ok(())
     }
 
+    pub async fn set_presence_hidden(db: &Db, id: &ObjectId, hidden: bool) -> Result<(), Error> {
+        let query = doc!{
+            "_id": id,
+        };
+        let update = doc!{
+            "$set": {
+                "hide_presence": hidden
+            },
+        };
+        db.database(DATABASE)
+            .collection::<User>(COLLECTION)
+            .update_one(query, update, None).await
+            .map_err(|_| Error::DbError("update user, presence", format!("{}", id)))?;
+        Ok(())
+    }
+
+    /// Hidden users are left out of search results, starting a chat with the
+    /// exact username still works.
+    pub async fn set_search_hidden(db: &Db, id: &ObjectId, hidden: bool) -> Result<(), Error> {
+        let query = doc!{
+            "_id": id,
+        };
+        let update = doc!{
+            "$set": {
+                "hide_from_search": hidden
+            },
+        };
+        db.database(DATABASE)
+            .collection::<User>(COLLECTION)
+            .update_one(query, update, None).await
+            .map_err(|_| Error::DbError("update user, search", format!("{}", id)))?;
+        Ok(())
+    }
+
+    /// Users whose name starts with `query` or contains its letters in order,
+    /// best matches first. `query` must already be a valid part of a username.
+    pub async fn search(db: &Db, query: &str, exclude: &ObjectId) -> Result<Vec<User>, Error> {
+        let pattern = query.chars()
+            .map(String::from)
+            .collect::<Vec<String>>()
+            .join(".*");
+        let filter = doc!{
+            "username": {"$regex": format!("^{}", pattern), "$options": "i"},
+            "hide_from_search": {"$ne": true},
+            "_id": {"$ne": exclude},
+        };
+        let options = FindOptions::builder()
+            .limit(SEARCH_CANDIDATES)
+            .build();
+
+        let documents = db::get_all_in_vec(db, filter, options, COLLECTION).await?;
+        let mut users: Vec<User> = Vec::with_capacity(documents.len());
+        for doc in documents {
+            users.push(from_document(doc)?);
+        }
+        users.sort_by_cached_key(|user| search_rank(query, &user.username));
+        users.truncate(SEARCH_LIMIT);
+
+        Ok(users)
+    }
+
+    pub async fn get_by_ids(db: &Db, ids: &[ObjectId]) -> Result<Vec<User>, Error> {
+        let filter = doc!{
+            "_id": {"$in": ids}
+        };
+
+        let documents = db::get_all_in_vec(db, filter, None, COLLECTION).await?;
+        let mut users = Vec::with_capacity(documents.len());
+        for doc in documents {
+            users.push(from_document(doc)?);
+        }
+
+        Ok(users)
+    }
+
+    /// Emails and usernames are unique regardless of case. The case sensitive
+    /// indexes of older databases are replaced.
+    pub async fn create_indexes(db: &Db) -> Result<(), Error> {
+        let unique = |name: &str| IndexOptions::builder()
+            .unique(true)
+            .collation(case_insensitive())
+            .name(String::from(name))
+            .build();
+        let indexes = vec![
+            IndexModel::builder()
+                .keys(doc!{"email": 1})
+                .options(unique("email_ci"))
+                .build(),
+            IndexModel::builder()
+                .keys(doc!{"username": 1})
+                .options(unique("username_ci"))
+                .build(),
+        ];
+
+        let collection = db.database(DATABASE)
+            .collection::<mongodb::bson::Document>(COLLECTION);
+        collection.create_indexes(indexes, None).await
+            .map_err(|_| Error::DbError("create indexes on", String::from(COLLECTION)))?;
+        for legacy in ["email_1", "username_1"] {
+            // Missing on new databases.
+            let _ = collection.drop_index(legacy, None).await;
+        }
+
+        Ok(())
+    }
+
+    /// Groups of users whose email or username only differ in case, they keep
+    /// the unique indexes from being built and have to be resolved by hand.
+    pub async fn find_case_duplicates(db: &Db) -> Result<Vec<Vec<String>>, Error> {
+        let collection = db.database(DATABASE)
+            .collection::<mongodb::bson::Document>(COLLECTION);
+        let mut groups = Vec::new();
+        for field in ["email", "username"] {
+            let pipeline = vec![
+                doc!{"$group": {
+                    "_id": {"$toLower": format!("${}", field)},
+                    "users": {"$push": {"$concat": [{"$toString": "$_id"}, " ", format!("${}", field)]}},
+                    "count": {"$sum": 1},
+                }},
+                doc!{"$match": {"count": {"$gt": 1}}},
+            ];
+            let documents: Vec<bson::Document> = collection.aggregate(pipeline, None).await
+                .map_err(|_| Error::DbError("aggregate", String::from(COLLECTION)))?
+                .try_collect().await
+                .map_err(|_| Error::DbError("aggregate", String::from(COLLECTION)))?;
+            for document in documents {
+                let users = document.get_array("users")
+                    .map_err(|_| Error::BsonConvError(error::BsonError::ConversionError))?
+                    .iter()
+                    .filter_map(|user| user.as_str().map(String::from))
+                    .collect();
+                groups.push(users);
+            }
+        }
+
+        Ok(groups)
+    }
+
     pub async fn get_by_email(db: &Db, email: &String) -> Result<User, Error> {
         let filter = doc!{
             "email": email.as_str()
         };
-        let document = db::get_by(db, &filter, &String::from("user"))
-            .await?
-            .ok_or(Error::NoUserWithSuchEmail)?;
-        
-        let user = from_document(document)?;
-
-        Ok(user)
+        get_one_case_insensitive(db, filter).await?
+            .ok_or(Error::NoUserWithSuchEmail)
     }
 
     pub async fn get_by_id(db: &Db, id: &String) -> Result<User, Error> {
         let id = objectid_from_str(id)
             .map_err(|_| Error::InvalidOID)?;
+        User::get_by_oid(db, &id).await
+    }
+
+    pub async fn get_by_oid(db: &Db, id: &ObjectId) -> Result<User, Error> {
         let filter = doc!{
             "_id": id
         };
 
         let document = db::get_by(db, &filter, &String::from("user"))
             .await?
-            .ok_or(Error::DbError("find", id.to_string()))?;
+            .ok_or(Error::NotFound("user"))?;
 
         let user = from_document(document)?;
         
@@ -88,12 +320,60 @@ impl User {
         let filter = doc!{
             "username": username.as_str()
         };
-        let document = db::get_by(db, &filter, &String::from("user"))
-            .await?
-            .ok_or(Error::NoUserWithSuchEmail)?;
-        
-        let user = from_document(document)?;
+        get_one_case_insensitive(db, filter).await?
+            .ok_or(Error::NotFound("user"))
+    }
+}
 
-        Ok(user)
+/// Collation of the unique indexes, lookups have to use it to hit them.
+fn case_insensitive() -> Collation {
+    Collation::builder()
+        .locale("en")
+        .strength(CollationStrength::Secondary)
+        .build()
+}
+
+async fn get_one_case_insensitive(db: &Db, filter: bson::Document) -> Result<Option<User>, Error> {
+    let options = FindOneOptions::builder()
+        .collation(case_insensitive())
+        .build();
+    let document = db.database(DATABASE)
+        .collection::<mongodb::bson::Document>(COLLECTION)
+        .find_one(filter.clone(), options).await
+        .map_err(|_| Error::DbError("find", filter.to_string()))?;
+
+    match document {
+        Some(document) => Ok(Some(from_document(document)?)),
+        None => Ok(None),
+    }
+}
+
+fn legacy_verified() -> bool {
+    true
+}
+
+/// Sort key of a search result: exact names first, then names starting with
+/// the query, then the rest, shorter names before longer ones.
+fn search_rank(query: &str, username: &str) -> (u8, usize, String) {
+    let query = query.to_lowercase();
+    let name = username.to_lowercase();
+    let kind = match name.strip_prefix(&query) {
+        Some("") => 0,
+        Some(_) => 1,
+        None => 2,
+    };
+    (kind, name.len(), name)
+}
+
+#[cfg(test)]
+mod user_test {
+    use super::search_rank;
+
+    #[test]
+    fn exact_and_prefix_rank_first() {
+        let mut names = vec!["alexander", "ax", "Alex", "alex_b", "anne_x"];
+        names.sort_by_cached_key(|name| search_rank("alex", name));
+
+        assert_eq!(names, vec!["Alex", "alex_b", "alexander", "ax", "anne_x"]);
     }
 }
@@ -0,0 +1,156 @@
+use std::sync::Arc;
+use bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use warp::{Filter, reject::Rejection, reply::Json};
+use crate::{model::{self, Db, block::Block, message::Message, report::Report, user::User, objectid_from_str}, error::Error, server::{with_auth, with_ip_limit}, LimitsHandle};
+use super::{json_response, member_chat};
+
+const MAX_REASON: usize = 500;
+const MAX_EXCERPT: usize = 4000;
+
+#[derive(Deserialize)]
+struct BlockBody {
+    username: String,
+}
+
+#[derive(Deserialize)]
+struct ReportBody {
+    username: String,
+    reason: String,
+    excerpt: String,
+    chat_id: Option<ObjectId>,
+    message_id: Option<ObjectId>,
+    #[serde(default)]
+    block: bool,
+}
+
+#[derive(Serialize)]
+struct BlockedResponse {
+    id: ObjectId,
+    username: String,
+}
+
+pub fn block_paths(db: Arc<Db>, limits: LimitsHandle) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    let auth = with_auth(db.clone());
+    let with_db = warp::any()
+        .map(move || db.clone());
+    let common = with_db.clone()
+        .and(auth);
+
+    let get_blocked = warp::path("blocks")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(common.clone())
+        .and_then(get_blocked_handle);
+
+    let block = warp::path("block")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(common.clone())
+        .and(warp::body::json())
+        .and_then(block_handle);
+
+    let unblock = warp::path!("block" / "remove")
+        .and(warp::post())
+        .and(common.clone())
+        .and(warp::body::json())
+        .and_then(unblock_handle);
+
+    let report = warp::path("report")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(with_ip_limit(limits.clone(), |l| &l.lookup))
+        .and(common.clone())
+        .and(warp::body::json())
+        .and_then(report_handle);
+
+    get_blocked
+        .or(block)
+        .or(unblock)
+        .or(report)
+}
+
+async fn get_blocked_handle(db: Arc<Db>, oid: String) -> Result<Json, Rejection> {
+    let blocker_id = objectid_from_str(&oid)?;
+    let ids: Vec<ObjectId> = Block::get_by_blocker(&db, &blocker_id).await?
+        .iter()
+        .map(|block| *block.blocked_id())
+        .collect();
+    let mut blocked: Vec<BlockedResponse> = User::get_by_ids(&db, &ids).await?
+        .into_iter()
+        .filter_map(|user| Some(BlockedResponse { id: user.id?, username: user.username }))
+        .collect();
+    blocked.sort_by(|a, b| a.username.cmp(&b.username));
+
+    let response = json!({
+        "blocked": blocked
+    });
+    json_response(&response)
+}
+
+async fn block_handle(db: Arc<Db>, oid: String, body: BlockBody) -> Result<Json, Rejection> {
+    let blocker_id = objectid_from_str(&oid)?;
+    let blocked_id = user_id(&db, &body.username).await?;
+    if blocked_id == blocker_id {
+        return Err(Error::Forbidden("can't block yourself").into());
+    }
+    Block::add_to_db(&db, &Block::new(blocker_id, blocked_id)).await?;
+
+    let content = json!({
+        "message": "success",
+    });
+    json_response(&content)
+}
+
+async fn unblock_handle(db: Arc<Db>, oid: String, body: BlockBody) -> Result<Json, Rejection> {
+    let blocker_id = objectid_from_str(&oid)?;
+    let blocked_id = user_id(&db, &body.username).await?;
+    Block::remove(&db, &blocker_id, &blocked_id).await?;
+
+    let content = json!({
+        "message": "success",
+    });
+    json_response(&content)
+}
+
+/// Stores the report for moderators, a reported message must have been sent
+/// by the reported user in a chat the reporter takes part in.
+async fn report_handle(db: Arc<Db>, oid: String, body: ReportBody) -> Result<Json, Rejection> {
+    let reporter_id = objectid_from_str(&oid)?;
+    let reported_id = user_id(&db, &body.username).await?;
+    if reported_id == reporter_id {
+        return Err(Error::Forbidden("can't report yourself").into());
+    }
+    let excerpt: String = body.excerpt.trim().chars().take(MAX_EXCERPT).collect();
+    if excerpt.is_empty() {
+        return Err(Error::BodyError("excerpt").into());
+    }
+    let reason: String = body.reason.trim().chars().take(MAX_REASON).collect();
+    if let Some(chat_id) = &body.chat_id {
+        member_chat(&db, &oid, chat_id).await?;
+    }
+    if let Some(message_id) = &body.message_id {
+        let message = Message::get_by_id(&db, message_id).await?;
+        if Some(message.chat_id()) != body.chat_id.as_ref() || message.sender_id() != &reported_id {
+            return Err(model::Error::NotFound("message").into());
+        }
+    }
+    let mut report = Report::new(reporter_id, reported_id, reason, excerpt)
+        .about(body.chat_id, body.message_id);
+    let id = Report::add_to_db(&db, &mut report).await?;
+    if body.block {
+        Block::add_to_db(&db, &Block::new(reporter_id, reported_id)).await?;
+    }
+
+    let content = json!({
+        "report": id,
+    });
+    json_response(&content)
+}
+
+async fn user_id(db: &Db, username: &String) -> Result<ObjectId, Rejection> {
+    let user = User::get_by_username(db, username).await?;
+    let id = user.id().ok_or(Error::InternalError)?;
+    Ok(*id)
+}
@@ -1,14 +1,15 @@
 use std::{sync::Arc, collections::HashMap};
 use serde_json::json;
 use warp::{Filter, reject::Rejection, reply::Json};
-use crate::{model::{Db, chat::Chat, user::User}, error::Error, server::with_auth};
-use super::json_response;
+use crate::{model::{Db, chat::Chat, user::User, objectid_from_str}, error::Error, server::with_auth};
+use super::{json_response, not_blocked};
 
 pub fn chat_paths(db: Arc<Db>) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    let auth = with_auth(db.clone());
     let with_db = warp::any()
         .map(move || db.clone());
     let common = with_db.clone()
-        .and(with_auth());
+        .and(auth);
 
     let get_chats = warp::path("chats")
         .and(warp::path::end())
@@ -36,16 +37,21 @@ async fn get_chats_handle(db: Arc<Db>, oid: String) -> Result<Json, Rejection> {
     json_response(&response)
 }
 
+/// Returns the chat with `member`, only verified users may start new ones.
 async fn add_chat_handle(db: Arc<Db>, oid: String, body: HashMap<String, String>) -> Result<Json, Rejection> {
     let member_name = body.get("member")
         .ok_or(Error::BodyError("member"))?;
     let member = User::get_by_username(&db, &member_name).await?;
     let member_id = member.id().ok_or(Error::InternalError)?;
+    not_blocked(&db, &objectid_from_str(&oid)?, &[*member_id]).await?;
     let id = match Chat::get_by_users(&db, &oid, &member_id).await {
         Ok(chat) => {
             chat.id().to_owned()
         },
         Err(_) => {
+            if !User::get_by_id(&db, &oid).await?.is_verified() {
+                return Err(Error::Unverified.into());
+            }
             let chat = Chat::new(&oid, member_id.to_owned())?;
             Chat::add_to_db(&db, &chat).await.ok()
         },
@@ -0,0 +1,120 @@
+use std::sync::Arc;
+use bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use warp::{Filter, reject::Rejection, reply::Json};
+use crate::{model::{Db, contact::Contact, user::User, objectid_from_str}, error::Error, server::with_auth};
+use super::json_response;
+
+const MAX_NICKNAME: usize = 64;
+
+#[derive(Deserialize)]
+struct AddContactBody {
+    username: String,
+    nickname: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RemoveContactBody {
+    username: String,
+}
+
+#[derive(Serialize)]
+struct ContactResponse {
+    id: ObjectId,
+    username: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nickname: Option<String>,
+}
+
+pub fn contact_paths(db: Arc<Db>) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    let auth = with_auth(db.clone());
+    let with_db = warp::any()
+        .map(move || db.clone());
+    let common = with_db.clone()
+        .and(auth);
+
+    let get_contacts = warp::path("contacts")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(common.clone())
+        .and_then(get_contacts_handle);
+
+    let add_contact = warp::path("contact")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(common.clone())
+        .and(warp::body::json())
+        .and_then(add_contact_handle);
+
+    let remove_contact = warp::path!("contact" / "remove")
+        .and(warp::post())
+        .and(common.clone())
+        .and(warp::body::json())
+        .and_then(remove_contact_handle);
+
+    get_contacts
+        .or(add_contact)
+        .or(remove_contact)
+}
+
+/// Contacts with their current usernames, ones whose account is gone are left out.
+async fn get_contacts_handle(db: Arc<Db>, oid: String) -> Result<Json, Rejection> {
+    let owner_id = objectid_from_str(&oid)?;
+    let contacts = Contact::get_by_owner(&db, &owner_id).await?;
+    let ids: Vec<ObjectId> = contacts.iter()
+        .map(|contact| *contact.contact_id())
+        .collect();
+    let users = User::get_by_ids(&db, &ids).await?;
+
+    let mut contacts: Vec<ContactResponse> = contacts.into_iter()
+        .filter_map(|contact| {
+            let user = users.iter().find(|u| u.id() == Some(contact.contact_id()))?;
+            Some(ContactResponse {
+                id: *contact.contact_id(),
+                username: user.username().clone(),
+                nickname: contact.nickname().cloned(),
+            })
+        })
+        .collect();
+    contacts.sort_by(|a, b| a.username.cmp(&b.username));
+
+    let response = json!({
+        "contacts": contacts
+    });
+    json_response(&response)
+}
+
+/// Adds user to the contacts, for a contact already there it only changes the nickname.
+async fn add_contact_handle(db: Arc<Db>, oid: String, body: AddContactBody) -> Result<Json, Rejection> {
+    let owner_id = objectid_from_str(&oid)?;
+    let user = User::get_by_username(&db, &body.username).await?;
+    let contact_id = *user.id().ok_or(Error::InternalError)?;
+    if contact_id == owner_id {
+        return Err(Error::Forbidden("can't add yourself as a contact").into());
+    }
+    let nickname = body.nickname
+        .map(|nickname| nickname.trim().chars().take(MAX_NICKNAME).collect::<String>())
+        .filter(|nickname| !nickname.is_empty());
+    let contact = Contact::new(owner_id, contact_id, nickname);
+    Contact::add_to_db(&db, &contact).await?;
+
+    let response = ContactResponse {
+        id: contact_id,
+        username: user.username().clone(),
+        nickname: contact.nickname().cloned(),
+    };
+    json_response(&response)
+}
+
+async fn remove_contact_handle(db: Arc<Db>, oid: String, body: RemoveContactBody) -> Result<Json, Rejection> {
+    let owner_id = objectid_from_str(&oid)?;
+    let user = User::get_by_username(&db, &body.username).await?;
+    let contact_id = user.id().ok_or(Error::InternalError)?;
+    Contact::remove(&db, &owner_id, contact_id).await?;
+
+    let content = json!({
+        "message": "success",
+    });
+    json_response(&content)
+}
@@ -0,0 +1,143 @@
+use std::sync::Arc;
+use bson::oid::ObjectId;
+use serde::Deserialize;
+use serde_json::json;
+use warp::{Filter, reject::Rejection, reply::Json};
+use crate::{model::{Db, device::Device, keys::RegisterBundle, user::User, objectid_from_str}, error::Error, server::{with_auth, with_device, with_ip_limit}, LimitsHandle};
+use super::{json_response, not_blocked, owned_device};
+
+const DEFAULT_DEVICE_NAME: &str = "device";
+const MAX_DEVICE_NAME: usize = 64;
+
+#[derive(Deserialize)]
+struct RegisterDeviceBody {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct UserDevicesBody {
+    username: String,
+}
+
+#[derive(Deserialize)]
+struct TargetDeviceBody {
+    device_id: ObjectId,
+}
+
+pub fn device_paths(db: Arc<Db>, limits: LimitsHandle) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    let auth = with_auth(db.clone());
+    let with_db = warp::any()
+        .map(move || db.clone());
+    let common = with_db.clone()
+        .and(auth);
+
+    let register_device = warp::path("device")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(common.clone())
+        .and(warp::body::json())
+        .and_then(register_device_handle);
+
+    let own_devices = warp::path("devices")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(common.clone())
+        .and_then(own_devices_handle);
+
+    let user_devices = warp::path("user_devices")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(with_ip_limit(limits.clone(), |l| &l.lookup))
+        .and(common.clone())
+        .and(warp::body::json())
+        .and_then(user_devices_handle);
+
+    let approve_device = warp::path!("device" / "approve")
+        .and(warp::post())
+        .and(common.clone())
+        .and(with_device())
+        .and(warp::body::json())
+        .and_then(approve_device_handle);
+
+    let remove_device = warp::path!("device" / "remove")
+        .and(warp::post())
+        .and(common.clone())
+        .and(with_device())
+        .and(warp::body::json())
+        .and_then(remove_device_handle);
+
+    register_device
+        .or(own_devices)
+        .or(user_devices)
+        .or(approve_device)
+        .or(remove_device)
+}
+
+/// First device of an account is linked right away, later ones wait for approval.
+async fn register_device_handle(db: Arc<Db>, oid: String, body: RegisterDeviceBody) -> Result<Json, Rejection> {
+    let user_id = objectid_from_str(&oid)?;
+    let approved = Device::get_by_user(&db, &user_id, true).await?
+        .is_empty();
+    let name: String = body.name.trim()
+        .chars()
+        .take(MAX_DEVICE_NAME)
+        .collect();
+    let name = match name.is_empty() {
+        true => DEFAULT_DEVICE_NAME,
+        false => name.as_str(),
+    };
+    let mut device = Device::new(user_id, name, approved);
+    Device::add_to_db(&db, &mut device).await?;
+
+    let response = json!({
+        "device": device
+    });
+    json_response(&response)
+}
+
+async fn own_devices_handle(db: Arc<Db>, oid: String) -> Result<Json, Rejection> {
+    let user_id = objectid_from_str(&oid)?;
+    let devices = Device::get_by_user(&db, &user_id, false).await?;
+
+    let response = json!({
+        "devices": devices
+    });
+    json_response(&response)
+}
+
+async fn user_devices_handle(db: Arc<Db>, oid: String, body: UserDevicesBody) -> Result<Json, Rejection> {
+    let user = User::get_by_username(&db, &body.username).await?;
+    let user_id = user.id().ok_or(Error::InternalError)?;
+    not_blocked(&db, &objectid_from_str(&oid)?, &[*user_id]).await?;
+    let devices = Device::get_by_user(&db, user_id, true).await?;
+
+    let response = json!({
+        "devices": devices
+    });
+    json_response(&response)
+}
+
+async fn approve_device_handle(db: Arc<Db>, oid: String, device_id: ObjectId, body: TargetDeviceBody) -> Result<Json, Rejection> {
+    owned_device(&db, &oid, &device_id, true).await?;
+    owned_device(&db, &oid, &body.device_id, false).await?;
+    Device::approve(&db, &body.device_id).await?;
+
+    let response = json!({
+        "device": "approved"
+    });
+    json_response(&response)
+}
+
+/// Unlinks a device, pending devices may only remove themselves.
+async fn remove_device_handle(db: Arc<Db>, oid: String, device_id: ObjectId, body: TargetDeviceBody) -> Result<Json, Rejection> {
+    let is_self = device_id == body.device_id;
+    owned_device(&db, &oid, &device_id, !is_self).await?;
+    owned_device(&db, &oid, &body.device_id, false).await?;
+    Device::remove(&db, &body.device_id).await?;
+    RegisterBundle::remove_by_device(&db, &body.device_id).await?;
+
+    let response = json!({
+        "device": "removed"
+    });
+    json_response(&response)
+}
@@ -4,25 +4,40 @@ use serde::Deserialize;
 use serde_json::json;
 use warp::{Filter, reject::Rejection, reply::Json};
 use x3dh::handshake::{self};
-use crate::{model::{Db, keys::{RegisterBundle, InitialMessage}, user::User}, server::with_auth, error::Error};
-use super::json_response;
+use crate::{model::{self, Db, keys::{RegisterBundle, InitialMessage}, user::User, chat::Chat, device::Device, objectid_from_str}, server::{with_auth, with_device}, error::Error, LimitsHandle};
+use super::{json_response, not_blocked, owned_device};
+
+#[derive(Deserialize)]
+struct PeerBundleBody {
+    username: String,
+    device_id: ObjectId,
+}
 
 #[derive(Deserialize)]
 struct AddInitialMessageBody {
-    chat_id: ObjectId,
+    to_device: ObjectId,
     message: handshake::InitialMessageBinary,
 }
 
-pub fn keys_paths(db: Arc<Db>) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+#[derive(Deserialize)]
+struct GetInitialMessageBody {
+    from_device: ObjectId,
+}
+
+pub fn keys_paths(db: Arc<Db>, limits: LimitsHandle) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    let auth = with_auth(db.clone());
     let with_db = warp::any()
         .map(move || db.clone());
+    let with_limits = warp::any()
+        .map(move || limits.clone());
     let common = with_db.clone()
-        .and(with_auth());
+        .and(auth);
 
     let add_bundle = warp::path("bundle")
         .and(warp::path::end())
         .and(warp::post())
         .and(common.clone())
+        .and(with_device())
         .and(warp::body::json())
         .and_then(add_bundle_handle);
 
@@ -30,6 +45,7 @@ pub fn keys_paths(db: Arc<Db>) -> impl Filter<Extract = (impl warp::Reply,), Err
         .and(warp::path::end())
         .and(warp::post())
         .and(common.clone())
+        .and(with_limits.clone())
         .and(warp::body::json())
         .and_then(get_bundle_handle);
 
@@ -37,6 +53,7 @@ pub fn keys_paths(db: Arc<Db>) -> impl Filter<Extract = (impl warp::Reply,), Err
         .and(warp::path::end())
         .and(warp::post())
         .and(common.clone())
+        .and(with_device())
         .and(warp::body::json())
         .and_then(add_initial_message_handle);
 
@@ -44,6 +61,7 @@ pub fn keys_paths(db: Arc<Db>) -> impl Filter<Extract = (impl warp::Reply,), Err
         .and(warp::path::end())
         .and(warp::post())
         .and(common.clone())
+        .and(with_device())
         .and(warp::body::json())
         .and_then(get_initial_message_handle);
 
@@ -53,8 +71,9 @@ pub fn keys_paths(db: Arc<Db>) -> impl Filter<Extract = (impl warp::Reply,), Err
         .or(get_initial_message)
 }
 
-async fn add_bundle_handle(db: Arc<Db>, oid: String, bundle: handshake::RegisterBundleBinary) -> Result<Json, Rejection> {
-    let bundle = RegisterBundle::new(&oid, bundle)?;
+async fn add_bundle_handle(db: Arc<Db>, oid: String, device_id: ObjectId, bundle: handshake::RegisterBundleBinary) -> Result<Json, Rejection> {
+    owned_device(&db, &oid, &device_id, false).await?;
+    let bundle = RegisterBundle::new(&oid, device_id, bundle)?;
     RegisterBundle::add_to_db(&db, &bundle).await?;
 
     let response = json!({
@@ -63,16 +82,23 @@ async fn add_bundle_handle(db: Arc<Db>, oid: String, bundle: handshake::Register
     json_response(&response)
 }
 
-async fn get_bundle_handle(db: Arc<Db>, oid: String, username: String) -> Result<Json, Rejection> {
-    let user = User::get_by_username(&db, &username).await?;
+async fn get_bundle_handle(db: Arc<Db>, oid: String, limits: LimitsHandle, body: PeerBundleBody) -> Result<Json, Rejection> {
+    limits.prekey.hit(&oid)
+        .map_err(Error::RateLimited)?;
+    let user = User::get_by_username(&db, &body.username).await?;
     let user_id = user.id().ok_or(Error::InternalError)?;
-    let register_bundle = RegisterBundle::get_by_user(&db, &user_id).await?;
+    not_blocked(&db, &objectid_from_str(&oid)?, &[*user_id]).await?;
+    let device = Device::get_by_id(&db, &body.device_id).await?;
+    if device.user_id() != user_id || !device.is_approved() {
+        return Err(model::Error::NotFound("device").into());
+    }
+    let register_bundle = RegisterBundle::get_by_device(&db, &body.device_id).await?;
     let bundle = register_bundle.bundle.deserialize();
     let peer_bundle = handshake::PeerBundle {
         identity: bundle.identity,
         signature: bundle.signature,
         signed_pre: bundle.signed_pre,
-        one_time_pre: bundle.one_time_pres.first().ok_or(Error::InternalError)?.clone(),
+        one_time_pre: bundle.one_time_pres.first().ok_or(Error::PrekeysExhausted)?.clone(),
     }.serialize();
     RegisterBundle::pop_one_time_key(&db, &register_bundle.id.expect("Record from DB has OID")).await?;
     let response = json!({
@@ -81,8 +107,20 @@ async fn get_bundle_handle(db: Arc<Db>, oid: String, username: String) -> Result
     json_response(&response)
 }
 
-async fn add_initial_message_handle(db: Arc<Db>, oid: String, body: AddInitialMessageBody) -> Result<Json, Rejection> {
-    let message = InitialMessage::new(body.chat_id, body.message);
+/// Starts a session between two devices, allowed between devices of one account
+/// or of users sharing a chat.
+async fn add_initial_message_handle(db: Arc<Db>, oid: String, device_id: ObjectId, body: AddInitialMessageBody) -> Result<Json, Rejection> {
+    let device = owned_device(&db, &oid, &device_id, true).await?;
+    let target = Device::get_by_id(&db, &body.to_device).await?;
+    if !target.is_approved() {
+        return Err(model::Error::NotFound("device").into());
+    }
+    if target.user_id() != device.user_id() {
+        not_blocked(&db, device.user_id(), &[*target.user_id()]).await?;
+        Chat::get_by_users(&db, &oid, target.user_id()).await
+            .map_err(|_| Error::Forbidden("no chat with device owner"))?;
+    }
+    let message = InitialMessage::new(device_id, body.to_device, body.message);
     InitialMessage::add_to_db(&db, &message).await?;
     let response = json!({
         "message": "ok"
@@ -90,8 +128,9 @@ async fn add_initial_message_handle(db: Arc<Db>, oid: String, body: AddInitialMe
     json_response(&response)
 }
 
-async fn get_initial_message_handle(db: Arc<Db>, oid: String, chat_id: ObjectId) -> Result<Json, Rejection> {
-    let message = InitialMessage::get_by_chat(&db, &chat_id).await?;
+async fn get_initial_message_handle(db: Arc<Db>, oid: String, device_id: ObjectId, body: GetInitialMessageBody) -> Result<Json, Rejection> {
+    owned_device(&db, &oid, &device_id, true).await?;
+    let message = InitialMessage::get_for(&db, &device_id, &body.from_device).await?;
     let message = message.map(|m| m.message);
     let response = json!({
         "message": message
@@ -3,22 +3,41 @@ use bson::oid::ObjectId;
 use serde::Deserialize;
 use serde_json::json;
 use warp::{Filter, reject::Rejection, reply::Json};
-use crate::{model::{Db, message::Message, objectid_from_str}, server::with_auth};
+use crate::{model::{Db, message::{Message, Payload, Cursor, DEFAULT_PAGE_SIZE}, objectid_from_str}, server::{with_auth, with_device}, ws, ClientsHandle};
 
-use super::json_response;
+use super::{json_response, member_chat, not_blocked, owned_device, own_message};
+
+#[derive(Deserialize)]
+struct GetMessagesBody {
+    chat_id: ObjectId,
+    before: Option<Cursor>,
+    after: Option<Cursor>,
+    limit: Option<i64>,
+}
 
 #[derive(Deserialize)]
 struct SendMessageBody {
     chat_id: ObjectId,
-    message: Vec<u8>,
+    payloads: Vec<Payload>,
     timestamp: u64,
+    edit_of: Option<ObjectId>,
+    expires_at: Option<u64>,
 }
 
-pub fn message_paths(db: Arc<Db>) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+#[derive(Deserialize)]
+struct DeleteMessageBody {
+    chat_id: ObjectId,
+    message_id: ObjectId,
+}
+
+pub fn message_paths(db: Arc<Db>, clients: ClientsHandle) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    let auth = with_auth(db.clone());
     let with_db = warp::any()
         .map(move || db.clone());
+    let with_clients = warp::any()
+        .map(move || clients.clone());
     let common = with_db.clone()
-        .and(with_auth());
+        .and(auth.clone());
 
     let get_messages = warp::path("messages")
         .and(warp::path::end())
@@ -30,29 +49,68 @@ pub fn message_paths(db: Arc<Db>) -> impl Filter<Extract = (impl warp::Reply,),
     let add_message = warp::path("message")
         .and(warp::path::end())
         .and(warp::post())
-        .and(common.clone())
+        .and(with_db.clone())
+        .and(with_clients.clone())
+        .and(auth.clone())
+        .and(with_device())
         .and(warp::body::json())
         .and_then(add_message_handle);
 
+    let delete_message = warp::path!("message" / "delete")
+        .and(warp::post())
+        .and(with_db.clone())
+        .and(with_clients)
+        .and(auth.clone())
+        .and(warp::body::json())
+        .and_then(delete_message_handle);
+
     get_messages
         .or(add_message)
+        .or(delete_message)
 }
 
-async fn get_messages_handle(db: Arc<Db>, oid: String, chat_id: ObjectId) -> Result<Json, Rejection> {
-    let messages = Message::get_messages_from_chat(&db, chat_id).await?;
+async fn get_messages_handle(db: Arc<Db>, oid: String, body: GetMessagesBody) -> Result<Json, Rejection> {
+    member_chat(&db, &oid, &body.chat_id).await?;
+    let limit = body.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    let page = Message::get_page(&db, body.chat_id, body.before, body.after, limit).await?;
 
     let response = json!({
-        "messages": messages 
+        "messages": page.messages,
+        "has_more": page.has_more,
     });
     json_response(&response)
 }
 
-async fn add_message_handle(db: Arc<Db>, oid: String, body: SendMessageBody) -> Result<Json, Rejection> {
-    let id = objectid_from_str(&oid).unwrap();
-    let new_message = Message::new(body.chat_id, id, body.message, body.timestamp);
-    Message::add_to_db(&db, &new_message).await?;
+async fn add_message_handle(db: Arc<Db>, clients: ClientsHandle, oid: String, device_id: ObjectId, body: SendMessageBody) -> Result<Json, Rejection> {
+    let chat = member_chat(&db, &oid, &body.chat_id).await?;
+    owned_device(&db, &oid, &device_id, true).await?;
+    let id = objectid_from_str(&oid)?;
+    not_blocked(&db, &id, chat.members()).await?;
+    // Edits disappear together with the original message.
+    let expires_at = match &body.edit_of {
+        Some(original) => own_message(&db, &id, &body.chat_id, original).await?.expires_at(),
+        None => body.expires_at,
+    };
+    let mut new_message = Message::new(body.chat_id, id, device_id, body.payloads, body.timestamp)
+        .editing(body.edit_of)
+        .expiring(expires_at);
+    Message::add_to_db(&db, &mut new_message).await?;
+    ws::deliver(&clients, &chat, &new_message).await;
     let response = json!({
         "send message": "ok"
     });
     json_response(&response)
 }
+
+/// Deletes the message for everyone, its edits included.
+async fn delete_message_handle(db: Arc<Db>, clients: ClientsHandle, oid: String, body: DeleteMessageBody) -> Result<Json, Rejection> {
+    let chat = member_chat(&db, &oid, &body.chat_id).await?;
+    let id = objectid_from_str(&oid)?;
+    own_message(&db, &id, &body.chat_id, &body.message_id).await?;
+    Message::delete(&db, &body.message_id).await?;
+    ws::deleted(&clients, &chat, &body.message_id).await;
+    let response = json!({
+        "delete message": "ok"
+    });
+    json_response(&response)
+}
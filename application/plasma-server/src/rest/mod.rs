@@ -2,18 +2,25 @@ mod user;
 mod chat;
 mod message;
 mod keys;
+mod device;
+mod contact;
+mod block;
 
 use std::sync::Arc;
+use bson::oid::ObjectId;
 use serde::Serialize;
 use serde_json::json;
 use warp::{Filter, reply::{Reply, Json}, reject::Rejection};
-use crate::model::Db;
+use crate::{model::{self, Db, block::Block, chat::Chat, device::Device, message::Message, objectid_from_str}, error::Error, ClientsHandle, LimitsHandle, MailerHandle};
 
-pub fn rest_routes(db: Arc<Db>) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
-    user::account_paths(db.clone())
+pub fn rest_routes(db: Arc<Db>, clients: ClientsHandle, limits: LimitsHandle, mailer: MailerHandle) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    user::account_paths(db.clone(), clients.clone(), limits.clone(), mailer)
         .or(chat::chat_paths(db.clone()))
-        .or(message::message_paths(db.clone()))
-        .or(keys::keys_paths(db.clone()))
+        .or(message::message_paths(db.clone(), clients))
+        .or(keys::keys_paths(db.clone(), limits.clone()))
+        .or(device::device_paths(db.clone(), limits.clone()))
+        .or(contact::contact_paths(db.clone()))
+        .or(block::block_paths(db.clone(), limits.clone()))
 
 }
 
@@ -24,3 +31,44 @@ fn json_response<T: Serialize>(data: &T) -> Result<Json, Rejection> {
     Ok(warp::reply::json(&response))
 }
 
+async fn member_chat(db: &Db, oid: &str, chat_id: &ObjectId) -> Result<Chat, Rejection> {
+    let user_id = objectid_from_str(oid)?;
+    let chat = Chat::get_by_id(db, &chat_id.to_hex()).await?;
+    if !chat.is_member(&user_id) {
+        return Err(Error::Forbidden("not a chat member").into());
+    }
+    Ok(chat)
+}
+
+/// Rejects contact between `user_id` and any of `others` when one blocked the other.
+pub async fn not_blocked(db: &Db, user_id: &ObjectId, others: &[ObjectId]) -> Result<(), Rejection> {
+    if Block::between(db, user_id, others).await? {
+        return Err(Error::Blocked("one of you blocked the other").into());
+    }
+    Ok(())
+}
+
+/// Device of the requesting user, unlinked devices are rejected when `approved` is set.
+pub async fn owned_device(db: &Db, oid: &str, device_id: &ObjectId, approved: bool) -> Result<Device, Rejection> {
+    let user_id = objectid_from_str(oid)?;
+    let device = Device::get_by_id(db, device_id).await?;
+    if device.user_id() != &user_id {
+        return Err(model::Error::NotFound("device").into());
+    }
+    if approved && !device.is_approved() {
+        return Err(Error::DeviceNotApproved.into());
+    }
+    Ok(device)
+}
+
+/// Message of the requesting user in `chat_id` that can still be edited or deleted.
+pub async fn own_message(db: &Db, user_id: &ObjectId, chat_id: &ObjectId, message_id: &ObjectId) -> Result<Message, Rejection> {
+    let message = Message::get_by_id(db, message_id).await?;
+    if message.chat_id() != chat_id || message.is_deleted() {
+        return Err(model::Error::NotFound("message").into());
+    }
+    if message.sender_id() != user_id || message.edit_of().is_some() {
+        return Err(Error::Forbidden("only the sender can change a message").into());
+    }
+    Ok(message)
+}
@@ -1,14 +1,18 @@
 use bson::oid::ObjectId;
 use warp::{Filter, Rejection, reply::Json};
 use std::sync::Arc;
-use crate::error::AuthorizationError;
+use crate::error::{AuthorizationError, ValidationError, Error};
 use crate::model;
-use crate::model::{Db, user::User};
+use crate::model::{Db, user::User, objectid_from_str};
+use crate::model::{block::Block, chat::Chat, contact::Contact, device::Device, keys::{InitialMessage, RegisterBundle}, message::Message, report::Report, mail_token::{MailToken, Purpose}};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use crate::security::{hash::hashed_password, token};
+use crate::security::{hash::hashed_password, rate_limit::login_key, token};
 use crate::rest::json_response;
-use crate::server::with_auth;
+use crate::server::{client_ip, with_auth, with_ip_limit};
+use crate::mail::Mail;
+use crate::{ws, ClientsHandle, LimitsHandle, MailerHandle};
+use crate::validation::{validate_username, validate_email, validate_password, validate_search_query};
 
 #[derive(Deserialize, Debug)]
 struct RegisterBody {
@@ -28,6 +32,97 @@ pub struct LoginResponse {
     pub jwtoken: String,
 }
 
+const DELETION_CHATS: &str = "ACCOUNT_DELETION_CHATS";
+
+/// What deleting an account does to its chats, `remove` drops them with the
+/// whole history, by default the user just leaves them.
+enum ChatPolicy {
+    Leave,
+    Remove,
+}
+
+impl ChatPolicy {
+    fn from_env() -> Self {
+        match dotenv::var(DELETION_CHATS).as_deref() {
+            Ok("remove") => ChatPolicy::Remove,
+            _ => ChatPolicy::Leave,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct VerifyBody {
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct ChangePasswordBody {
+    password: String,
+    new_password: String,
+}
+
+#[derive(Deserialize)]
+struct ForgotPasswordBody {
+    email: String,
+}
+
+#[derive(Deserialize)]
+struct ResetPasswordBody {
+    token: String,
+    password: String,
+}
+
+#[derive(Deserialize)]
+struct ConfirmBody {
+    password: String,
+}
+
+#[derive(Serialize)]
+struct Profile {
+    id: Option<ObjectId>,
+    email: String,
+    username: String,
+    hide_presence: bool,
+    hide_from_search: bool,
+}
+
+/// Everything the server keeps about an account, message content stays
+/// encrypted for the receiving devices.
+#[derive(Serialize)]
+struct AccountExport {
+    profile: Profile,
+    devices: Vec<Device>,
+    bundles: Vec<RegisterBundle>,
+    initial_messages: Vec<InitialMessage>,
+    chats: Vec<Chat>,
+    messages: Vec<Message>,
+    contacts: Vec<Contact>,
+    blocks: Vec<Block>,
+    reports: Vec<Report>,
+}
+
+#[derive(Deserialize)]
+struct PresenceBody {
+    hidden: bool,
+}
+
+#[derive(Deserialize)]
+struct DiscoveryBody {
+    hidden: bool,
+}
+
+#[derive(Deserialize)]
+struct SearchBody {
+    query: String,
+}
+
+/// Search results carry no email, only what starting a chat needs.
+#[derive(Serialize)]
+struct FoundUser {
+    id: Option<ObjectId>,
+    username: String,
+}
+
 #[derive(Deserialize)]
 struct FindBody {
     id: Option<ObjectId>,
@@ -52,21 +147,52 @@ impl From<User> for UserResponse {
     }
 }
 
-pub fn account_paths(db: Arc<Db>) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+pub fn account_paths(db: Arc<Db>, clients: ClientsHandle, limits: LimitsHandle, mailer: MailerHandle) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    let auth = with_auth(db.clone());
     let with_db = warp::any()
         .map(move || db.clone());
+    let with_clients = warp::any()
+        .map(move || clients.clone());
+    let with_mailer = warp::any()
+        .map(move || mailer.clone());
+    let with_limits = {
+        let limits = limits.clone();
+        warp::any()
+            .map(move || limits.clone())
+    };
 
     let register = warp::path("register")
         .and(warp::path::end())
         .and(warp::post())
+        .and(with_ip_limit(limits.clone(), |l| &l.auth))
         .and(with_db.clone())
+        .and(with_mailer.clone())
         .and(warp::body::json())
         .and_then(register_handle);
 
+    let verify = warp::path("verify")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(with_ip_limit(limits.clone(), |l| &l.auth))
+        .and(with_db.clone())
+        .and(warp::body::json())
+        .and_then(verify_handle);
+
+    let resend_verification = warp::path!("verify" / "resend")
+        .and(warp::post())
+        .and(with_ip_limit(limits.clone(), |l| &l.auth))
+        .and(with_db.clone())
+        .and(with_mailer.clone())
+        .and(auth.clone())
+        .and_then(resend_verification_handle);
+
     let login = warp::path("login")
         .and(warp::path::end())
         .and(warp::post())
+        .and(with_ip_limit(limits.clone(), |l| &l.auth))
         .and(with_db.clone())
+        .and(with_limits.clone())
+        .and(client_ip(limits.clone()))
         .and(warp::body::json())
         .and_then(login_handle);
 
@@ -74,38 +200,136 @@ pub fn account_paths(db: Arc<Db>) -> impl Filter<Extract = (impl warp::Reply,),
         .and(warp::path::end())
         .and(warp::get())
         .and(with_db.clone())
-        .and(with_auth())
+        .and(auth.clone())
         .and_then(dashboard_handle);
 
     let find = warp::path("user")
         .and(warp::path::end())
         .and(warp::post())
+        .and(with_ip_limit(limits.clone(), |l| &l.lookup))
         .and(with_db.clone())
         .and(warp::body::json())
         .and_then(find_handle);
 
+    let search = warp::path!("users" / "search")
+        .and(warp::post())
+        .and(with_ip_limit(limits.clone(), |l| &l.lookup))
+        .and(with_db.clone())
+        .and(auth.clone())
+        .and(warp::body::json())
+        .and_then(search_handle);
+
+    let discovery = warp::path("discovery")
+        .and(warp::path::end())
+        .and(warp::put())
+        .and(with_db.clone())
+        .and(auth.clone())
+        .and(warp::body::json())
+        .and_then(discovery_handle);
+
+    let change_password = warp::path("password")
+        .and(warp::path::end())
+        .and(warp::put())
+        .and(with_ip_limit(limits.clone(), |l| &l.auth))
+        .and(with_db.clone())
+        .and(with_clients.clone())
+        .and(auth.clone())
+        .and(warp::body::json())
+        .and_then(change_password_handle);
+
+    let forgot_password = warp::path!("password" / "forgot")
+        .and(warp::post())
+        .and(with_ip_limit(limits.clone(), |l| &l.auth))
+        .and(with_db.clone())
+        .and(with_mailer)
+        .and(warp::body::json())
+        .and_then(forgot_password_handle);
+
+    let reset_password = warp::path!("password" / "reset")
+        .and(warp::post())
+        .and(with_ip_limit(limits.clone(), |l| &l.auth))
+        .and(with_db.clone())
+        .and(with_clients.clone())
+        .and(warp::body::json())
+        .and_then(reset_password_handle);
+
+    let export = warp::path!("account" / "export")
+        .and(warp::post())
+        .and(with_ip_limit(limits.clone(), |l| &l.auth))
+        .and(with_db.clone())
+        .and(auth.clone())
+        .and(warp::body::json())
+        .and_then(export_handle);
+
+    let delete = warp::path!("account" / "delete")
+        .and(warp::post())
+        .and(with_ip_limit(limits.clone(), |l| &l.auth))
+        .and(with_db.clone())
+        .and(with_clients.clone())
+        .and(auth.clone())
+        .and(warp::body::json())
+        .and_then(delete_handle);
+
+    let presence = warp::path("presence")
+        .and(warp::path::end())
+        .and(warp::put())
+        .and(with_db.clone())
+        .and(with_clients)
+        .and(auth.clone())
+        .and(warp::body::json())
+        .and_then(presence_handle);
+
     register
+        .or(verify)
+        .or(resend_verification)
         .or(login)
         .or(dashboard)
         .or(find)
+        .or(search)
+        .or(discovery)
+        .or(presence)
+        .or(change_password)
+        .or(forgot_password)
+        .or(reset_password)
+        .or(export)
+        .or(delete)
 }
 
-async fn login_handle(db: Arc<Db>, body: LoginBody) -> Result<Json, Rejection> {
-    let user = User::get_by_email(&db, &body.email).await
-        .map_err(|_| AuthorizationError::InvalidCredentials("email"))?;
+async fn login_handle(db: Arc<Db>, limits: LimitsHandle, ip: String, body: LoginBody) -> Result<Json, Rejection> {
+    let lockout_key = login_key(&ip, &body.email);
+    limits.login.check(&lockout_key)
+        .map_err(Error::RateLimited)?;
+
+    let user = match User::get_by_email(&db, &body.email).await {
+        Ok(user) => user,
+        Err(_) => {
+            limits.login.record_failure(&lockout_key);
+            return Err(AuthorizationError::InvalidCredentials("email").into());
+        }
+    };
 
     if !user.password_matches(&hashed_password(&body.password)) {
+        limits.login.record_failure(&lockout_key);
         return Err(AuthorizationError::InvalidCredentials("password").into());
     }
+    limits.login.record_success(&lockout_key);
     let token = token::create_jwt(&user)?;
 
     json_response(&LoginResponse {jwtoken: token})
 }
 
-async fn register_handle(db: Arc<Db>, body: RegisterBody) -> Result<Json, Rejection> {
-    let is_unique = is_unique_email(&db, &body.email).await?;
-    if !is_unique {
-        return Err(model::Error::NotUnique("email").into());
+/// Creates an unverified account and mails its verification token. A failed
+/// mail doesn't undo the registration, the user can ask for a new token.
+async fn register_handle(db: Arc<Db>, mailer: MailerHandle, body: RegisterBody) -> Result<Json, Rejection> {
+    validate_email(&body.email)?;
+    validate_username(&body.username)?;
+    validate_password(&body.password, &body.username)?;
+
+    if !is_unique_email(&db, &body.email).await? {
+        return Err(ValidationError::EmailTaken.into());
+    }
+    if !is_unique_username(&db, &body.username).await? {
+        return Err(ValidationError::UsernameTaken.into());
     }
 
     let new_user = User::new(
@@ -113,7 +337,37 @@ async fn register_handle(db: Arc<Db>, body: RegisterBody) -> Result<Json, Reject
         &body.username,
         &hashed_password(&body.password)
     );
-    User::add_to_db(&db, &new_user).await?;
+    let user_id = User::add_to_db(&db, &new_user).await?;
+    if let Err(err) = send_verification(&db, &mailer, &user_id, &new_user).await {
+        error!("Failed mailing verification to {}: {:?}", user_id, err);
+    }
+
+    let content = json!({
+        "message": "success",
+    });
+    json_response(&content)
+}
+
+async fn send_verification(db: &Db, mailer: &MailerHandle, user_id: &ObjectId, user: &User) -> Result<(), Rejection> {
+    let (verification, token) = MailToken::new(*user_id, Purpose::Verification);
+    MailToken::add_to_db(db, &verification).await?;
+    let mail = Mail {
+        to: user.email.clone(),
+        subject: String::from("Verify your Plasma email address"),
+        body: format!(
+            "Welcome to Plasma, {}. Verify your email address with this token to start chatting:\n\n{}\n\nIt expires in {} hours.",
+            user.username(), token, Purpose::Verification.lifetime().as_secs() / 60 / 60,
+        ),
+    };
+    mailer.send(&mail).await
+        .map_err(Error::from)?;
+    Ok(())
+}
+
+async fn verify_handle(db: Arc<Db>, body: VerifyBody) -> Result<Json, Rejection> {
+    let verification = MailToken::take(&db, body.token.trim(), Purpose::Verification).await?;
+    User::set_verified(&db, verification.user_id()).await?;
+    MailToken::remove_by_user(&db, verification.user_id(), Purpose::Verification).await?;
 
     let content = json!({
         "message": "success",
@@ -121,6 +375,21 @@ async fn register_handle(db: Arc<Db>, body: RegisterBody) -> Result<Json, Reject
     json_response(&content)
 }
 
+/// Mails a new verification token, earlier ones keep working until they expire.
+async fn resend_verification_handle(db: Arc<Db>, mailer: MailerHandle, oid: String) -> Result<Json, Rejection> {
+    let user = User::get_by_id(&db, &oid).await?;
+    let sent = !user.is_verified();
+    if sent {
+        let user_id = user.id().ok_or(Error::InternalError)?;
+        send_verification(&db, &mailer, user_id, &user).await?;
+    }
+
+    let content = json!({
+        "sent": sent,
+    });
+    json_response(&content)
+}
+
 async fn is_unique_email(db: &Db, email: &String) -> Result<bool, model::Error> {
     match User::get_by_email(db, &email).await {
         Ok(_) => Ok(false),
@@ -133,6 +402,175 @@ async fn is_unique_email(db: &Db, email: &String) -> Result<bool, model::Error>
     }
 }
 
+async fn is_unique_username(db: &Db, username: &String) -> Result<bool, model::Error> {
+    match User::get_by_username(db, username).await {
+        Ok(_) => Ok(false),
+        Err(err) => {
+            match err {
+                model::Error::NotFound("user") => Ok(true),
+                _ => Err(err)
+            }
+        }
+    }
+}
+
+/// Account of the requesting user, provided the password is right.
+async fn confirmed_user(db: &Db, oid: &String, password: &String) -> Result<User, Rejection> {
+    let user = User::get_by_id(db, oid).await?;
+    if !user.password_matches(&hashed_password(password)) {
+        return Err(AuthorizationError::InvalidCredentials("password").into());
+    }
+    Ok(user)
+}
+
+/// Changes the password and signs out every session, the returned token
+/// replaces the one used for this request.
+async fn change_password_handle(db: Arc<Db>, clients: ClientsHandle, oid: String, body: ChangePasswordBody) -> Result<Json, Rejection> {
+    let user = confirmed_user(&db, &oid, &body.password).await?;
+    validate_password(&body.new_password, user.username())?;
+    let user_id = objectid_from_str(&oid)?;
+    let user = User::set_password(&db, &user_id, &hashed_password(&body.new_password)).await?;
+    ws::close_user(&clients, &user_id).await;
+    let token = token::create_jwt(&user)?;
+
+    json_response(&LoginResponse {jwtoken: token})
+}
+
+/// Mails a reset token when the address belongs to an account. The lookup and
+/// mail happen after responding and failures are only logged, so neither the
+/// response nor its timing tells which addresses are registered.
+async fn forgot_password_handle(db: Arc<Db>, mailer: MailerHandle, body: ForgotPasswordBody) -> Result<Json, Rejection> {
+    tokio::spawn(async move {
+        if let Err(err) = send_reset(&db, &mailer, &body.email).await {
+            error!("Failed mailing password reset: {:?}", err);
+        }
+    });
+
+    let content = json!({
+        "message": "success",
+    });
+    json_response(&content)
+}
+
+async fn send_reset(db: &Db, mailer: &MailerHandle, email: &String) -> Result<(), Rejection> {
+    let user = match User::get_by_email(db, email).await {
+        Ok(user) => user,
+        Err(model::Error::NoUserWithSuchEmail) => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    let user_id = user.id().ok_or(Error::InternalError)?;
+    let (reset, token) = MailToken::new(*user_id, Purpose::PasswordReset);
+    MailToken::add_to_db(db, &reset).await?;
+    let mail = Mail {
+        to: user.email.clone(),
+        subject: String::from("Reset your Plasma password"),
+        body: format!(
+            "Someone asked to reset the password of {}. If it was you, use this token to choose a new one:\n\n{}\n\nIt expires in {} minutes. Otherwise just ignore this mail.",
+            user.username(), token, Purpose::PasswordReset.lifetime().as_secs() / 60,
+        ),
+    };
+    mailer.send(&mail).await
+        .map_err(Error::from)?;
+    Ok(())
+}
+
+/// Sets a new password with a mailed token, signing out every session. Getting
+/// the token proves the address too, so the account counts as verified. A
+/// rejected password leaves the token usable for another try, an accepted one
+/// consumes it in a single delete so concurrent resets can't both go through.
+async fn reset_password_handle(db: Arc<Db>, clients: ClientsHandle, body: ResetPasswordBody) -> Result<Json, Rejection> {
+    let token = body.token.trim();
+    let pending = MailToken::get(&db, token, Purpose::PasswordReset).await?;
+    let user = User::get_by_oid(&db, pending.user_id()).await?;
+    validate_password(&body.password, user.username())?;
+    let reset = MailToken::take(&db, token, Purpose::PasswordReset).await?;
+    let user_id = *reset.user_id();
+    User::set_password(&db, &user_id, &hashed_password(&body.password)).await?;
+    MailToken::remove_by_user(&db, &user_id, Purpose::PasswordReset).await?;
+    User::set_verified(&db, &user_id).await?;
+    ws::close_user(&clients, &user_id).await;
+
+    let content = json!({
+        "message": "success",
+    });
+    json_response(&content)
+}
+
+async fn own_device_ids(db: &Db, user_id: &ObjectId) -> Result<Vec<ObjectId>, model::Error> {
+    let ids = Device::get_by_user(db, user_id, false).await?
+        .iter()
+        .filter_map(|device| device.id().copied())
+        .collect();
+    Ok(ids)
+}
+
+async fn export_handle(db: Arc<Db>, oid: String, body: ConfirmBody) -> Result<Json, Rejection> {
+    let user = confirmed_user(&db, &oid, &body.password).await?;
+    let user_id = objectid_from_str(&oid)?;
+    let devices = Device::get_by_user(&db, &user_id, false).await?;
+    let device_ids: Vec<ObjectId> = devices.iter()
+        .filter_map(|device| device.id().copied())
+        .collect();
+
+    let export = AccountExport {
+        profile: Profile {
+            hide_presence: user.hides_presence(),
+            hide_from_search: user.hides_from_search(),
+            id: user.id,
+            email: user.email,
+            username: user.username,
+        },
+        devices,
+        bundles: RegisterBundle::get_by_user(&db, &user_id).await?,
+        initial_messages: InitialMessage::get_by_devices(&db, &device_ids).await?,
+        chats: Chat::get_users_chats(db.clone(), &oid).await?,
+        messages: Message::get_by_sender(&db, &user_id).await?,
+        contacts: Contact::get_by_owner(&db, &user_id).await?,
+        blocks: Block::get_by_blocker(&db, &user_id).await?,
+        reports: Report::get_by_reporter(&db, &user_id).await?,
+    };
+
+    let content = json!({
+        "account": export
+    });
+    json_response(&content)
+}
+
+/// Deletes the account with its keys, devices, sent messages, contacts and
+/// blocks. Reports about the user are kept for moderators. The user record
+/// goes last so a failed deletion can simply be retried.
+async fn delete_handle(db: Arc<Db>, clients: ClientsHandle, oid: String, body: ConfirmBody) -> Result<Json, Rejection> {
+    confirmed_user(&db, &oid, &body.password).await?;
+    let user_id = objectid_from_str(&oid)?;
+    ws::close_user(&clients, &user_id).await;
+
+    match ChatPolicy::from_env() {
+        ChatPolicy::Leave => Chat::leave_all(&db, &user_id).await?,
+        ChatPolicy::Remove => {
+            let chats: Vec<ObjectId> = Chat::get_users_chats(db.clone(), &oid).await?
+                .iter()
+                .filter_map(|chat| *chat.id())
+                .collect();
+            Message::delete_by_chats(&db, &chats).await?;
+            Chat::delete_many(&db, &chats).await?;
+        },
+    }
+    Message::delete_by_sender(&db, &user_id).await?;
+    let devices = own_device_ids(&db, &user_id).await?;
+    InitialMessage::remove_by_devices(&db, &devices).await?;
+    RegisterBundle::remove_by_user(&db, &user_id).await?;
+    Device::remove_by_user(&db, &user_id).await?;
+    Contact::remove_all(&db, &user_id).await?;
+    Block::remove_all(&db, &user_id).await?;
+    MailToken::remove_all(&db, &user_id).await?;
+    User::delete(&db, &user_id).await?;
+
+    let content = json!({
+        "message": "success",
+    });
+    json_response(&content)
+}
+
 async fn dashboard_handle(db: Arc<Db>, id: String) -> Result<Json, Rejection> {
     let user = User::get_by_id(&db, &id).await?;
 
@@ -142,6 +580,44 @@ async fn dashboard_handle(db: Arc<Db>, id: String) -> Result<Json, Rejection> {
     json_response(&content)
 }
 
+/// Opting out hides both online state and last seen from every contact.
+async fn presence_handle(db: Arc<Db>, clients: ClientsHandle, oid: String, body: PresenceBody) -> Result<Json, Rejection> {
+    let id = objectid_from_str(&oid)?;
+    User::set_presence_hidden(&db, &id, body.hidden).await?;
+    clients.write().await.set_hidden(id, body.hidden);
+    ws::broadcast_presence(&db, &clients, &id).await;
+
+    let content = json!({
+        "message": "success",
+    });
+    json_response(&content)
+}
+
+async fn search_handle(db: Arc<Db>, oid: String, body: SearchBody) -> Result<Json, Rejection> {
+    let query = body.query.trim();
+    validate_search_query(query)?;
+    let id = objectid_from_str(&oid)?;
+    let users: Vec<FoundUser> = User::search(&db, query, &id).await?
+        .into_iter()
+        .map(|user| FoundUser { id: user.id, username: user.username })
+        .collect();
+
+    let content = json!({
+        "users": users
+    });
+    json_response(&content)
+}
+
+async fn discovery_handle(db: Arc<Db>, oid: String, body: DiscoveryBody) -> Result<Json, Rejection> {
+    let id = objectid_from_str(&oid)?;
+    User::set_search_hidden(&db, &id, body.hidden).await?;
+
+    let content = json!({
+        "message": "success",
+    });
+    json_response(&content)
+}
+
 async fn find_handle(db: Arc<Db>, body: FindBody) -> Result<Json, Rejection> {
     let user = {
         if body.id.is_some() {
@@ -3,3 +3,8 @@ use sha3::{Digest, Sha3_256};
 pub fn hashed_password(password: &String) -> String {
     format!("{:x}", Sha3_256::digest(password.as_bytes()))
 }
+
+/// Mailed tokens are stored hashed, so a leaked database can't reset passwords.
+pub fn hashed_token(token: &str) -> String {
+    format!("{:x}", Sha3_256::digest(token.as_bytes()))
+}
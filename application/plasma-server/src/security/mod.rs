@@ -1,2 +1,3 @@
 pub mod hash;
 pub mod token;
+pub mod rate_limit;
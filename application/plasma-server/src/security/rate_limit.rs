@@ -0,0 +1,250 @@
+use std::{collections::HashMap, sync::Mutex, time::{Duration, Instant}};
+use dotenv;
+
+const WINDOW_SECS: &str = "RATE_LIMIT_WINDOW_SECS";
+const AUTH_PER_WINDOW: &str = "RATE_LIMIT_AUTH";
+const LOOKUP_PER_WINDOW: &str = "RATE_LIMIT_LOOKUP";
+const PREKEY_PER_HOUR: &str = "RATE_LIMIT_PREKEY_PER_HOUR";
+const LOCKOUT_THRESHOLD: &str = "LOGIN_LOCKOUT_THRESHOLD";
+const LOCKOUT_BASE_SECS: &str = "LOGIN_LOCKOUT_BASE_SECS";
+const LOCKOUT_MAX_SECS: &str = "LOGIN_LOCKOUT_MAX_SECS";
+const TRUST_PROXY: &str = "TRUST_PROXY";
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    dotenv::var(key)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+struct Window {
+    start: Instant,
+    count: u32,
+}
+
+/// Fixed window counter allowing `max` hits per key within every `window`.
+pub struct RateLimiter {
+    max: u32,
+    window: Duration,
+    windows: Mutex<HashMap<String, Window>>,
+}
+
+impl RateLimiter {
+    pub fn new(max: u32, window: Duration) -> Self {
+        RateLimiter {
+            max,
+            window,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts a hit for `key`, on rejection returns time until the window resets.
+    pub fn hit(&self, key: &str) -> Result<(), Duration> {
+        self.hit_at(key, Instant::now())
+    }
+
+    fn hit_at(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        let mut windows = self.windows.lock().expect("Rate limiter lock poisoned");
+        let window = windows.entry(key.to_owned())
+            .or_insert(Window { start: now, count: 0 });
+        if now.duration_since(window.start) >= self.window {
+            window.start = now;
+            window.count = 0;
+        }
+        if window.count >= self.max {
+            return Err(self.window - now.duration_since(window.start));
+        }
+        window.count += 1;
+        Ok(())
+    }
+
+    fn prune(&self, now: Instant) {
+        self.windows.lock().expect("Rate limiter lock poisoned")
+            .retain(|_, window| now.duration_since(window.start) < self.window);
+    }
+}
+
+struct Attempts {
+    failures: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// Tracks failed logins per client and account, locking the pair out for a
+/// period that doubles with every failure past the threshold.
+pub struct LoginGuard {
+    threshold: u32,
+    base: Duration,
+    max: Duration,
+    attempts: Mutex<HashMap<String, Attempts>>,
+}
+
+impl LoginGuard {
+    pub fn new(threshold: u32, base: Duration, max: Duration) -> Self {
+        LoginGuard {
+            threshold,
+            base,
+            max,
+            attempts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// On lockout returns time remaining until next attempt is allowed.
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        self.check_at(key, Instant::now())
+    }
+
+    pub fn record_failure(&self, key: &str) {
+        self.record_failure_at(key, Instant::now())
+    }
+
+    pub fn record_success(&self, key: &str) {
+        self.attempts.lock().expect("Login guard lock poisoned")
+            .remove(key);
+    }
+
+    fn check_at(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        let attempts = self.attempts.lock().expect("Login guard lock poisoned");
+        match attempts.get(key).and_then(|a| a.locked_until) {
+            Some(until) if until > now => Err(until - now),
+            _ => Ok(()),
+        }
+    }
+
+    fn record_failure_at(&self, key: &str, now: Instant) {
+        let mut attempts = self.attempts.lock().expect("Login guard lock poisoned");
+        let entry = attempts.entry(key.to_owned())
+            .or_insert(Attempts { failures: 0, last_failure: now, locked_until: None });
+        entry.failures += 1;
+        entry.last_failure = now;
+        if entry.failures >= self.threshold {
+            let exponent = (entry.failures - self.threshold).min(16);
+            let lockout = self.base
+                .saturating_mul(1 << exponent)
+                .min(self.max);
+            entry.locked_until = Some(now + lockout);
+        }
+    }
+
+    fn prune(&self, now: Instant) {
+        self.attempts.lock().expect("Login guard lock poisoned")
+            .retain(|_, a| now.duration_since(a.last_failure) < self.max);
+    }
+}
+
+/// Lockout key of a login attempt. Keyed by client too, so guessing passwords
+/// from one address can't lock the owner out of their account elsewhere.
+/// Emails match regardless of case, so does the lockout.
+pub fn login_key(ip: &str, email: &str) -> String {
+    format!("{} {}", ip, email.to_lowercase())
+}
+
+/// All limits applied by the rest routes, configured from env.
+pub struct Limits {
+    /// Login and registration attempts per IP.
+    pub auth: RateLimiter,
+    /// User lookups per IP.
+    pub lookup: RateLimiter,
+    /// One-time prekey claims per requesting user.
+    pub prekey: RateLimiter,
+    /// Progressive lockout of accounts with failed logins, per client.
+    pub login: LoginGuard,
+    /// Take client IP from `X-Forwarded-For` when running behind a proxy.
+    pub trust_proxy: bool,
+}
+
+impl Limits {
+    pub fn from_env() -> Self {
+        let window = Duration::from_secs(env_or(WINDOW_SECS, 60));
+        Limits {
+            auth: RateLimiter::new(env_or(AUTH_PER_WINDOW, 10), window),
+            lookup: RateLimiter::new(env_or(LOOKUP_PER_WINDOW, 60), window),
+            prekey: RateLimiter::new(env_or(PREKEY_PER_HOUR, 20), HOUR),
+            login: LoginGuard::new(
+                env_or(LOCKOUT_THRESHOLD, 5),
+                Duration::from_secs(env_or(LOCKOUT_BASE_SECS, 30)),
+                Duration::from_secs(env_or(LOCKOUT_MAX_SECS, 60 * 60)),
+            ),
+            trust_proxy: env_or(TRUST_PROXY, false),
+        }
+    }
+
+    /// Drops expired entries so memory use stays bounded.
+    pub fn prune(&self) {
+        let now = Instant::now();
+        self.auth.prune(now);
+        self.lookup.prune(now);
+        self.prekey.prune(now);
+        self.login.prune(now);
+    }
+}
+
+#[cfg(test)]
+mod rate_limit_test {
+    use std::time::{Duration, Instant};
+    use super::{RateLimiter, LoginGuard, login_key};
+
+    #[test]
+    fn limiter_rejects_over_limit() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(10));
+        let now = Instant::now();
+
+        assert!(limiter.hit_at("ip", now).is_ok());
+        assert!(limiter.hit_at("ip", now).is_ok());
+        assert_eq!(limiter.hit_at("ip", now), Err(Duration::from_secs(10)));
+        assert!(limiter.hit_at("other", now).is_ok());
+    }
+
+    #[test]
+    fn limiter_resets_after_window() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(10));
+        let now = Instant::now();
+
+        assert!(limiter.hit_at("ip", now).is_ok());
+        assert!(limiter.hit_at("ip", now + Duration::from_secs(5)).is_err());
+        assert!(limiter.hit_at("ip", now + Duration::from_secs(10)).is_ok());
+    }
+
+    #[test]
+    fn login_lockout_grows() {
+        let guard = LoginGuard::new(2, Duration::from_secs(10), Duration::from_secs(25));
+        let now = Instant::now();
+
+        guard.record_failure_at("mail", now);
+        assert!(guard.check_at("mail", now).is_ok());
+
+        guard.record_failure_at("mail", now);
+        assert_eq!(guard.check_at("mail", now), Err(Duration::from_secs(10)));
+        assert!(guard.check_at("mail", now + Duration::from_secs(10)).is_ok());
+
+        guard.record_failure_at("mail", now);
+        assert_eq!(guard.check_at("mail", now), Err(Duration::from_secs(20)));
+
+        guard.record_failure_at("mail", now);
+        assert_eq!(guard.check_at("mail", now), Err(Duration::from_secs(25)));
+    }
+
+    #[test]
+    fn login_lockout_stays_with_client() {
+        let guard = LoginGuard::new(1, Duration::from_secs(10), Duration::from_secs(60));
+        let now = Instant::now();
+
+        guard.record_failure_at(&login_key("10.0.0.1", "Alice@x.org"), now);
+        assert!(guard.check_at(&login_key("10.0.0.1", "alice@x.org"), now).is_err());
+        assert!(guard.check_at(&login_key("10.0.0.2", "alice@x.org"), now).is_ok());
+    }
+
+    #[test]
+    fn login_success_clears_lockout() {
+        let guard = LoginGuard::new(1, Duration::from_secs(10), Duration::from_secs(60));
+        let now = Instant::now();
+
+        guard.record_failure_at("mail", now);
+        assert!(guard.check_at("mail", now).is_err());
+
+        guard.record_success("mail");
+        assert!(guard.check_at("mail", now).is_ok());
+    }
+}
@@ -14,6 +14,9 @@ const BEARER: &str = "Bearer ";
 pub struct Claims {
     sub: String,
     exp: usize,
+    /// Token version of the user when issued, tokens from before it was bumped are revoked.
+    #[serde(default)]
+    ver: u32,
 }
 
 impl Claims {
@@ -25,7 +28,8 @@ impl Claims {
             exp: Utc::now()
                 .checked_add_signed(Duration::minutes(TOKEN_DURATION))
                 .ok_or(Error::InvalidClaimData("token expiration date exceeded"))?
-                .timestamp() as usize
+                .timestamp() as usize,
+            ver: user.token_version(),
         };
 
         Ok(claims)
@@ -34,6 +38,10 @@ impl Claims {
     pub fn sub(self: &Self) -> String {
         self.sub.clone()
     }
+
+    pub fn version(&self) -> u32 {
+        self.ver
+    }
 }
 
 pub fn create_jwt(user: &User) -> Result<String, Error> {
@@ -0,0 +1,124 @@
+use warp::hyper::StatusCode;
+
+/// Stable, machine-readable error codes returned in the `error` field of every
+/// failed response. Codes are part of the API and must not be renamed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    BadRequest,
+    MissingField,
+    InvalidId,
+    InvalidUsername,
+    InvalidEmail,
+    WeakPassword,
+    Unauthorized,
+    InvalidCredentials,
+    Forbidden,
+    NotFound,
+    UserNotFound,
+    ChatNotFound,
+    BundleNotFound,
+    DeviceNotFound,
+    DeviceNotApproved,
+    Blocked,
+    EmailNotVerified,
+    EmailTaken,
+    UsernameTaken,
+    AlreadyExists,
+    PrekeysExhausted,
+    RateLimited,
+    InvalidToken,
+    MailFailed,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => "bad_request",
+            ErrorCode::MissingField => "missing_field",
+            ErrorCode::InvalidId => "invalid_id",
+            ErrorCode::InvalidUsername => "invalid_username",
+            ErrorCode::InvalidEmail => "invalid_email",
+            ErrorCode::WeakPassword => "weak_password",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::InvalidCredentials => "invalid_credentials",
+            ErrorCode::Forbidden => "forbidden",
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::UserNotFound => "user_not_found",
+            ErrorCode::ChatNotFound => "chat_not_found",
+            ErrorCode::BundleNotFound => "bundle_not_found",
+            ErrorCode::DeviceNotFound => "device_not_found",
+            ErrorCode::DeviceNotApproved => "device_not_approved",
+            ErrorCode::Blocked => "blocked",
+            ErrorCode::EmailNotVerified => "email_not_verified",
+            ErrorCode::EmailTaken => "email_taken",
+            ErrorCode::UsernameTaken => "username_taken",
+            ErrorCode::AlreadyExists => "already_exists",
+            ErrorCode::PrekeysExhausted => "prekeys_exhausted",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::InvalidToken => "invalid_token",
+            ErrorCode::MailFailed => "mail_failed",
+            ErrorCode::Internal => "internal_error",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErrorCode::BadRequest
+            | ErrorCode::MissingField
+            | ErrorCode::InvalidId
+            | ErrorCode::InvalidUsername
+            | ErrorCode::InvalidEmail
+            | ErrorCode::WeakPassword
+            | ErrorCode::InvalidToken => StatusCode::BAD_REQUEST,
+            ErrorCode::Unauthorized
+            | ErrorCode::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            ErrorCode::Forbidden
+            | ErrorCode::DeviceNotApproved
+            | ErrorCode::Blocked
+            | ErrorCode::EmailNotVerified => StatusCode::FORBIDDEN,
+            ErrorCode::NotFound
+            | ErrorCode::UserNotFound
+            | ErrorCode::ChatNotFound
+            | ErrorCode::BundleNotFound
+            | ErrorCode::DeviceNotFound => StatusCode::NOT_FOUND,
+            ErrorCode::EmailTaken
+            | ErrorCode::UsernameTaken
+            | ErrorCode::AlreadyExists
+            | ErrorCode::PrekeysExhausted => StatusCode::CONFLICT,
+            ErrorCode::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::MailFailed => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => "Malformed request",
+            ErrorCode::MissingField => "Request is missing a required field",
+            ErrorCode::InvalidId => "Invalid identifier",
+            ErrorCode::InvalidUsername => "Invalid username",
+            ErrorCode::InvalidEmail => "Invalid email address",
+            ErrorCode::WeakPassword => "Password too weak",
+            ErrorCode::Unauthorized => "Not logged in or session expired",
+            ErrorCode::InvalidCredentials => "Invalid email or password",
+            ErrorCode::Forbidden => "Not allowed to access this resource",
+            ErrorCode::NotFound => "Not found",
+            ErrorCode::UserNotFound => "No such user",
+            ErrorCode::ChatNotFound => "No such chat",
+            ErrorCode::BundleNotFound => "User has no key bundle yet",
+            ErrorCode::DeviceNotFound => "No such device",
+            ErrorCode::DeviceNotApproved => "Device is waiting for approval from another device of the account",
+            ErrorCode::Blocked => "You can't contact this user",
+            ErrorCode::EmailNotVerified => "Verify your email address before starting chats",
+            ErrorCode::EmailTaken => "Email already in use",
+            ErrorCode::UsernameTaken => "Username already in use",
+            ErrorCode::AlreadyExists => "Already exists",
+            ErrorCode::PrekeysExhausted => "User has no one-time keys left, try again later",
+            ErrorCode::RateLimited => "Too many requests, try again later",
+            ErrorCode::InvalidToken => "Invalid or expired token",
+            ErrorCode::MailFailed => "Couldn't send the email, try again later",
+            ErrorCode::Internal => "Internal server error",
+        }
+    }
+}
@@ -1,15 +1,22 @@
 mod web_error;
+mod error_code;
 
-use std::{sync::Arc, convert::Infallible};
+use std::{sync::Arc, convert::Infallible, net::SocketAddr};
 use serde_json::json;
-use warp::{Rejection, Filter, hyper::HeaderMap, http::HeaderValue, Reply};
-use crate::ClientsHandle;
+use bson::oid::ObjectId;
+use warp::{Rejection, Filter, hyper::{HeaderMap, header::RETRY_AFTER}, http::HeaderValue, Reply};
+use crate::{ClientsHandle, LimitsHandle, MailerHandle};
+use crate::security::rate_limit::{Limits, RateLimiter};
 use crate::{error::AuthorizationError, ws, rest, error};
-use crate::{security::token::{jwt_from_header, decode_jwt}, model::Db};
+use crate::{security::token::{jwt_from_header, decode_jwt}, model::{Db, objectid_from_str_raw, user::User}};
 use web_error::WebErrorMessage;
+pub use error_code::ErrorCode;
 
-pub fn routes(db: Arc<Db>, clients: ClientsHandle) -> impl Filter<Extract = (impl Reply,), Error = Infallible> + Clone {
-    rest::rest_routes(db.clone())
+/// Header naming the device a request is made from.
+pub const DEVICE_HEADER: &str = "x-device-id";
+
+pub fn routes(db: Arc<Db>, clients: ClientsHandle, limits: LimitsHandle, mailer: MailerHandle) -> impl Filter<Extract = (impl Reply,), Error = Infallible> + Clone {
+    rest::rest_routes(db.clone(), clients.clone(), limits.clone(), mailer)
         .or(ws::ws_paths(db.clone(), clients.clone()))
         .recover(handle_rejection)
 }
@@ -17,26 +24,45 @@ pub fn routes(db: Arc<Db>, clients: ClientsHandle) -> impl Filter<Extract = (imp
 async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
     error!("ERROR - {:?}", err);
 
-    let error_message = match err.find::<WebErrorMessage>() {
-        Some(err) => err.clone(),
-        None => WebErrorMessage::unknown()
+    let error_message = if let Some(err) = err.find::<WebErrorMessage>() {
+        err.clone()
+    } else if err.is_not_found() {
+        WebErrorMessage::new(ErrorCode::NotFound)
+    } else if err.find::<warp::filters::body::BodyDeserializeError>().is_some()
+        || err.find::<warp::reject::UnsupportedMediaType>().is_some()
+        || err.find::<warp::reject::MethodNotAllowed>().is_some()
+        || err.find::<warp::reject::InvalidHeader>().is_some()
+        || err.find::<warp::reject::MissingHeader>().is_some() {
+        WebErrorMessage::new(ErrorCode::BadRequest)
+    } else {
+        WebErrorMessage::unknown()
     };
-    info!("{}", error_message.message);
+    info!("{}: {}", error_message.code.code(), error_message.detail);
 
     let result = json!({
-        "error": error_message.kind
+        "error": error_message.code.code(),
+        "message": error_message.message,
     });
     let result = warp::reply::json(&result);
 
-    Ok(warp::reply::with_status(result, error_message.status_code))
+    let mut response = warp::reply::with_status(result, error_message.code.status())
+        .into_response();
+    if let Some(secs) = error_message.retry_after {
+        response.headers_mut().insert(RETRY_AFTER, HeaderValue::from(secs));
+    }
+    Ok(response)
 }
 
-pub fn with_auth() -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
-    warp::header::headers_cloned()
+/// Id of the user whose token authorizes the request, tokens issued before the
+/// user's password changed are rejected.
+pub fn with_auth(db: Arc<Db>) -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+    warp::any()
+        .map(move || db.clone())
+        .and(warp::header::headers_cloned())
         .and_then(auth)
 }
 
-async fn auth(auth_header: HeaderMap<HeaderValue>) -> Result<String, warp::Rejection> {
+async fn auth(db: Arc<Db>, auth_header: HeaderMap<HeaderValue>) -> Result<String, warp::Rejection> {
     let token = jwt_from_header(&auth_header)
         .ok_or(AuthorizationError::MissingAuthHeader)?;
 
@@ -50,5 +76,67 @@ async fn auth(auth_header: HeaderMap<HeaderValue>) -> Result<String, warp::Rejec
         }
     };
 
-    Ok(token_data.claims.sub())
+    let sub = token_data.claims.sub();
+    let user = User::get_by_id(&db, &sub).await
+        .map_err(|_| AuthorizationError::Revoked)?;
+    if user.token_version() != token_data.claims.version() {
+        return Err(AuthorizationError::Revoked.into());
+    }
+    Ok(sub)
+}
+
+pub fn with_device() -> impl Filter<Extract = (ObjectId,), Error = Rejection> + Clone {
+    warp::header::<String>(DEVICE_HEADER)
+        .and_then(|device: String| async move {
+            objectid_from_str_raw(&device)
+                .map_err(warp::Rejection::from)
+        })
+}
+
+pub fn with_ip_limit(limits: LimitsHandle, limiter: fn(&Limits) -> &RateLimiter) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    client_ip(limits.clone())
+        .and_then(move |ip: String| {
+            let limits = limits.clone();
+            async move {
+                limiter(&limits).hit(&ip)
+                    .map_err(|retry_after| warp::Rejection::from(error::Error::RateLimited(retry_after)))
+            }
+        })
+        .untuple_one()
+}
+
+/// Address the proxy saw the request from. Proxies append to the header, so
+/// every entry but the last one is whatever the client chose to send.
+fn forwarded_client(header: &str) -> Option<String> {
+    header.rsplit(',')
+        .next()
+        .map(|ip| ip.trim().to_owned())
+        .filter(|ip| !ip.is_empty())
+}
+
+pub fn client_ip(limits: LimitsHandle) -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("x-forwarded-for")
+        .and(warp::addr::remote())
+        .map(move |forwarded: Option<String>, remote: Option<SocketAddr>| {
+            let forwarded = forwarded
+                .filter(|_| limits.trust_proxy)
+                .and_then(|f| forwarded_client(&f));
+            match (forwarded, remote) {
+                (Some(ip), _) => ip,
+                (None, Some(addr)) => addr.ip().to_string(),
+                (None, None) => String::from("unknown"),
+            }
+        })
+}
+
+#[cfg(test)]
+mod server_test {
+    use super::forwarded_client;
+
+    #[test]
+    fn forwarded_takes_proxy_hop() {
+        assert_eq!(forwarded_client("1.2.3.4, 10.0.0.7"), Some(String::from("10.0.0.7")));
+        assert_eq!(forwarded_client("10.0.0.7"), Some(String::from("10.0.0.7")));
+        assert_eq!(forwarded_client(""), None);
+    }
 }
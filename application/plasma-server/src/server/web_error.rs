@@ -1,65 +1,133 @@
-use warp::hyper::StatusCode;
+use std::time::Duration;
 use crate::model;
 use crate::error;
+use super::ErrorCode;
 
 #[derive(Debug, Clone)]
 pub struct WebErrorMessage {
-    pub kind: &'static str,
+    pub code: ErrorCode,
     pub message: String,
-    pub status_code: StatusCode,
+    pub detail: String,
+    pub retry_after: Option<u64>,
 }
 impl warp::reject::Reject for WebErrorMessage {}
 
 impl WebErrorMessage {
-    pub fn unknown() -> WebErrorMessage {
-        WebErrorMessage{
-            kind: "Unkown error", 
-            message: String::from("unknown"),
-            status_code: StatusCode::BAD_REQUEST
+    pub fn new(code: ErrorCode) -> WebErrorMessage {
+        WebErrorMessage {
+            code,
+            message: String::from(code.message()),
+            detail: String::from(code.message()),
+            retry_after: None,
         }
     }
-    pub fn rejection(kind: &'static str, message: String, status_code: StatusCode) -> warp::Rejection {
-        warp::reject::custom(WebErrorMessage {kind, message, status_code})
+
+    pub fn unknown() -> WebErrorMessage {
+        WebErrorMessage::new(ErrorCode::Internal)
+    }
+
+    pub fn rejection(code: ErrorCode, detail: String) -> warp::Rejection {
+        warp::reject::custom(WebErrorMessage {
+            code,
+            message: String::from(code.message()),
+            detail,
+            retry_after: None,
+        })
+    }
+
+    pub fn rejection_with_message(code: ErrorCode, message: String) -> warp::Rejection {
+        warp::reject::custom(WebErrorMessage {
+            code,
+            message: message.clone(),
+            detail: message,
+            retry_after: None,
+        })
+    }
+
+    pub fn rate_limited(retry_after: Duration) -> warp::Rejection {
+        let secs = retry_after.as_secs().max(1);
+        warp::reject::custom(WebErrorMessage {
+            code: ErrorCode::RateLimited,
+            message: format!("Too many requests, try again in {}s", secs),
+            detail: format!("retry after {}s", secs),
+            retry_after: Some(secs),
+        })
     }
 }
 
 impl From<error::Error> for warp::Rejection {
     fn from(other: error::Error) -> Self {
-        WebErrorMessage::rejection(
-            "error::Error",
-            format!("{}", other),
-            StatusCode::BAD_REQUEST,
-        )
+        if let error::Error::RateLimited(retry_after) = other {
+            return WebErrorMessage::rate_limited(retry_after);
+        }
+        let code = match other {
+            error::Error::BodyError(_) => ErrorCode::MissingField,
+            error::Error::Forbidden(_) => ErrorCode::Forbidden,
+            error::Error::DeviceNotApproved => ErrorCode::DeviceNotApproved,
+            error::Error::Blocked(_) => ErrorCode::Blocked,
+            error::Error::Unverified => ErrorCode::EmailNotVerified,
+            error::Error::PrekeysExhausted => ErrorCode::PrekeysExhausted,
+            error::Error::RateLimited(_) => ErrorCode::RateLimited,
+            error::Error::JWTokenError(_) => ErrorCode::Unauthorized,
+            error::Error::MailError(_) => ErrorCode::MailFailed,
+            error::Error::EnvError(_)
+            | error::Error::InvalidClaimData(_)
+            | error::Error::InternalError => ErrorCode::Internal,
+        };
+        match code {
+            ErrorCode::MissingField => WebErrorMessage::rejection_with_message(code, format!("{}", other)),
+            _ => WebErrorMessage::rejection(code, format!("{}", other)),
+        }
     }
 }
 
 impl From<model::Error> for warp::Rejection {
     fn from(other: model::Error) -> Self {
-        WebErrorMessage::rejection(
-            "model::Error",
-            format!("{}", other),
-            StatusCode::BAD_REQUEST,
-        )
+        let code = match other {
+            model::Error::NoUserWithSuchEmail => ErrorCode::UserNotFound,
+            model::Error::NotFound("user") => ErrorCode::UserNotFound,
+            model::Error::NotFound("chat") => ErrorCode::ChatNotFound,
+            model::Error::NotFound("bundle") => ErrorCode::BundleNotFound,
+            model::Error::NotFound("device") => ErrorCode::DeviceNotFound,
+            model::Error::NotFound("token") => ErrorCode::InvalidToken,
+            model::Error::NotFound(_) => ErrorCode::NotFound,
+            model::Error::InvalidOID => ErrorCode::InvalidId,
+            model::Error::NotUnique(_) => ErrorCode::AlreadyExists,
+            model::Error::BsonConvError(_)
+            | model::Error::DbError(_, _)
+            | model::Error::CouldNotConnectToDB => ErrorCode::Internal,
+        };
+        WebErrorMessage::rejection(code, format!("{}", other))
     }
 }
 
 impl From<error::BsonError> for warp::Rejection {
     fn from(other: error::BsonError) -> Self {
-        WebErrorMessage::rejection(
-            "error::BsonError",
-            format!("{}", other),
-            StatusCode::BAD_REQUEST,
-        )
+        WebErrorMessage::rejection(ErrorCode::Internal, format!("{}", other))
     }
 }
 
 impl From<error::AuthorizationError> for warp::Rejection {
     fn from(other: error::AuthorizationError) -> Self {
-        WebErrorMessage::rejection(
-            "error::AuthError",
-            format!("{}", other),
-            StatusCode::UNAUTHORIZED,
-        )
+        let code = match other {
+            error::AuthorizationError::InvalidCredentials(_) => ErrorCode::InvalidCredentials,
+            error::AuthorizationError::InvalidToken(_)
+            | error::AuthorizationError::MissingAuthHeader
+            | error::AuthorizationError::Revoked => ErrorCode::Unauthorized,
+        };
+        WebErrorMessage::rejection(code, format!("{}", other))
     }
 }
 
+impl From<error::ValidationError> for warp::Rejection {
+    fn from(other: error::ValidationError) -> Self {
+        let code = match other {
+            error::ValidationError::InvalidUsername(_) => ErrorCode::InvalidUsername,
+            error::ValidationError::InvalidEmail => ErrorCode::InvalidEmail,
+            error::ValidationError::WeakPassword(_) => ErrorCode::WeakPassword,
+            error::ValidationError::EmailTaken => ErrorCode::EmailTaken,
+            error::ValidationError::UsernameTaken => ErrorCode::UsernameTaken,
+        };
+        WebErrorMessage::rejection_with_message(code, format!("{}", other))
+    }
+}
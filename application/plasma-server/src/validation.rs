@@ -0,0 +1,130 @@
+use crate::error::ValidationError;
+
+const USERNAME_MIN_LEN: usize = 3;
+const USERNAME_MAX_LEN: usize = 32;
+/// Shortest user search, single letters would list users too easily.
+const SEARCH_MIN_LEN: usize = 2;
+const EMAIL_MAX_LEN: usize = 254;
+const PASSWORD_MIN_LEN: usize = 8;
+const PASSWORD_MAX_LEN: usize = 128;
+
+pub fn validate_username(username: &str) -> Result<(), ValidationError> {
+    let len = username.chars().count();
+    if len < USERNAME_MIN_LEN {
+        return Err(ValidationError::InvalidUsername("too short"));
+    }
+    if len > USERNAME_MAX_LEN {
+        return Err(ValidationError::InvalidUsername("too long"));
+    }
+    let allowed = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    if !username.chars().all(allowed) {
+        return Err(ValidationError::InvalidUsername("only letters, digits, '_' and '-' are allowed"));
+    }
+    if !username.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return Err(ValidationError::InvalidUsername("must start with a letter"));
+    }
+    Ok(())
+}
+
+/// Search query is a part of a username, so it may start with any allowed character.
+pub fn validate_search_query(query: &str) -> Result<(), ValidationError> {
+    let len = query.chars().count();
+    if len < SEARCH_MIN_LEN {
+        return Err(ValidationError::InvalidUsername("search needs at least 2 characters"));
+    }
+    if len > USERNAME_MAX_LEN {
+        return Err(ValidationError::InvalidUsername("too long"));
+    }
+    let allowed = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    if !query.chars().all(allowed) {
+        return Err(ValidationError::InvalidUsername("only letters, digits, '_' and '-' are allowed"));
+    }
+    Ok(())
+}
+
+pub fn validate_email(email: &str) -> Result<(), ValidationError> {
+    if email.len() > EMAIL_MAX_LEN || email.chars().any(char::is_whitespace) {
+        return Err(ValidationError::InvalidEmail);
+    }
+    let (local, domain) = email.split_once('@')
+        .ok_or(ValidationError::InvalidEmail)?;
+    let domain_valid = !domain.contains('@')
+        && domain.split('.').count() >= 2
+        && domain.split('.').all(|label| !label.is_empty());
+    if local.is_empty() || !domain_valid {
+        return Err(ValidationError::InvalidEmail);
+    }
+    Ok(())
+}
+
+pub fn validate_password(password: &str, username: &str) -> Result<(), ValidationError> {
+    let len = password.chars().count();
+    if len < PASSWORD_MIN_LEN {
+        return Err(ValidationError::WeakPassword("must be at least 8 characters long"));
+    }
+    if len > PASSWORD_MAX_LEN {
+        return Err(ValidationError::WeakPassword("must be at most 128 characters long"));
+    }
+    let has_letter = password.chars().any(char::is_alphabetic);
+    let has_digit = password.chars().any(|c| c.is_ascii_digit());
+    if !has_letter || !has_digit {
+        return Err(ValidationError::WeakPassword("must contain both letters and digits"));
+    }
+    if password.to_lowercase().contains(&username.to_lowercase()) {
+        return Err(ValidationError::WeakPassword("must not contain username"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod validation_test {
+    use super::{validate_username, validate_email, validate_password, validate_search_query};
+
+    #[test]
+    fn username_valid() {
+        assert!(validate_username("alice").is_ok());
+        assert!(validate_username("bob_the-2nd").is_ok());
+    }
+
+    #[test]
+    fn username_invalid() {
+        assert!(validate_username("al").is_err());
+        assert!(validate_username(&"a".repeat(33)).is_err());
+        assert!(validate_username("1alice").is_err());
+        assert!(validate_username("ali/ce").is_err());
+        assert!(validate_username("ali ce").is_err());
+    }
+
+    #[test]
+    fn search_query() {
+        assert!(validate_search_query("al").is_ok());
+        assert!(validate_search_query("_b").is_ok());
+        assert!(validate_search_query("a").is_err());
+        assert!(validate_search_query("a.*").is_err());
+    }
+
+    #[test]
+    fn email_valid() {
+        assert!(validate_email("alice@example.com").is_ok());
+        assert!(validate_email("a.b+c@mail.example.org").is_ok());
+    }
+
+    #[test]
+    fn email_invalid() {
+        assert!(validate_email("alice").is_err());
+        assert!(validate_email("@example.com").is_err());
+        assert!(validate_email("alice@example").is_err());
+        assert!(validate_email("alice@example..com").is_err());
+        assert!(validate_email("alice@@example.com").is_err());
+        assert!(validate_email("ali ce@example.com").is_err());
+    }
+
+    #[test]
+    fn password_strength() {
+        assert!(validate_password("correct9horse", "alice").is_ok());
+        assert!(validate_password("short1", "alice").is_err());
+        assert!(validate_password("onlyletters", "alice").is_err());
+        assert!(validate_password("1234567890", "alice").is_err());
+        assert!(validate_password("xAlice12345", "alice").is_err());
+    }
+}
@@ -1,29 +1,124 @@
-use std::collections::HashMap;
+use std::{collections::{HashMap, HashSet}, time::{SystemTime, UNIX_EPOCH}};
+use bson::oid::ObjectId;
 use tokio::sync::mpsc;
-use warp::{filters::ws::Message, reject::Rejection};
+use warp::filters::ws::Message;
 
 type Client = mpsc::UnboundedSender<Message>;
 
+/// What other members of a chat see about a user, last seen is in seconds since the epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Presence {
+    pub online: bool,
+    pub last_seen: Option<u64>,
+}
+
+/// Open websocket connections, one per connected device of a user.
 pub struct Clients {
-    client_map: HashMap<String, Client>,
+    client_map: HashMap<ObjectId, HashMap<ObjectId, Client>>,
+    last_seen: HashMap<ObjectId, u64>,
+    hidden: HashSet<ObjectId>,
 }
 
 impl Clients {
     pub fn new() -> Self {
         Clients {
             client_map: HashMap::new(),
+            last_seen: HashMap::new(),
+            hidden: HashSet::new(),
         }
     }
 
-    pub fn get_client(&mut self, user_id: &str) -> Option<&Client> {
+    pub fn get_clients(&self, user_id: &ObjectId) -> Vec<(ObjectId, Client)> {
         self.client_map.get(user_id)
+            .map(|devices| devices.iter()
+                .map(|(device, client)| (*device, client.clone()))
+                .collect())
+            .unwrap_or_default()
     }
 
-    pub fn add_client(&mut self, user_id: String, client: Client) {
-        self.client_map.insert(user_id, client);
+    /// Returns true when this is the first connected device, the user just came online.
+    pub fn add_client(&mut self, user_id: ObjectId, device_id: ObjectId, client: Client) -> bool {
+        let devices = self.client_map.entry(user_id)
+            .or_default();
+        devices.insert(device_id, client);
+        devices.len() == 1
     }
 
-    pub fn remove_client(&mut self, user_id: &str) {
+    /// Returns true when the last device disconnected, the user just went offline.
+    pub fn remove_client(&mut self, user_id: &ObjectId, device_id: &ObjectId) -> bool {
+        let devices = match self.client_map.get_mut(user_id) {
+            Some(devices) => devices,
+            None => return false,
+        };
+        devices.remove(device_id);
+        if !devices.is_empty() {
+            return false;
+        }
         self.client_map.remove(user_id);
+        self.last_seen.insert(*user_id, now());
+        true
+    }
+
+    /// Users who opted out look offline and never seen.
+    pub fn presence(&self, user_id: &ObjectId) -> Presence {
+        if self.hidden.contains(user_id) {
+            return Presence { online: false, last_seen: None };
+        }
+        Presence {
+            online: self.client_map.contains_key(user_id),
+            last_seen: self.last_seen.get(user_id).copied(),
+        }
+    }
+
+    pub fn set_hidden(&mut self, user_id: ObjectId, hidden: bool) {
+        match hidden {
+            true => self.hidden.insert(user_id),
+            false => self.hidden.remove(&user_id),
+        };
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
+}
+
+#[cfg(test)]
+mod clients_test {
+    use bson::oid::ObjectId;
+    use tokio::sync::mpsc;
+    use super::{Clients, Presence};
+
+    #[test]
+    fn presence_follows_devices() {
+        let mut clients = Clients::new();
+        let (user, device1, device2) = (ObjectId::new(), ObjectId::new(), ObjectId::new());
+        let (tx, _rx) = mpsc::unbounded_channel();
+
+        assert!(clients.add_client(user, device1, tx.clone()));
+        assert!(!clients.add_client(user, device2, tx));
+        assert!(clients.presence(&user).online);
+
+        assert!(!clients.remove_client(&user, &device1));
+        assert!(clients.remove_client(&user, &device2));
+        let presence = clients.presence(&user);
+        assert!(!presence.online);
+        assert!(presence.last_seen.is_some());
+    }
+
+    #[test]
+    fn presence_hidden() {
+        let mut clients = Clients::new();
+        let (user, device) = (ObjectId::new(), ObjectId::new());
+        let (tx, _rx) = mpsc::unbounded_channel();
+        clients.add_client(user, device, tx);
+        clients.set_hidden(user, true);
+
+        assert_eq!(clients.presence(&user), Presence { online: false, last_seen: None });
+
+        clients.set_hidden(user, false);
+        assert!(clients.presence(&user).online);
     }
 }
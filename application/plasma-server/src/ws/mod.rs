@@ -6,46 +6,100 @@ use futures::{StreamExt, SinkExt, TryFutureExt};
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
 use warp::{Filter, reject::Rejection, reply::Reply, ws::WebSocket};
-use crate::{model::{Db, chat::Chat, message::Message}, server::with_auth, ClientsHandle};
+use crate::{model::{Db, block::Block, chat::Chat, message::{Message, Payload}, user::User}, server::{with_auth, with_device}, rest::{owned_device, own_message}, ClientsHandle};
 use tokio_stream::wrappers::UnboundedReceiverStream;
+use clients::Clients;
 
+#[derive(Serialize, Deserialize)]
+struct WsPayload {
+    device_id: String,
+    content: Vec<u8>,
+}
+
+/// Message exchanged with clients, id and sender fields are filled in by the
+/// server. `edit_of` is empty unless the message replaces an earlier one, the
+/// message is dropped at `expires_at` when it is set.
 #[derive(Serialize, Deserialize)]
 struct WsMessage {
+    id: String,
     chat_id: String,
     sender_id: String,
-    content: Vec<u8>,
+    sender_device: String,
+    payloads: Vec<WsPayload>,
     timestamp: u64,
+    edit_of: String,
+    expires_at: Option<u64>,
+}
+
+/// Frames exchanged with clients, `Delivered` is sent by the receiving client
+/// and forwarded to the devices of the original sender. `Typing` is relayed
+/// to the other members and never stored.
+#[derive(Serialize, Deserialize)]
+enum WsFrame {
+    Message(WsMessage),
+    Stored { chat_id: String, id: String, timestamp: u64 },
+    Delivered { chat_id: String, sender_id: String, timestamp: u64 },
+    Typing { chat_id: String, sender_id: String },
+    Presence { user_id: String, online: bool, last_seen: Option<u64> },
+    Deleted { chat_id: String, message_id: String },
+}
+
+impl WsFrame {
+    fn encode(&self) -> Option<warp::filters::ws::Message> {
+        match bincode::serialize(self) {
+            Ok(frame) => Some(warp::filters::ws::Message::binary(frame)),
+            Err(e) => {
+                error!("Failed to encode frame: {}", e);
+                None
+            },
+        }
+    }
 }
 
 pub fn ws_paths(db: Arc<Db>, clients: ClientsHandle) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let auth = with_auth(db.clone());
     let with_db = warp::any()
         .map(move || db.clone());
     let with_clients = warp::any()
         .map(move || clients.clone());
     let common = with_db.clone()
         .and(with_clients.clone())
-        .and(with_auth());
+        .and(auth);
 
     let chat = warp::path("chat")
         .and(warp::ws())
         .and(common.clone())
+        .and(with_device())
         .and_then(handle);
 
     chat
 }
 
-async fn handle(ws: warp::ws::Ws, db: Arc<Db>, clients: ClientsHandle, oid: String) -> Result<impl Reply, Rejection> {
-    Ok(ws.on_upgrade(move |socket| user_connected(socket, db.clone(), clients.clone(), oid)))
+async fn handle(ws: warp::ws::Ws, db: Arc<Db>, clients: ClientsHandle, oid: String, device_id: ObjectId) -> Result<impl Reply, Rejection> {
+    let device = owned_device(&db, &oid, &device_id, true).await?;
+    let user_id = *device.user_id();
+    Ok(ws.on_upgrade(move |socket| user_connected(socket, db.clone(), clients.clone(), user_id, device_id)))
 }
 
-async fn user_connected(socket: WebSocket, db: Arc<Db>, clients: ClientsHandle, oid: String) {
-    debug!("User connected: {}", oid);
+async fn user_connected(socket: WebSocket, db: Arc<Db>, clients: ClientsHandle, user_id: ObjectId, device_id: ObjectId) {
+    debug!("User connected: {} device {}", user_id, device_id);
 
     let (mut user_ws_tx, mut user_ws_rx) = socket.split();
     let (tx, rx) = mpsc::unbounded_channel();
     let mut rx = UnboundedReceiverStream::new(rx);
 
-    clients.write().await.add_client(oid.clone(), tx);
+    let hidden = User::get_by_oid(&db, &user_id).await
+        .map(|user| user.hides_presence())
+        .unwrap_or(false);
+    let came_online = {
+        let mut clients = clients.write().await;
+        clients.set_hidden(user_id, hidden);
+        clients.add_client(user_id, device_id, tx.clone())
+    };
+    if came_online {
+        broadcast_presence(&db, &clients, &user_id).await;
+    }
+    send_presence_of_contacts(&db, &clients, &user_id, &tx).await;
 
     tokio::task::spawn(async move {
         while let Some(message) = rx.next().await {
@@ -62,52 +116,235 @@ async fn user_connected(socket: WebSocket, db: Arc<Db>, clients: ClientsHandle,
         let msg = match result {
             Ok(msg) => msg,
             Err(e) => {
-                error!("websocket error: id={}, error={}", oid, e);
+                error!("websocket error: id={}, error={}", user_id, e);
                 break;
             },
         };
-        user_message(db.clone(), &oid, msg, clients.clone()).await;
+        user_message(db.clone(), &user_id, &device_id, msg, clients.clone()).await;
     }
-    disconnect_user(&oid, clients.clone()).await;
+    disconnect_user(&db, &user_id, &device_id, clients.clone()).await;
 }
 
-async fn user_message(db: Arc<Db>, oid: &str, msg: warp::filters::ws::Message, clients: ClientsHandle) {
+async fn user_message(db: Arc<Db>, user_id: &ObjectId, device_id: &ObjectId, msg: warp::filters::ws::Message, clients: ClientsHandle) {
     if msg.as_bytes().is_empty() {
         return;
     }
-    let ws_msg: WsMessage = bincode::deserialize(msg.as_bytes()).unwrap();
-    let chat = Chat::get_by_id(&db, &ws_msg.chat_id).await.unwrap();
-    let sender_id = ObjectId::from_str(&ws_msg.sender_id).unwrap();
+    let frame: WsFrame = match bincode::deserialize(msg.as_bytes()) {
+        Ok(frame) => frame,
+        Err(e) => {
+            error!("Malformed frame from {}: {}", user_id, e);
+            return;
+        },
+    };
+    match frame {
+        WsFrame::Message(ws_msg) => store_message(db, user_id, device_id, ws_msg, clients).await,
+        WsFrame::Delivered { chat_id, sender_id, timestamp } => forward_delivered(db, user_id, chat_id, sender_id, timestamp, clients).await,
+        WsFrame::Typing { chat_id, .. } => relay_typing(db, user_id, chat_id, clients).await,
+        WsFrame::Stored { .. } | WsFrame::Presence { .. } | WsFrame::Deleted { .. } => error!("Unexpected server frame from {}", user_id),
+    }
+}
 
-    let new_message = Message::new(chat.id().unwrap(), sender_id, ws_msg.content, ws_msg.timestamp);
-    if let Err(e) = Message::add_to_db(&db, &new_message).await {
+async fn store_message(db: Arc<Db>, user_id: &ObjectId, device_id: &ObjectId, ws_msg: WsMessage, clients: ClientsHandle) {
+    let chat = match Chat::get_by_id(&db, &ws_msg.chat_id).await {
+        Ok(chat) if chat.is_member(user_id) => chat,
+        _ => {
+            error!("User {} is not a member of chat {}", user_id, ws_msg.chat_id);
+            return;
+        },
+    };
+    if is_blocked(&db, user_id, &chat).await {
+        info!("Dropped message of {} to chat {} with a block", user_id, ws_msg.chat_id);
+        return;
+    }
+    let payloads = ws_msg.payloads.iter()
+        .filter_map(|p| Some(Payload {
+            device_id: ObjectId::from_str(&p.device_id).ok()?,
+            content: p.content.clone(),
+        }))
+        .collect();
+    let chat_id = chat.id().unwrap();
+    let (edit_of, expires_at) = match ws_msg.edit_of.as_str() {
+        "" => (None, ws_msg.expires_at),
+        original => match ObjectId::from_str(original) {
+            Ok(id) => match own_message(&db, user_id, &chat_id, &id).await {
+                Ok(original) => (Some(id), original.expires_at()),
+                Err(_) => {
+                    error!("User {} can't edit message {}", user_id, id);
+                    return;
+                },
+            },
+            Err(_) => {
+                error!("User {} can't edit message {}", user_id, original);
+                return;
+            },
+        },
+    };
+    let mut new_message = Message::new(chat_id, *user_id, *device_id, payloads, ws_msg.timestamp)
+        .editing(edit_of)
+        .expiring(expires_at);
+    if let Err(e) = Message::add_to_db(&db, &mut new_message).await {
         error!("Failed to send message: {}", e);
         return;
     }
+    let id = new_message.id().map(|id| id.to_hex()).unwrap_or_default();
+    let stored = WsFrame::Stored { chat_id: ws_msg.chat_id, id, timestamp: ws_msg.timestamp };
+    send_to(&clients, user_id, |device| device == device_id, &stored).await;
+    deliver(&clients, &chat, &new_message).await;
+}
 
-    let members = chat.members()
-        .iter()
-        .filter(|&m| *m != sender_id);
+/// Passes the acknowledgement of a received message on to the devices of its sender.
+async fn forward_delivered(db: Arc<Db>, user_id: &ObjectId, chat_id: String, sender_id: String, timestamp: u64, clients: ClientsHandle) {
+    let sender = match ObjectId::from_str(&sender_id) {
+        Ok(sender) if sender != *user_id => sender,
+        _ => return,
+    };
+    match Chat::get_by_id(&db, &chat_id).await {
+        Ok(chat) if chat.is_member(user_id) && chat.is_member(&sender) => {},
+        _ => {
+            error!("User {} acknowledged message outside of chat {}", user_id, chat_id);
+            return;
+        },
+    }
+    let delivered = WsFrame::Delivered { chat_id, sender_id, timestamp };
+    send_to(&clients, &sender, |_| true, &delivered).await;
+}
 
-    for member in members {
-        let member_id_str = format!("ObjectId(\"{}\")", member.to_string());
-        match clients.write().await.get_client(&member_id_str) {
-            None => {
-                info!("No client for {}", member);
-                return
-            },
-            Some(client) => {
-                let res = client.send(msg.clone());
-                if res.is_err() {
-                    error!("User disconnected {}", oid);
-                    return;
-                }
+async fn relay_typing(db: Arc<Db>, user_id: &ObjectId, chat_id: String, clients: ClientsHandle) {
+    let chat = match Chat::get_by_id(&db, &chat_id).await {
+        Ok(chat) if chat.is_member(user_id) => chat,
+        _ => return,
+    };
+    if is_blocked(&db, user_id, &chat).await {
+        return;
+    }
+    let typing = WsFrame::Typing { chat_id, sender_id: user_id.to_hex() };
+    for member in chat.members().iter().filter(|member| *member != user_id) {
+        send_to(&clients, member, |_| true, &typing).await;
+    }
+}
+
+/// True when `user_id` or another member of the chat blocked the other, failed
+/// lookups count as blocked so nothing slips through.
+async fn is_blocked(db: &Db, user_id: &ObjectId, chat: &Chat) -> bool {
+    match Block::between(db, user_id, chat.members()).await {
+        Ok(blocked) => blocked,
+        Err(e) => {
+            error!("Failed to check blocks of {}: {}", user_id, e);
+            true
+        },
+    }
+}
+
+/// Users sharing a chat with `user_id`, the ones allowed to see its presence.
+async fn contacts(db: &Arc<Db>, user_id: &ObjectId) -> Vec<ObjectId> {
+    let chats = match Chat::get_users_chats_by_oid(db, user_id).await {
+        Ok(chats) => chats,
+        Err(e) => {
+            error!("Failed to find contacts of {}: {}", user_id, e);
+            return Vec::new();
+        },
+    };
+    let mut contacts: Vec<ObjectId> = chats.iter()
+        .flat_map(|chat| chat.members().iter().copied())
+        .filter(|member| member != user_id)
+        .collect();
+    contacts.sort();
+    contacts.dedup();
+    contacts
+}
+
+fn presence_frame(clients: &Clients, user_id: &ObjectId) -> WsFrame {
+    let presence = clients.presence(user_id);
+    WsFrame::Presence { user_id: user_id.to_hex(), online: presence.online, last_seen: presence.last_seen }
+}
+
+/// Tells every contact of `user_id` about its current presence.
+pub async fn broadcast_presence(db: &Arc<Db>, clients: &ClientsHandle, user_id: &ObjectId) {
+    let frame = presence_frame(&*clients.read().await, user_id);
+    for contact in contacts(db, user_id).await {
+        send_to(clients, &contact, |_| true, &frame).await;
+    }
+}
+
+async fn send_presence_of_contacts(db: &Arc<Db>, clients: &ClientsHandle, user_id: &ObjectId, client: &mpsc::UnboundedSender<warp::filters::ws::Message>) {
+    let contacts = contacts(db, user_id).await;
+    let clients = clients.read().await;
+    for contact in contacts {
+        if let Some(frame) = presence_frame(&clients, &contact).encode() {
+            let _ = client.send(frame);
+        }
+    }
+}
+
+async fn send_to(clients: &ClientsHandle, user_id: &ObjectId, filter: impl Fn(&ObjectId) -> bool, frame: &WsFrame) {
+    let frame = match frame.encode() {
+        Some(frame) => frame,
+        None => return,
+    };
+    for (device, client) in clients.read().await.get_clients(user_id) {
+        if filter(&device) && client.send(frame.clone()).is_err() {
+            info!("Device disconnected {}", device);
+        }
+    }
+}
+
+/// Forwards stored message to every connected device of the chat members but the sending one.
+pub async fn deliver(clients: &ClientsHandle, chat: &Chat, message: &Message) {
+    let ws_msg = WsMessage {
+        id: message.id().map(|id| id.to_hex()).unwrap_or_default(),
+        chat_id: message.chat_id().to_hex(),
+        sender_id: message.sender_id().to_hex(),
+        sender_device: message.sender_device().map(|d| d.to_hex()).unwrap_or_default(),
+        payloads: message.payloads().iter()
+            .map(|p| WsPayload {
+                device_id: p.device_id.to_hex(),
+                content: p.content.clone(),
+            })
+            .collect(),
+        timestamp: message.timestamp(),
+        edit_of: message.edit_of().map(|id| id.to_hex()).unwrap_or_default(),
+        expires_at: message.expires_at(),
+    };
+    let frame = match WsFrame::Message(ws_msg).encode() {
+        Some(frame) => frame,
+        None => return,
+    };
+    let clients = clients.read().await;
+    for member in chat.members() {
+        for (member_device, client) in clients.get_clients(member) {
+            if Some(&member_device) == message.sender_device() {
+                continue;
+            }
+            if client.send(frame.clone()).is_err() {
+                info!("Device disconnected {}", member_device);
             }
         }
     }
 }
 
-async fn disconnect_user(oid: &str, clients: ClientsHandle) {
-    info!("User disconnected: {}", oid);
-    clients.write().await.remove_client(oid);
+/// Tells every connected device of the chat members a message is gone.
+pub async fn deleted(clients: &ClientsHandle, chat: &Chat, message_id: &ObjectId) {
+    let chat_id = match chat.id() {
+        Some(id) => id.to_hex(),
+        None => return,
+    };
+    let frame = WsFrame::Deleted { chat_id, message_id: message_id.to_hex() };
+    for member in chat.members() {
+        send_to(clients, member, |_| true, &frame).await;
+    }
+}
+
+/// Closes every connection of the user, each one then disconnects as usual.
+pub async fn close_user(clients: &ClientsHandle, user_id: &ObjectId) {
+    for (_, client) in clients.read().await.get_clients(user_id) {
+        let _ = client.send(warp::filters::ws::Message::close());
+    }
+}
+
+async fn disconnect_user(db: &Arc<Db>, user_id: &ObjectId, device_id: &ObjectId, clients: ClientsHandle) {
+    info!("User disconnected: {} device {}", user_id, device_id);
+    let went_offline = clients.write().await.remove_client(user_id, device_id);
+    if went_offline {
+        broadcast_presence(db, &clients, user_id).await;
+    }
 }
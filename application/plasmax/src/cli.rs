@@ -0,0 +1,331 @@
+use std::io::{self, Read, Write};
+use std::path::Path;
+use bson::oid::ObjectId;
+use plasma_client::{keyring::write_private, session::UNREADABLE, Client, IncomingMessage, KeyStore, Keyring, PlasmaError};
+use clap::ValueEnum;
+use serde::Serialize;
+use crate::{error::{AppError, failed}, export::{self, DateRange, Format}};
+
+/// Reads message from stdin when passed as `-`.
+const STDIN_MESSAGE: &str = "-";
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Visibility {
+    Visible,
+    Hidden,
+}
+
+#[derive(Serialize)]
+struct ChatOutput<'a> {
+    id: String,
+    username: &'a str,
+}
+
+#[derive(Serialize)]
+struct MessageOutput<'a> {
+    id: Option<String>,
+    chat_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    edit_of: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to: Option<String>,
+    sender: &'a str,
+    timestamp: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+    content: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct UserOutput<'a> {
+    id: String,
+    username: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nickname: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct WhoamiOutput<'a> {
+    username: &'a str,
+    id: String,
+    device: String,
+    server: &'a str,
+}
+
+#[derive(Serialize)]
+struct SentOutput {
+    chat_id: String,
+    timestamp: u64,
+}
+
+/// Json output is one object per line so it can be streamed into other tools.
+fn print_json<T: Serialize>(value: &T) -> Result<(), PlasmaError> {
+    let line = serde_json::to_string(value)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    println!("{}", line);
+    Ok(())
+}
+
+fn print_message(message: &IncomingMessage, json: bool) -> Result<(), PlasmaError> {
+    match json {
+        true => print_json(&MessageOutput {
+            id: message.id.map(|id| id.to_hex()),
+            chat_id: message.chat_id.to_hex(),
+            edit_of: message.edit_of.map(|id| id.to_hex()),
+            reply_to: message.reply_to.map(|id| id.to_hex()),
+            expires_at: message.expires_at,
+            sender: &message.sender,
+            timestamp: message.timestamp,
+            content: message.content.as_deref(),
+        })?,
+        false => {
+            let content = message.content.as_deref().unwrap_or(UNREADABLE);
+            match (message.edit_of, message.reply_to) {
+                (Some(original), _) => println!("{} {} (edited {}): {}", message.timestamp, message.sender, original, content),
+                (None, Some(parent)) => println!("{} {} (reply to {}): {}", message.timestamp, message.sender, parent, content),
+                (None, None) => println!("{} {}: {}", message.timestamp, message.sender, content),
+            }
+        },
+    }
+    io::stdout().flush()?;
+    Ok(())
+}
+
+/// Text argument as given, or read from stdin when it is `-`.
+fn read_text(text: &str) -> Result<String, PlasmaError> {
+    match text {
+        STDIN_MESSAGE => {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text)?;
+            Ok(text.trim_end().to_owned())
+        },
+        text => Ok(text.to_owned()),
+    }
+}
+
+/// Asks a yes or no question, anything but yes counts as no.
+fn confirm(question: &str) -> Result<bool, PlasmaError> {
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+pub async fn chats(client: &Client, json: bool) -> Result<(), AppError> {
+    for chat in client.chats().await? {
+        match json {
+            true => print_json(&ChatOutput { id: chat.id.to_hex(), username: &chat.user.username })?,
+            false => println!("{}\t{}", chat.user.username, chat.id.to_hex()),
+        }
+    }
+    Ok(())
+}
+
+pub async fn send(client: &Client, username: &str, message: &str, reply_to: Option<&str>, json: bool) -> Result<(), AppError> {
+    let reply_to = reply_to.map(ObjectId::parse_str)
+        .transpose()
+        .map_err(|_| AppError::Aborted(String::from("Invalid message id to reply to")))?;
+    let message = read_text(message)?;
+    if message.is_empty() {
+        return Ok(());
+    }
+    let mut chat = client.open_chat(username).await?;
+    // Latest history carries the disappearing messages timer of the chat.
+    client.history(&mut chat, None).await?;
+    let timestamp = match reply_to {
+        Some(parent) => client.reply(&chat, &parent, &message).await?,
+        None => client.send(&chat, &message).await?,
+    };
+    if json {
+        print_json(&SentOutput { chat_id: chat.chat.id.to_hex(), timestamp })?;
+    }
+    Ok(())
+}
+
+/// Prints the latest page of messages, or every message newer than `since`.
+pub async fn read(client: &Client, username: &str, since: Option<u64>, json: bool) -> Result<(), AppError> {
+    let mut chat = client.existing_chat(username).await?;
+    for message in client.history(&mut chat, since).await? {
+        print_message(&message, json)?;
+    }
+    Ok(())
+}
+
+/// Streams messages of the chat as they arrive until the connection drops.
+pub async fn follow(client: &Client, username: &str, json: bool) -> Result<(), AppError> {
+    let chat = client.existing_chat(username).await?;
+    let mut incoming = client.follow(chat).await;
+    while let Some(message) = incoming.next().await {
+        print_message(&message?, json)?;
+    }
+    Ok(())
+}
+
+/// Writes decrypted history of the chat with `username` to `output`, or stdout.
+pub async fn export(client: &Client, username: &str, format: Format, output: Option<&Path>, range: DateRange) -> Result<(), AppError> {
+    let chat = client.chats().await?
+        .into_iter()
+        .find(|chat| chat.user.username == username)
+        .ok_or_else(|| PlasmaError::NoChat(username.to_owned()))?;
+    let messages = export::collect(client.api(), client.account(), &chat, range).await?;
+    let transcript = export::render(username, &messages, format)?;
+    match output {
+        Some(path) => {
+            write_private(path, transcript.as_bytes())?;
+            println!("Exported {} messages to {}", messages.len(), path.display());
+        },
+        None => print!("{}", transcript),
+    }
+    Ok(())
+}
+
+pub async fn presence(client: &Client, visibility: Visibility, json: bool) -> Result<(), AppError> {
+    let hidden = matches!(visibility, Visibility::Hidden);
+    client.set_presence_hidden(hidden).await?;
+    match json {
+        true => print_json(&serde_json::json!({ "presence_hidden": hidden }))?,
+        false if hidden => println!("Contacts no longer see when you are online"),
+        false => println!("Contacts see when you are online"),
+    }
+    Ok(())
+}
+
+pub async fn users(client: &Client, query: &str, json: bool) -> Result<(), AppError> {
+    let users = client.search_users(query).await
+        .map_err(failed("Search"))?;
+    for user in users {
+        match json {
+            true => print_json(&UserOutput { id: user.id.to_hex(), username: &user.username, nickname: None })?,
+            false => println!("{}", user.username),
+        }
+    }
+    Ok(())
+}
+
+/// Applies the requested change, then lists every contact.
+pub async fn contacts(client: &Client, add: Option<&str>, nickname: Option<&str>, remove: Option<&str>, json: bool) -> Result<(), AppError> {
+    let result = match (add, remove) {
+        (Some(username), _) => client.add_contact(username, nickname).await.map(|_| ()),
+        (None, Some(username)) => client.remove_contact(username).await,
+        (None, None) => Ok(()),
+    };
+    result.map_err(failed("Contact change"))?;
+    for contact in client.contacts().await? {
+        match (json, &contact.nickname) {
+            (true, _) => print_json(&UserOutput { id: contact.id.to_hex(), username: &contact.username, nickname: contact.nickname.as_deref() })?,
+            (false, Some(nickname)) => println!("{}\t{}", contact.username, nickname),
+            (false, None) => println!("{}", contact.username),
+        }
+    }
+    Ok(())
+}
+
+/// Applies the requested change, then lists every blocked user.
+pub async fn block(client: &Client, add: Option<&str>, remove: Option<&str>, json: bool) -> Result<(), AppError> {
+    let result = match (add, remove) {
+        (Some(username), _) => client.block(username).await,
+        (None, Some(username)) => client.unblock(username).await,
+        (None, None) => Ok(()),
+    };
+    result.map_err(failed("Block change"))?;
+    for user in client.blocked().await? {
+        match json {
+            true => print_json(&UserOutput { id: user.id.to_hex(), username: &user.username, nickname: None })?,
+            false => println!("{}", user.username),
+        }
+    }
+    Ok(())
+}
+
+pub async fn report(client: &Client, username: &str, excerpt: &str, reason: &str, block: bool, json: bool) -> Result<(), AppError> {
+    let excerpt = read_text(excerpt)?;
+    let report = client.report(username, reason, &excerpt, block).await
+        .map_err(failed("Report"))?;
+    match json {
+        true => print_json(&serde_json::json!({ "report": report.to_hex(), "blocked": block }))?,
+        false if block => println!("Reported and blocked {}", username),
+        false => println!("Reported {}", username),
+    }
+    Ok(())
+}
+
+pub async fn discovery(client: &Client, visibility: Visibility, json: bool) -> Result<(), AppError> {
+    let hidden = matches!(visibility, Visibility::Hidden);
+    client.set_search_hidden(hidden).await?;
+    match json {
+        true => print_json(&serde_json::json!({ "search_hidden": hidden }))?,
+        false if hidden => println!("Others no longer find you by searching, your exact username still works"),
+        false => println!("Others find you by searching"),
+    }
+    Ok(())
+}
+
+pub fn whoami(client: &Client, json: bool) -> Result<(), AppError> {
+    let acc = client.account();
+    let output = WhoamiOutput {
+        username: acc.username(),
+        id: acc.id().to_hex(),
+        device: acc.device().to_hex(),
+        server: client.server().name(),
+    };
+    match json {
+        true => print_json(&output)?,
+        false => println!("{} ({}) on {}, device {}", output.username, output.id, output.server, output.device),
+    }
+    Ok(())
+}
+
+pub fn logout(keyring: &Keyring, mail: &str, json: bool) -> Result<(), AppError> {
+    keyring.remove_token()?;
+    match json {
+        true => print_json(&serde_json::json!({ "logged_out": mail }))?,
+        false => println!("Logged out {}", mail),
+    }
+    Ok(())
+}
+
+/// Changes the password, this session gets a new token and stays logged in.
+pub async fn change_password(client: &mut Client, json: bool) -> Result<(), AppError> {
+    let password = crate::get_password();
+    let new_password = crate::get_new_password()?;
+    client.change_password(&password, &new_password).await
+        .map_err(failed("Password change"))?;
+    match json {
+        true => print_json(&serde_json::json!({ "password_changed": true }))?,
+        false => println!("Password changed, other sessions were signed out"),
+    }
+    Ok(())
+}
+
+pub async fn export_account(client: &Client, output: Option<&Path>) -> Result<(), AppError> {
+    let account = client.export_account(&crate::get_password()).await
+        .map_err(failed("Export"))?;
+    let account = serde_json::to_string_pretty(&account)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    match output {
+        Some(path) => {
+            write_private(path, account.as_bytes())?;
+            println!("Exported account data to {}", path.display());
+        },
+        None => println!("{}", account),
+    }
+    Ok(())
+}
+
+/// Deletes the account on the server, then wipes its keyring and cache here.
+pub async fn delete_account(client: Client, mail: &str, yes: bool, json: bool) -> Result<(), AppError> {
+    let server = client.server().clone();
+    let question = format!("Delete {} on {} with its messages and keys? This can't be undone.", mail, server.name());
+    if !yes && !confirm(&question)? {
+        return Err(AppError::Aborted(String::from("Nothing was deleted")));
+    }
+    client.delete_account(&crate::get_password()).await
+        .map_err(failed("Deletion"))?;
+    Keyring::wipe(&server, mail)?;
+    match json {
+        true => print_json(&serde_json::json!({ "deleted": mail }))?,
+        false => println!("Deleted {} and its keys on this machine", mail),
+    }
+    Ok(())
+}
@@ -0,0 +1,134 @@
+use std::{collections::HashMap, fs, io::ErrorKind, path::PathBuf};
+use home::home_dir;
+use plasma_client::server::{Server, InvalidServerUrl};
+use serde::Deserialize;
+use thiserror::Error;
+
+const CONFIG_DIR: &str = "plasmax";
+const CONFIG_FILENAME: &str = "config.toml";
+const LOCAL_SERVER: &str = "local";
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Parse(#[from] toml::de::Error),
+    #[error("Unknown server profile {0}")]
+    UnknownServer(String),
+    #[error(transparent)]
+    InvalidUrl(#[from] InvalidServerUrl),
+}
+
+/// Contents of `config.toml`:
+///
+/// ```toml
+/// default = "home"
+///
+/// [servers.home]
+/// url = "https://chat.example.org"
+/// ```
+#[derive(Deserialize, Default, Debug)]
+pub struct Config {
+    default: Option<String>,
+    #[serde(default)]
+    servers: HashMap<String, Profile>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Profile {
+    url: String,
+}
+
+impl Config {
+    /// Reads the config file, a missing file means the built-in local server only.
+    pub fn load() -> Result<Config, ConfigError> {
+        let path = match config_path() {
+            Some(path) => path,
+            None => return Ok(Config::default()),
+        };
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Config::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn parse(text: &str) -> Result<Config, ConfigError> {
+        Ok(toml::from_str(text)?)
+    }
+
+    /// Picks the server named by `--server`, either a profile name or a url,
+    /// falling back to the default profile.
+    pub fn server(&self, requested: Option<&str>) -> Result<Server, ConfigError> {
+        let name = match requested.or(self.default.as_deref()) {
+            Some(name) => name,
+            None => return Ok(Server::local(LOCAL_SERVER)),
+        };
+        match self.servers.get(name) {
+            Some(profile) => Ok(Server::new(name, &profile.url)?),
+            None if name == LOCAL_SERVER => Ok(Server::local(LOCAL_SERVER)),
+            None if name.contains("://") => Ok(Server::new(name, name)?),
+            None => Err(ConfigError::UnknownServer(name.to_owned())),
+        }
+    }
+}
+
+fn config_path() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => home_dir()?.join(".config"),
+    };
+    Some(base.join(CONFIG_DIR).join(CONFIG_FILENAME))
+}
+
+#[cfg(test)]
+mod config_test {
+    use super::{Config, ConfigError};
+
+    const CONFIG: &str = r#"
+        default = "home"
+
+        [servers.home]
+        url = "https://chat.example.org/plasma"
+
+        [servers.dev]
+        url = "http://10.0.0.2:8000"
+    "#;
+
+    #[test]
+    fn server_default_profile() {
+        let config = Config::parse(CONFIG).unwrap();
+        let server = config.server(None).unwrap();
+
+        assert_eq!(server.name(), "home");
+        assert_eq!(server.ws_url().as_str(), "wss://chat.example.org/plasma/chat");
+    }
+
+    #[test]
+    fn server_requested_profile_or_url() {
+        let config = Config::parse(CONFIG).unwrap();
+
+        let dev = config.server(Some("dev")).unwrap();
+        assert_eq!(dev.ws_url().as_str(), "ws://10.0.0.2:8000/chat");
+        assert_eq!(dev.namespace(), "10.0.0.2-8000");
+
+        let adhoc = config.server(Some("https://other.org")).unwrap();
+        assert_eq!(adhoc.namespace(), "other.org");
+    }
+
+    #[test]
+    fn server_without_config_is_local() {
+        let server = Config::default().server(None).unwrap();
+
+        assert!(server.is_local());
+    }
+
+    #[test]
+    fn server_invalid() {
+        let config = Config::parse(CONFIG).unwrap();
+
+        assert!(matches!(config.server(Some("work")), Err(ConfigError::UnknownServer(_))));
+        assert!(matches!(config.server(Some("ftp://files.org")), Err(ConfigError::InvalidUrl(_))));
+    }
+}
@@ -1,15 +1,22 @@
+use plasma_client::PlasmaError;
 use thiserror::Error;
-use x3dh::error::X3dhError;
-use crate::{api::ApiError, cipher::CipherError};
+use crate::config::ConfigError;
 
 #[derive(Error, Debug)]
-pub enum PlasmaError {
+pub enum AppError {
     #[error(transparent)]
-    IoError( #[from] std::io::Error ),
-    #[error(transparent)]
-    ServerError( #[from] ApiError),
+    ClientError( #[from] PlasmaError),
     #[error(transparent)]
-    MessageCipherError( #[from] CipherError),
+    ConfigError( #[from] ConfigError),
     #[error(transparent)]
-    X3dhLibError( #[from] X3dhError),
+    IoError( #[from] std::io::Error ),
+    #[error("{0} failed: {1}")]
+    CommandFailed(&'static str, PlasmaError),
+    #[error("{0}")]
+    Aborted(String),
+}
+
+/// Maps an error to the action that failed, as shown to the user.
+pub fn failed<E: Into<PlasmaError>>(action: &'static str) -> impl FnOnce(E) -> AppError {
+    move |err| AppError::CommandFailed(action, err.into())
 }
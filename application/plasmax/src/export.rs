@@ -0,0 +1,236 @@
+use std::fmt::Write;
+use bson::oid::ObjectId;
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::ValueEnum;
+use serde::Serialize;
+use plasma_client::{account::{Account, Authorized}, api::{Api, body::{Cursor, MessagesBody}}, chats::Chat, session::{self, UNREADABLE}, Envelope, PlasmaError};
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Format {
+    Txt,
+    Json,
+    Markdown,
+    Html,
+}
+
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Txt => "txt",
+            Format::Json => "json",
+            Format::Markdown => "md",
+            Format::Html => "html",
+        }
+    }
+}
+
+/// Days to export, both ends included and in UTC.
+#[derive(Default, Clone, Copy)]
+pub struct DateRange {
+    pub since: Option<NaiveDate>,
+    pub until: Option<NaiveDate>,
+}
+
+impl DateRange {
+    /// First message timestamp in range, in microseconds like message timestamps.
+    fn start(&self) -> u64 {
+        self.since
+            .and_then(|day| day.and_hms_opt(0, 0, 0))
+            .map(|start| start.and_utc().timestamp_micros().max(0) as u64)
+            .unwrap_or(0)
+    }
+
+    fn contains(&self, timestamp: u64) -> bool {
+        let day = time(timestamp).date_naive();
+        self.since.is_none_or(|since| day >= since) && self.until.is_none_or(|until| day <= until)
+    }
+
+    /// Whether `timestamp` is after the last day, history is in order so nothing later is needed.
+    fn is_past(&self, timestamp: u64) -> bool {
+        let day = time(timestamp).date_naive();
+        self.until.is_some_and(|until| day > until)
+    }
+}
+
+/// Decrypted message of a transcript, edits are already applied.
+#[derive(Serialize)]
+pub struct ExportedMessage {
+    id: Option<String>,
+    sender: String,
+    timestamp: u64,
+    time: String,
+    content: String,
+    edited: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to: Option<String>,
+}
+
+fn time(timestamp: u64) -> DateTime<Utc> {
+    DateTime::from_timestamp_micros(timestamp as i64)
+        .unwrap_or_default()
+}
+
+/// Fetches and decrypts whole history of `chat` in `range`. Receipts,
+/// reactions, deleted and expired messages are left out. Messages carry
+/// no attachments yet, so a transcript is text only.
+pub async fn collect(api: &Api, account: &Account<Authorized>, chat: &Chat, range: DateRange) -> Result<Vec<ExportedMessage>, PlasmaError> {
+    let mut session = account.open_session(api, &chat.user.username).await?;
+    let mut messages: Vec<ExportedMessage> = Vec::new();
+    // Cursor is exclusive, a message sent right at midnight still belongs to the range.
+    let mut cursor = Cursor::Timestamp(range.start().saturating_sub(1));
+    loop {
+        let params = MessagesBody::after(chat.id, cursor);
+        let page = account.messages(api, &params).await?;
+        let now = session::timestamp();
+        for message in page.messages.iter() {
+            if range.is_past(message.timestamp) {
+                return Ok(messages);
+            }
+            cursor = Cursor::at(message.timestamp, message.id);
+            if message.deleted || message.expires_at.is_some_and(|at| at <= now) || !range.contains(message.timestamp) {
+                continue;
+            }
+            let sender = match message.sender_id == *account.id() {
+                true => account.username().clone(),
+                false => chat.user.username.clone(),
+            };
+            let content = message.payload_for(account.device());
+            let envelope = account.decrypt(api, &mut session, &sender, message.sender_device, content, message.timestamp).await?
+                .unwrap_or_else(|| Envelope::Text(String::from(UNREADABLE)));
+            let text = match envelope.text() {
+                Some(text) => text.to_owned(),
+                None => continue,
+            };
+            if let Some(original) = message.edit_of {
+                apply_edit(&mut messages, &original, &sender, text);
+                continue;
+            }
+            messages.push(ExportedMessage {
+                id: message.id.map(|id| id.to_hex()),
+                sender,
+                timestamp: message.timestamp,
+                time: time(message.timestamp).to_rfc3339(),
+                content: text,
+                edited: false,
+                reply_to: envelope.parent().map(|id| id.to_hex()),
+            });
+        }
+        if !page.has_more || page.messages.is_empty() {
+            return Ok(messages);
+        }
+    }
+}
+
+fn apply_edit(messages: &mut [ExportedMessage], original: &ObjectId, sender: &str, content: String) {
+    let original = original.to_hex();
+    let message = messages.iter_mut()
+        .find(|m| m.id.as_ref() == Some(&original) && m.sender == sender);
+    if let Some(message) = message {
+        message.content = content;
+        message.edited = true;
+    }
+}
+
+/// Transcript of the chat with `peer` in `format`.
+pub fn render(peer: &str, messages: &[ExportedMessage], format: Format) -> Result<String, PlasmaError> {
+    let mut out = String::new();
+    match format {
+        Format::Txt => {
+            for message in messages {
+                let _ = writeln!(out, "[{}] {}: {}{}", display_time(message), message.sender, message.content, suffix(message));
+            }
+        },
+        Format::Json => {
+            out = serde_json::to_string_pretty(messages)
+                .map_err(std::io::Error::from)?;
+            out.push('\n');
+        },
+        Format::Markdown => {
+            let _ = writeln!(out, "# Chat with {}\n", peer);
+            for message in messages {
+                let _ = writeln!(out, "- **{}** _{}_: {}{}", message.sender, display_time(message), message.content, suffix(message));
+            }
+        },
+        Format::Html => {
+            let _ = writeln!(out, "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Chat with {0}</title></head>\n<body>\n<h1>Chat with {0}</h1>\n<ul>", escape_html(peer));
+            for message in messages {
+                let _ = writeln!(out, "<li id=\"{}\"><b>{}</b> <time datetime=\"{}\">{}</time>: {}{}</li>",
+                    message.id.as_deref().unwrap_or_default(),
+                    escape_html(&message.sender),
+                    message.time,
+                    display_time(message),
+                    escape_html(&message.content),
+                    escape_html(&suffix(message)));
+            }
+            out.push_str("</ul>\n</body>\n</html>\n");
+        },
+    }
+    Ok(out)
+}
+
+fn display_time(message: &ExportedMessage) -> String {
+    time(message.timestamp).format("%Y-%m-%d %H:%M:%S UTC").to_string()
+}
+
+/// Edit and reply markers written after the content.
+fn suffix(message: &ExportedMessage) -> String {
+    let mut suffix = String::new();
+    if message.edited {
+        suffix.push_str(" (edited)");
+    }
+    if let Some(parent) = &message.reply_to {
+        let _ = write!(suffix, " (reply to {})", parent);
+    }
+    suffix
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod export_test {
+    use chrono::NaiveDate;
+    use super::{render, DateRange, ExportedMessage, Format};
+
+    fn message(content: &str) -> ExportedMessage {
+        ExportedMessage {
+            id: Some(String::from("1")),
+            sender: String::from("alice"),
+            timestamp: 1_700_000_000_000_000,
+            time: String::from("2023-11-14T22:13:20+00:00"),
+            content: content.to_owned(),
+            edited: true,
+            reply_to: None,
+        }
+    }
+
+    #[test]
+    fn range_includes_both_days() {
+        let day = NaiveDate::from_ymd_opt(2023, 11, 14);
+        let range = DateRange { since: day, until: day };
+
+        assert!(range.contains(1_700_000_000_000_000));
+        assert!(!range.contains(1_700_000_000_000_000 + 86_400_000_000));
+        assert!(!range.is_past(1_700_000_000_000_000));
+        assert!(range.is_past(1_700_000_000_000_000 + 86_400_000_000));
+        assert_eq!(range.start(), 1_699_920_000_000_000);
+    }
+
+    #[test]
+    fn formats() {
+        let messages = [message("<b>hi</b>")];
+
+        let txt = render("bob", &messages, Format::Txt).unwrap();
+        assert_eq!(txt, "[2023-11-14 22:13:20 UTC] alice: <b>hi</b> (edited)\n");
+        let html = render("bob", &messages, Format::Html).unwrap();
+        assert!(html.contains("&lt;b&gt;hi&lt;/b&gt; (edited)"));
+        let markdown = render("bob", &messages, Format::Markdown).unwrap();
+        assert!(markdown.starts_with("# Chat with bob\n"));
+        let json = render("bob", &messages, Format::Json).unwrap();
+        assert!(json.contains("\"edited\": true"));
+    }
+}
@@ -1,25 +1,25 @@
-#[allow(unused)]
 mod error;
-mod account;
-mod api;
-mod chats;
 mod tui;
-mod keyring;
-mod cipher;
+mod config;
+mod cli;
+mod export;
 
 use crate::tui::tools::Mode;
-use account::Authorized;
-use api::Api;
+use plasma_client::{account::Account, api::Api, backup, Client, KeyStore, Keyring, PlasmaError, Server};
+use std::sync::Arc;
 use ratatui::Terminal;
 use tui::app::App;
 use clap::{Parser, Subcommand};
-use error::PlasmaError;
+use error::{AppError, failed};
 use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::{
     io,
     time::{Duration, Instant},
 };
-use crate::account::Account;
+use crate::config::Config;
+use bson::oid::ObjectId;
+use chrono::NaiveDate;
 use crossterm::{
     event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind},
     execute,
@@ -30,6 +30,10 @@ use ratatui::prelude::{Backend, CrosstermBackend};
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    #[arg(short, long, global = true, help="Server profile from config.toml or server url")]
+    server: Option<String>,
+    #[arg(long, global = true, help="Print output as json, one object per line")]
+    json: bool,
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -49,43 +53,459 @@ enum Commands {
         #[arg(short, long, help="Unique username")]
         username: String,
     },
+
+    /// Verify the account email with the mailed token, or ask for a new one
+    Verify {
+        #[arg(required_unless_present = "resend", help="Verification token from the mail")]
+        token: Option<String>,
+        #[arg(long, value_name = "MAIL", conflicts_with = "token", help="Mail a new token to this account instead")]
+        resend: Option<String>,
+    },
+
+    /// Change the password, signing out other sessions of the account
+    Password {
+        #[arg(short, long, help="Mail to login with")]
+        mail: String,
+    },
+
+    /// Mail a password reset token to the account
+    ForgotPassword {
+        #[arg(short, long, help="Mail of the account")]
+        mail: String,
+    },
+
+    /// Choose a new password with a mailed reset token
+    ResetPassword {
+        #[arg(help="Reset token from the mail")]
+        token: String,
+    },
+
+    /// Export keys, chat secrets and contacts into an encrypted archive
+    Backup {
+        #[arg(short, long, help="Mail of the account to back up")]
+        mail: String,
+        #[arg(short, long, help="Archive file to write")]
+        output: PathBuf,
+    },
+
+    /// Import keys, chat secrets and contacts from an encrypted archive
+    Restore {
+        #[arg(short, long, help="Mail of the account to restore")]
+        mail: String,
+        #[arg(short, long, help="Archive file to read")]
+        input: PathBuf,
+        #[arg(long, help="Replace identity already present on this machine")]
+        force: bool,
+    },
+
+    /// List devices of the account, approving or removing them
+    Devices {
+        #[arg(short, long, help="Mail to login with")]
+        mail: String,
+        #[arg(long, conflicts_with = "remove", help="Link pending device with given id")]
+        approve: Option<String>,
+        #[arg(long, help="Unlink device with given id")]
+        remove: Option<String>,
+    },
+
+    /// List chats
+    Chats {
+        #[arg(short, long, help="Mail to login with")]
+        mail: String,
+    },
+
+    /// Send a message, starting the chat if needed
+    Send {
+        #[arg(short, long, help="Mail to login with")]
+        mail: String,
+        #[arg(help="Username to send to")]
+        user: String,
+        #[arg(help="Message text, - reads it from stdin")]
+        message: String,
+        #[arg(long, help="Id of the message to reply to")]
+        reply_to: Option<String>,
+    },
+
+    /// Print messages of a chat
+    Read {
+        #[arg(short, long, help="Mail to login with")]
+        mail: String,
+        #[arg(help="Username of the chat")]
+        user: String,
+        #[arg(long, help="Only messages newer than this timestamp, as printed")]
+        since: Option<u64>,
+    },
+
+    /// Print messages of a chat as they arrive
+    Follow {
+        #[arg(short, long, help="Mail to login with")]
+        mail: String,
+        #[arg(help="Username of the chat")]
+        user: String,
+    },
+
+    /// Write decrypted history of a chat to a file or stdout
+    Export {
+        #[arg(short, long, help="Mail to login with")]
+        mail: String,
+        #[arg(help="Username of the chat")]
+        user: String,
+        #[arg(value_enum, long, default_value = "txt", help="Transcript format")]
+        format: export::Format,
+        #[arg(short, long, help="File to write, stdout when not given")]
+        output: Option<PathBuf>,
+        #[arg(long, help="Only messages sent on or after this day, as YYYY-MM-DD in UTC")]
+        since: Option<NaiveDate>,
+        #[arg(long, help="Only messages sent on or before this day, as YYYY-MM-DD in UTC")]
+        until: Option<NaiveDate>,
+    },
+
+    /// Show or hide online state and last seen from contacts
+    Presence {
+        #[arg(short, long, help="Mail to login with")]
+        mail: String,
+        #[arg(value_enum, help="Whether contacts see when you are online")]
+        visibility: cli::Visibility,
+    },
+
+    /// Search users by the start of their name, to find whom to chat with
+    Users {
+        #[arg(short, long, help="Mail to login with")]
+        mail: String,
+        #[arg(help="Start of the username, at least 2 characters")]
+        query: String,
+    },
+
+    /// List contacts, adding or removing one first
+    Contacts {
+        #[arg(short, long, help="Mail to login with")]
+        mail: String,
+        #[arg(long, conflicts_with = "remove", help="Username to add, or to rename when already a contact")]
+        add: Option<String>,
+        #[arg(long, requires = "add", help="Nickname shown for the added contact")]
+        nickname: Option<String>,
+        #[arg(long, help="Username to remove")]
+        remove: Option<String>,
+    },
+
+    /// List blocked users, blocking or unblocking one first
+    Block {
+        #[arg(short, long, help="Mail to login with")]
+        mail: String,
+        #[arg(long, conflicts_with = "remove", help="Username to block")]
+        add: Option<String>,
+        #[arg(long, help="Username to unblock")]
+        remove: Option<String>,
+    },
+
+    /// Report a user to moderators with an excerpt of the conversation
+    Report {
+        #[arg(short, long, help="Mail to login with")]
+        mail: String,
+        #[arg(help="Username to report")]
+        user: String,
+        #[arg(help="Decrypted messages shared with moderators, - reads them from stdin")]
+        excerpt: String,
+        #[arg(long, default_value = "", help="What the user did")]
+        reason: String,
+        #[arg(long, help="Block the user as well")]
+        block: bool,
+    },
+
+    /// Show or hide the account in user search
+    Discovery {
+        #[arg(short, long, help="Mail to login with")]
+        mail: String,
+        #[arg(value_enum, help="Whether others find you by searching")]
+        visibility: cli::Visibility,
+    },
+
+    /// Write everything the server keeps about the account as json
+    ExportAccount {
+        #[arg(short, long, help="Mail to login with")]
+        mail: String,
+        #[arg(short, long, help="File to write, stdout when not given")]
+        output: Option<PathBuf>,
+    },
+
+    /// Delete the account on the server and its keys on this machine
+    DeleteAccount {
+        #[arg(short, long, help="Mail of the account to delete")]
+        mail: String,
+        #[arg(long, help="Don't ask for confirmation")]
+        yes: bool,
+    },
+
+    /// Show the logged in account
+    Whoami {
+        #[arg(short, long, help="Mail to login with")]
+        mail: String,
+    },
+
+    /// Forget the session token of an account
+    Logout {
+        #[arg(short, long, help="Mail to logout")]
+        mail: String,
+    },
 }
 
+/// Lets scripts provide secrets without a terminal.
+const PASSWORD_ENV: &str = "PLASMAX_PASSWORD";
+const PASSPHRASE_ENV: &str = "PLASMAX_PASSPHRASE";
+const NEW_PASSWORD_ENV: &str = "PLASMAX_NEW_PASSWORD";
+
 fn get_password() -> String {
+    if let Ok(pw) = std::env::var(PASSWORD_ENV) {
+        return pw;
+    }
     print!("Password: ");
     std::io::stdout().flush().unwrap();
     let pw = rpassword::read_password().unwrap();
     pw
 }
 
-async fn cli_get_accout(cli: Cli, api: &Api) -> Result<Option<Account<Authorized>>, PlasmaError> {
-    match &cli.command {
-        Some(Commands::Login { mail } ) => {
-            let acc = match Account::new(mail.clone()).try_login_token(&api).await {
-                Ok(a) => a,
-                Err(_) => {
-                    let pw = get_password();
-                    Account::new(mail.clone()).login(pw, &api).await.unwrap()
-                },
-            };
-            Ok(Some(acc))
+fn get_new_password() -> Result<String, AppError> {
+    if let Ok(pw) = std::env::var(NEW_PASSWORD_ENV) {
+        return Ok(pw);
+    }
+    let password = prompt_secret("New password: ");
+    if password != prompt_secret("Repeat new password: ") {
+        return Err(AppError::Aborted(String::from("Passwords don't match")));
+    }
+    Ok(password)
+}
+
+fn prompt_secret(prompt: &str) -> String {
+    print!("{}", prompt);
+    std::io::stdout().flush().unwrap();
+    rpassword::read_password().unwrap()
+}
+
+fn prompt_new_secret(prompt: &str) -> Result<String, AppError> {
+    let passphrase = prompt_secret(prompt);
+    if passphrase.is_empty() {
+        return Err(AppError::Aborted(String::from("Passphrase can't be empty")));
+    }
+    if passphrase != prompt_secret("Repeat passphrase: ") {
+        return Err(AppError::Aborted(String::from("Passphrases don't match")));
+    }
+    Ok(passphrase)
+}
+
+/// Asks for the keyring passphrase once per session, setting it up on first use.
+fn unlock_keyring(server: &Server, mail: &str) -> Result<Keyring, AppError> {
+    let passphrase = match (std::env::var(PASSPHRASE_ENV), Keyring::is_initialized(server, mail)?) {
+        (Ok(passphrase), _) => passphrase,
+        (Err(_), true) => prompt_secret("Keyring passphrase: "),
+        (Err(_), false) => {
+            println!("Choose a passphrase to encrypt local keys of {} on {}", mail, server.name());
+            prompt_new_secret("New keyring passphrase: ")?
         },
+    };
+    Ok(Keyring::unlock(server, mail, &passphrase)?)
+}
+
+fn backup_keyring(server: &Server, mail: &str, output: &Path) -> Result<(), AppError> {
+    let keyring = unlock_keyring(server, mail)?;
+    if keyring.read_identity().is_err() {
+        return Err(AppError::Aborted(format!("No identity for {} on this machine, nothing to back up", mail)));
+    }
+    let entries = keyring.export_entries()?;
+    let passphrase = prompt_new_secret("Backup passphrase: ")?;
+    backup::write(output, mail, entries, &passphrase)
+        .map_err(failed("Backup"))?;
+    println!("Backup written to {}", output.display());
+    Ok(())
+}
+
+fn restore_keyring(server: &Server, mail: &str, input: &Path, force: bool) -> Result<(), AppError> {
+    let keyring = unlock_keyring(server, mail)?;
+    if keyring.read_identity().is_ok() && !force {
+        return Err(AppError::Aborted(format!("{} already has an identity on this machine, use --force to replace it", mail)));
+    }
+    let passphrase = prompt_secret("Backup passphrase: ");
+    let entries = backup::read(input, mail, &passphrase)
+        .map_err(failed("Restore"))?;
+    keyring.import_entries(&entries)?;
+    println!("Restored keys of {}, you can now login", mail);
+    Ok(())
+}
+
+async fn login(server: &Server, mail: &str, api: &Api) -> Result<Client, AppError> {
+    let keys: Arc<dyn KeyStore> = Arc::new(unlock_keyring(server, mail)?);
+    let result = match Account::new(mail.to_owned(), keys.clone()).try_login_token(api).await {
+        Err(PlasmaError::DevicePending(device)) => Err(PlasmaError::DevicePending(device)),
+        Err(_) => {
+            let pw = get_password();
+            Account::new(mail.to_owned(), keys).login(pw, api).await
+        },
+        result => result,
+    };
+    match result {
+        Ok(a) => Ok(Client::new(server.clone(), api.clone(), a)),
+        Err(err @ PlasmaError::DevicePending(device)) => Err(AppError::Aborted(format!(
+            "{}\nApprove it from a linked device: plasmax --server {} devices --mail {} --approve {}",
+            err, server.name(), mail, device.to_hex(),
+        ))),
+        Err(err) => Err(AppError::CommandFailed("Login", err)),
+    }
+}
+
+async fn manage_devices(server: &Server, mail: &str, approve: Option<&str>, remove: Option<&str>, api: &Api) -> Result<(), AppError> {
+    let client = login(server, mail, api).await?;
+    let acc = client.account();
+    let target = approve.or(remove)
+        .map(ObjectId::parse_str)
+        .transpose()
+        .map_err(|_| AppError::Aborted(String::from("Invalid device id")))?;
+    let result = match (target, approve.is_some()) {
+        (Some(target), true) => api.approve_device(acc.token(), acc.device(), &target).await,
+        (Some(target), false) => api.remove_device(acc.token(), acc.device(), &target).await,
+        (None, _) => Ok(()),
+    };
+    result.map_err(failed("Device change"))?;
+    for device in api.devices(acc.token()).await.map_err(failed("Listing devices"))? {
+        let current = match device.id == *acc.device() {
+            true => " (this device)",
+            false => "",
+        };
+        let pending = match device.approved {
+            true => "",
+            false => " [pending]",
+        };
+        println!("{}  {}{}{}", device.id.to_hex(), device.name, current, pending);
+    }
+    Ok(())
+}
+
+/// Unverified accounts can log in but not start chats.
+async fn verify_email(server: &Server, token: Option<&str>, resend: Option<&str>, api: &Api) -> Result<(), AppError> {
+    match (token, resend) {
+        (_, Some(mail)) => {
+            let client = login(server, mail, api).await?;
+            match client.resend_verification().await.map_err(failed("Resending"))? {
+                true => println!("Sent a new verification token to {}", mail),
+                false => println!("{} is verified already", mail),
+            }
+        },
+        (Some(token), None) => {
+            api.verify_email(token).await.map_err(failed("Verification"))?;
+            println!("Email verified, you can now start chats");
+        },
+        (None, None) => {},
+    }
+    Ok(())
+}
+
+/// Scripting subcommands, they print their result and exit without opening the TUI.
+async fn run_command(command: &Commands, json: bool, server: &Server, api: &Api) -> Result<(), AppError> {
+    let mail = match command {
+        Commands::Chats { mail }
+        | Commands::Send { mail, .. }
+        | Commands::Read { mail, .. }
+        | Commands::Follow { mail, .. }
+        | Commands::Export { mail, .. }
+        | Commands::Presence { mail, .. }
+        | Commands::Users { mail, .. }
+        | Commands::Contacts { mail, .. }
+        | Commands::Discovery { mail, .. }
+        | Commands::Block { mail, .. }
+        | Commands::Report { mail, .. }
+        | Commands::ExportAccount { mail, .. }
+        | Commands::DeleteAccount { mail, .. }
+        | Commands::Password { mail }
+        | Commands::Whoami { mail } => mail,
+        _ => return Ok(()),
+    };
+    let mut client = login(server, mail, api).await?;
+    match command {
+        Commands::Chats { .. } => cli::chats(&client, json).await,
+        Commands::Send { user, message, reply_to, .. } => cli::send(&client, user, message, reply_to.as_deref(), json).await,
+        Commands::Read { user, since, .. } => cli::read(&client, user, *since, json).await,
+        Commands::Follow { user, .. } => cli::follow(&client, user, json).await,
+        Commands::Export { user, format, output, since, until, .. } => {
+            let range = export::DateRange { since: *since, until: *until };
+            cli::export(&client, user, *format, output.as_deref(), range).await
+        },
+        Commands::Presence { visibility, .. } => cli::presence(&client, *visibility, json).await,
+        Commands::Users { query, .. } => cli::users(&client, query, json).await,
+        Commands::Contacts { add, nickname, remove, .. } => cli::contacts(&client, add.as_deref(), nickname.as_deref(), remove.as_deref(), json).await,
+        Commands::Discovery { visibility, .. } => cli::discovery(&client, *visibility, json).await,
+        Commands::Block { add, remove, .. } => cli::block(&client, add.as_deref(), remove.as_deref(), json).await,
+        Commands::Report { user, excerpt, reason, block, .. } => cli::report(&client, user, excerpt, reason, *block, json).await,
+        Commands::ExportAccount { output, .. } => cli::export_account(&client, output.as_deref()).await,
+        Commands::DeleteAccount { yes, .. } => cli::delete_account(client, mail, *yes, json).await,
+        Commands::Password { .. } => cli::change_password(&mut client, json).await,
+        Commands::Whoami { .. } => cli::whoami(&client, json),
+        _ => Ok(()),
+    }
+}
+
+async fn cli_get_accout(cli: Cli, server: &Server, api: &Api) -> Result<Option<Client>, AppError> {
+    match &cli.command {
+        Some(Commands::Login { mail } ) => login(server, mail, api).await.map(Some),
         Some(Commands::Register { mail, username } ) => {
             let pw = get_password();
-            api.register(mail, username, pw).await?;
+            api.register(mail, username, pw).await.map_err(failed("Registration"))?;
+            println!("Registered {}, verify {} with the mailed token: plasmax verify <TOKEN>", username, mail);
+            Ok(None)
+        },
+        Some(Commands::Verify { token, resend }) => {
+            verify_email(server, token.as_deref(), resend.as_deref(), api).await?;
+            Ok(None)
+        },
+        Some(Commands::ForgotPassword { mail }) => {
+            api.forgot_password(mail).await.map_err(failed("Reset"))?;
+            println!("If {} has an account, a reset token is on its way", mail);
+            Ok(None)
+        },
+        Some(Commands::ResetPassword { token }) => {
+            let pw = get_new_password()?;
+            api.reset_password(token, pw).await.map_err(failed("Reset"))?;
+            println!("Password changed, you can now login");
+            Ok(None)
+        },
+        Some(Commands::Backup { mail, output }) => {
+            backup_keyring(server, mail, output)?;
+            Ok(None)
+        },
+        Some(Commands::Restore { mail, input, force }) => {
+            restore_keyring(server, mail, input, *force)?;
+            Ok(None)
+        },
+        Some(Commands::Devices { mail, approve, remove }) => {
+            manage_devices(server, mail, approve.as_deref(), remove.as_deref(), api).await?;
+            Ok(None)
+        },
+        Some(Commands::Logout { mail }) => {
+            cli::logout(&unlock_keyring(server, mail)?, mail, cli.json)?;
+            Ok(None)
+        },
+        Some(command) => {
+            run_command(command, cli.json, server, api).await?;
             Ok(None)
         },
         None => Ok(None),
     }
 }
 
+/// Failures go to stderr with a non-zero exit, so scripts can tell them apart
+/// from output.
 #[tokio::main]
-async fn main() -> Result<(), PlasmaError> {
-    let api = Api::new();
+async fn main() {
+    if let Err(err) = run().await {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
+
+async fn run() -> Result<(), AppError> {
     let cli = Cli::parse();
+    let server = Config::load()?.server(cli.server.as_deref())?;
+    let api = Api::new(server.api_url());
 
-    let acc = match cli_get_accout(cli, &api).await? {
-        Some(a) => a,
+    let client = match cli_get_accout(cli, &server, &api).await? {
+        Some(c) => c,
         None => return Ok(()),
     };
 
@@ -96,7 +516,7 @@ async fn main() -> Result<(), PlasmaError> {
     let mut terminal = Terminal::new(backend)?;
 
     let tick_rate = Duration::from_millis(1000);
-    let app = App::new(api, acc).await?;
+    let app = App::new(client).await?;
     let res = run_app(&mut terminal, app, tick_rate).await;
 
     disable_raw_mode()?;
@@ -133,7 +553,7 @@ async fn run_app<B: Backend>(
                         return Ok(());
                     }
                     match key.code {
-                        KeyCode::Esc => app.mode = Mode::Normal,
+                        KeyCode::Esc => app.escape(),
                         _ => {
                             app.handle_evt(key.code).await;
                         },
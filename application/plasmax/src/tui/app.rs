@@ -1,8 +1,40 @@
+use std::{collections::{HashMap, hash_map::Entry}, time::{Duration, Instant}};
 use bson::oid::ObjectId;
 use crossterm::event::KeyCode;
-use crate::{api::{Api, ws::{ThreadComm, Ws, WsMessage}}, account::{Account, Authorized}, error::PlasmaError, chats::Chat, cipher::Cipher};
-use super::tools::{Mode, StatefulList, UserInput, MessagesBuffer, ErrorMessage};
-use std::time::{SystemTime, UNIX_EPOCH};
+use home::home_dir;
+use tokio::sync::mpsc::error::TryRecvError;
+use plasma_client::{api::{Api, ApiError, ws::{ConnectionState, ThreadComm, Ws, WsFrame, WsMessage, WsPayload}, body::{MessagesBody, Cursor}, response::{self, BlockedUser, Contact}, body::ReportBody}, account::{Account, Authorized}, chats::{Chat, UserHandle}, keyring::write_private, search::SearchIndex, session::{self, ChatSession, UNREADABLE}, store::{CachedHistory, MessageStatus, Store}, Client, Envelope, PlasmaError};
+use crate::export::{self, DateRange, Format};
+use super::tools::{Mode, StatefulList, UserInput, MessagesBuffer, ErrorMessage, Message, Edit, Reaction, SearchResult, Suggestion, suggestions};
+
+/// Decrypted page of history, edits, reactions, receipts and timer changes in
+/// it are applied once its messages are shown.
+#[derive(Default)]
+struct DecryptedPage {
+    messages: Vec<Message>,
+    edits: Vec<Edit>,
+    reactions: Vec<Reaction>,
+    read_up_to: Option<u64>,
+    timer: Option<Option<u64>>,
+}
+
+/// Typing indicator of a peer disappears when no new event came in this long.
+const TYPING_TIMEOUT: Duration = Duration::from_secs(5);
+/// Typing events are sent at most this often while composing.
+const TYPING_INTERVAL: Duration = Duration::from_secs(3);
+/// Disappearing messages timers cycled through in the chat, in seconds.
+const TIMERS: [Option<u64>; 6] = [None, Some(30), Some(5 * 60), Some(60 * 60), Some(24 * 60 * 60), Some(7 * 24 * 60 * 60)];
+/// Reactions toggled on the selected message with keys `1` to `5`.
+const REACTIONS: [&str; 5] = ["👍", "❤", "😂", "😮", "😢"];
+/// Server only searches users once this many characters were typed.
+const USER_SEARCH_MIN_LEN: usize = 2;
+/// Messages before the reported one shared with moderators for context.
+const REPORT_CONTEXT: usize = 4;
+/// Format of transcripts exported from the open chat with `w`.
+const EXPORT_FORMAT: Format = Format::Markdown;
+/// Keys shown in the help line of the modes that have more than moving around.
+const NORMAL_KEYS: &str = "b chats | n new chat | s scroll | m message | / search";
+const SCROLL_KEYS: &str = "K/J select | e edit | d delete | x delete here | r reply | v thread | 1-5 react | t timer | w export | R report | q unselect";
 
 pub struct App {
     pub api: Api,
@@ -12,17 +44,44 @@ pub struct App {
     pub new_chat_input: UserInput,
     pub message_input: UserInput,
     pub messages_buffer: MessagesBuffer,
-    pub comms: ThreadComm<WsMessage>,
-    pub cipher: Option<Cipher>,
+    pub search_input: UserInput,
+    pub search_results: StatefulList<SearchResult>,
+    contacts: Vec<Contact>,
+    blocked: Vec<BlockedUser>,
+    pub suggestions: Vec<Suggestion>,
+    search_index: SearchIndex,
+    pub comms: ThreadComm<WsFrame>,
+    connection: ConnectionState,
+    resume_from: Option<(u64, Option<ObjectId>)>,
+    pub session: Option<ChatSession>,
+    online: HashMap<ObjectId, bool>,
+    typing: HashMap<ObjectId, Instant>,
+    typing_sent: Option<Instant>,
+    editing: Option<ObjectId>,
+    replying: Option<ObjectId>,
+    deleting: Option<ObjectId>,
+    notice: Option<String>,
     pub error_message: ErrorMessage,
+    pub offline: bool,
+    chats_synced: bool,
 }
 
 impl App {
-    pub async fn new(api: Api, account: Account<Authorized>) -> Result<App, PlasmaError> {
-        let chats = account.chats(&api).await?.chats;
+    pub async fn new(client: Client) -> Result<App, PlasmaError> {
+        let (server, api, account) = client.into_parts();
+        let store = Self::account_store(&account);
+        let chats = store.read_chats()?;
+        let mut search_index = store.read_index()?;
+        // Histories cached before the index existed are indexed once.
+        for chat in chats.iter() {
+            if !search_index.has_chat(&chat.id) {
+                search_index.index_chat(&chat.id, &store.read_history(&chat.id)?);
+            }
+        }
         let un = account.username().clone();
-        let ws = Ws::new("ws://localhost:8000/chat", account.token());
+        let ws = Ws::new(&server.ws_url(), account.token(), account.device());
         let comms = ws.run().await;
+        let connection = *comms.state.borrow();
         let app = App {
             api,
             account,
@@ -31,31 +90,255 @@ impl App {
             new_chat_input: UserInput::new(),
             message_input: UserInput::new(),
             messages_buffer: MessagesBuffer::new(un),
+            search_input: UserInput::new(),
+            search_results: StatefulList::with_items(Vec::new()),
+            contacts: Vec::new(),
+            blocked: Vec::new(),
+            suggestions: Vec::new(),
+            search_index,
             comms,
-            cipher: None,
+            connection,
+            resume_from: None,
+            session: None,
+            online: HashMap::new(),
+            typing: HashMap::new(),
+            typing_sent: None,
+            editing: None,
+            replying: None,
+            deleting: None,
+            notice: None,
             error_message: ErrorMessage::default(),
+            offline: false,
+            chats_synced: false,
         };
         Ok(app)
     }
 
+    fn account_store(account: &Account<Authorized>) -> &Store {
+        account.store()
+            .expect("Keyring accounts keep a local cache")
+    }
+
+    fn store(&self) -> &Store {
+        Self::account_store(&self.account)
+    }
+
     pub async fn on_tick(&mut self) {
         if let Err(e) = self.on_tick_impl().await {
-            self.error_message.set(&format!("{}", e));
+            self.report(e);
+        }
+    }
+
+    fn report(&mut self, err: PlasmaError) {
+        match err.is_offline() {
+            true => self.offline = true,
+            false => self.error_message.set(&format!("{}", err)),
         }
     }
+
     pub async fn on_tick_impl(&mut self) -> Result<(), PlasmaError> {
-        let message = match self.comms.receiver.try_recv() {
-            Ok(mess) => mess,
+        if !self.chats_synced {
+            self.sync_chats().await?;
+        }
+        if self.messages_buffer.purge_expired(session::timestamp()) {
+            self.persist_history()?;
+        }
+        if self.comms.state.has_changed().unwrap_or(false) {
+            let state = *self.comms.state.borrow_and_update();
+            self.on_connection_change(state).await?;
+        }
+        loop {
+            let frame = match self.comms.receiver.try_recv() {
+                Ok(frame) => frame,
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => return Ok(()),
+            };
+            self.on_frame(frame).await?;
+        }
+    }
+
+    async fn on_frame(&mut self, frame: WsFrame) -> Result<(), PlasmaError> {
+        match frame {
+            WsFrame::Message(message) => self.on_message(message).await,
+            WsFrame::Stored { chat_id, id, timestamp } => {
+                let id = ObjectId::parse_str(id).ok();
+                self.update_history(&chat_id, |buffer| buffer.stored(timestamp, id))
+            },
+            WsFrame::Delivered { chat_id, timestamp, .. } => {
+                self.update_history(&chat_id, |buffer| buffer.set_status(timestamp, MessageStatus::Delivered))
+            },
+            WsFrame::Typing { chat_id, .. } => {
+                if let Ok(chat_id) = ObjectId::parse_str(&chat_id) {
+                    self.typing.insert(chat_id, Instant::now());
+                }
+                Ok(())
+            },
+            WsFrame::Presence { user_id, online, .. } => {
+                if let Ok(user_id) = ObjectId::parse_str(&user_id) {
+                    self.online.insert(user_id, online);
+                }
+                Ok(())
+            },
+            WsFrame::Deleted { chat_id, message_id } => match ObjectId::parse_str(message_id) {
+                Ok(message_id) => self.update_history(&chat_id, |buffer| buffer.mark_deleted(&message_id)),
+                Err(_) => Ok(()),
+            },
+        }
+    }
+
+    pub fn is_online(&self, user_id: &ObjectId) -> bool {
+        self.online.get(user_id).copied().unwrap_or(false)
+    }
+
+    pub fn is_typing(&self, chat_id: &ObjectId) -> bool {
+        self.typing.get(chat_id).is_some_and(|at| at.elapsed() < TYPING_TIMEOUT)
+    }
+
+    /// Lets the peer know a message is being composed, throttled to `TYPING_INTERVAL`.
+    async fn notify_typing(&mut self) -> Result<(), PlasmaError> {
+        let chat_id = match self.items.get() {
+            Some(chat) => chat.id,
+            None => return Ok(()),
+        };
+        if self.typing_sent.is_some_and(|at| at.elapsed() < TYPING_INTERVAL) {
+            return Ok(());
+        }
+        self.typing_sent = Some(Instant::now());
+        self.send_frame(WsFrame::typing(&chat_id)).await
+    }
+
+    async fn on_message(&mut self, message: WsMessage) -> Result<(), PlasmaError> {
+        let chat = match self.items.get() {
+            Some(chat) if chat.id.to_hex() == message.chat_id => chat.clone(),
+            _ => return Ok(()),
+        };
+        let sender_device = ObjectId::parse_str(&message.sender_device).ok();
+        let content = message.payload_for(self.account.device());
+        let username = match message.sender_id == self.account.id().to_hex() {
+            true => self.account.username().clone(),
+            false => chat.user.username.clone(),
+        };
+        let envelope = self.decrypt(&username, sender_device, content, message.timestamp).await?;
+        let parent = envelope.parent();
+        match envelope {
+            Envelope::Text(text) | Envelope::Reply { text, .. } => match message.edit_of() {
+                Some(original) => self.messages_buffer.apply_edit(Edit { original, username, content: text, timestamp: message.timestamp }),
+                None => {
+                    self.typing.remove(&chat.id);
+                    let received = self.received(&username, message.id(), &text, message.timestamp)
+                        .with_reply_to(parent)
+                        .with_expiry(message.expires_at);
+                    self.messages_buffer.push(received);
+                    self.send_read_receipt().await?;
+                },
+            },
+            Envelope::Read { up_to } if username != *self.account.username() => self.messages_buffer.mark_read(up_to),
+            Envelope::Read { .. } => {},
+            Envelope::Timer { seconds } => self.messages_buffer.set_timer(seconds),
+            Envelope::React { target, emoji, add } => {
+                let target = ObjectId::from_bytes(target);
+                self.messages_buffer.react(Reaction { target, username, emoji, timestamp: message.timestamp, add });
+            },
+        }
+        self.persist_history()
+    }
+
+    /// Applies `update` to the history of a chat, whether it is open or only cached.
+    fn update_history(&mut self, chat_id: &str, update: impl FnOnce(&mut MessagesBuffer)) -> Result<(), PlasmaError> {
+        let chat_id = match ObjectId::parse_str(chat_id) {
+            Ok(chat_id) => chat_id,
             Err(_) => return Ok(()),
         };
-        let decrypted = self.cipher
-            .as_ref()
-            .expect("Cipher should be some if messages are read")
-            .decrypt(&message.content, message.timestamp)?;
-        self.messages_buffer.push("other", &decrypted);
+        if self.items.get().is_some_and(|chat| chat.id == chat_id) {
+            update(&mut self.messages_buffer);
+            return self.persist_history();
+        }
+        let mut buffer = MessagesBuffer::new(self.account.username().clone());
+        buffer.restore(self.store().read_history(&chat_id)?);
+        update(&mut buffer);
+        self.save_history(&chat_id, &buffer.to_history())
+    }
+
+    /// Caches history of a chat and indexes it for search.
+    fn save_history(&mut self, chat_id: &ObjectId, history: &CachedHistory) -> Result<(), PlasmaError> {
+        self.store().save_history(chat_id, history)?;
+        self.search_index.index_chat(chat_id, history);
+        self.store().save_index(&self.search_index)?;
         Ok(())
     }
 
+    /// Own messages seen from the server were stored at least.
+    fn received(&self, username: &str, id: Option<ObjectId>, content: &str, timestamp: u64) -> Message {
+        let message = Message::new(id, username, content, timestamp);
+        match username == self.account.username() {
+            true => message.with_status(MessageStatus::Stored),
+            false => message,
+        }
+    }
+
+    /// Tells the peer everything up to its newest message in the open chat was read.
+    /// The receipt disappears like messages do, callers persist how far it went.
+    async fn send_read_receipt(&mut self) -> Result<(), PlasmaError> {
+        let (chat_id, up_to) = match (self.items.get(), self.messages_buffer.newest_peer_timestamp()) {
+            (Some(chat), Some(up_to)) => (chat.id, up_to),
+            _ => return Ok(()),
+        };
+        if self.session.is_none() || self.messages_buffer.read_sent().is_some_and(|sent| sent >= up_to) {
+            return Ok(());
+        }
+        let mut ws_message = self.make_message(&Envelope::Read { up_to }, &chat_id)?;
+        ws_message.expires_at = session::expiry(ws_message.timestamp, self.messages_buffer.timer());
+        self.send_frame(WsFrame::Message(ws_message)).await?;
+        self.messages_buffer.set_read_sent(up_to);
+        Ok(())
+    }
+
+    async fn send_frame(&self, frame: WsFrame) -> Result<(), PlasmaError> {
+        self.comms.sender
+            .send(frame)
+            .await
+            .map_err(|_| PlasmaError::Disconnected)
+    }
+
+    /// Remembers where the open chat was when the connection dropped and
+    /// fetches everything missed since then once it is back.
+    async fn on_connection_change(&mut self, state: ConnectionState) -> Result<(), PlasmaError> {
+        let was = std::mem::replace(&mut self.connection, state);
+        match state {
+            ConnectionState::Reconnecting { .. } if was.is_connected() => {
+                self.resume_from = self.messages_buffer.newest_position();
+            },
+            ConnectionState::Connected if self.chats_synced => {
+                self.sync_chats().await?;
+                let since = self.resume_from.take().or(self.messages_buffer.newest_position());
+                self.load_messages_since(since).await?;
+                self.send_read_receipt().await?;
+                self.persist_history()?;
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+
+    async fn sync_chats(&mut self) -> Result<(), PlasmaError> {
+        let chats = self.account.chats(&self.api).await?.chats;
+        self.store().save_chats(&chats)?;
+        self.items.set_items(chats);
+        self.contacts = self.api.contacts(self.account.token()).await?;
+        self.blocked = self.api.blocked(self.account.token()).await?;
+        self.chats_synced = true;
+        self.offline = false;
+        Ok(())
+    }
+
+    fn persist_history(&mut self) -> Result<(), PlasmaError> {
+        let chat_id = match self.items.get() {
+            Some(chat) => chat.id,
+            None => return Ok(()),
+        };
+        let history = self.messages_buffer.to_history();
+        self.save_history(&chat_id, &history)
+    }
+
     pub fn calculate_scroll(&self, area_height: u16, text_height: u16) -> u16 {
         let scroll = if text_height < area_height - 2 { 0 } else { text_height + 2 - area_height };
         let scroll = scroll.saturating_sub(self.messages_buffer.scroll_get());
@@ -63,20 +346,35 @@ impl App {
     }
 
     pub fn get_small_help(&self) -> String {
-        format!("Mode: {}", self.mode)
+        let help = match self.offline && self.connection.is_connected() {
+            true => format!("Mode: {} | {} | Offline", self.mode, self.connection),
+            false => format!("Mode: {} | {}", self.mode, self.connection),
+        };
+        let help = match (self.editing, self.replying) {
+            (Some(_), _) => format!("{} | Editing", help),
+            (None, Some(_)) => format!("{} | Replying", help),
+            (None, None) => help,
+        };
+        match (&self.notice, &self.mode) {
+            (Some(notice), _) => format!("{} | {}", help, notice),
+            (None, Mode::Normal) => format!("{} | {}", help, NORMAL_KEYS),
+            (None, Mode::ChatScroll) => format!("{} | {}", help, SCROLL_KEYS),
+            (None, _) => help,
+        }
     }
 
     pub async fn handle_evt(&mut self, key: KeyCode) -> bool {
         match self.handle_evt_impl(key).await {
             Ok(res) => res,
             Err(e) => {
-                self.error_message.set(&format!("{}", e));
+                self.report(e);
                 false
             }
         }
     }
 
     async fn handle_evt_impl(&mut self, key: KeyCode) -> Result<bool, PlasmaError> {
+        self.notice = None;
         if self.error_message.is_err() {
             self.error_message.clear();
             return Ok(true);
@@ -85,7 +383,8 @@ impl App {
             Mode::Normal => self.handle_evt_normal(key),
             Mode::BrowseChats => self.handle_evt_browse_chats(key).await,
             Mode::NewChat | Mode::Message => self.handle_evt_input(key).await,
-            Mode::ChatScroll => self.handle_evt_scroll(key),
+            Mode::ChatScroll => self.handle_evt_scroll(key).await,
+            Mode::Search => self.handle_evt_search(key).await,
         }
     }
 
@@ -95,6 +394,7 @@ impl App {
             KeyCode::Down | KeyCode::Char('j')  => self.items.next(),
             KeyCode::Up | KeyCode::Char('k')  => self.items.previous(),
             KeyCode::Enter => self.init_message_buffer().await?,
+            KeyCode::Char('B') => self.toggle_block().await?,
             _ => return Ok(false),
         }
         return Ok(true);
@@ -107,27 +407,133 @@ impl App {
         }
         let chat = self.items
             .get()
-            .expect("Has value because select returns true");
-        self.account
-            .ensure_secret(&self.api, &chat.id, &chat.user.username)
-            .await?;
-        let cipher = self.account
-            .get_cipher(&chat.user.username)?;
-        self.cipher = Some(cipher);
-        let oid = self.account.id();
+            .expect("Has value because select returns true")
+            .clone();
+        self.session = None;
         self.messages_buffer = MessagesBuffer::new(self.account.username().clone());
-        for message in self.account.messages(&self.api, &chat.id).await?.iter() {
-            let username = match message.sender_id == *oid {
+        self.messages_buffer.restore(self.store().read_history(&chat.id)?);
+        self.messages_buffer.purge_expired(session::timestamp());
+        let session = self.account
+            .open_session(&self.api, &chat.user.username)
+            .await?;
+        self.session = Some(session);
+        self.load_messages_since(self.messages_buffer.newest_position()).await?;
+        self.send_read_receipt().await?;
+        self.persist_history()
+    }
+
+    async fn load_older_messages(&mut self) -> Result<(), PlasmaError> {
+        let (chat, oldest) = match (self.items.get(), self.messages_buffer.oldest_id()) {
+            (Some(chat), Some(oldest)) => (chat.clone(), oldest),
+            _ => return Ok(()),
+        };
+        let params = MessagesBody::before(chat.id, Cursor::Id(oldest));
+        let page = self.account.messages(&self.api, &params).await?;
+        let mut decrypted = self.decrypt_messages(&chat.user.username, &page.messages).await?;
+        self.messages_buffer.prepend(std::mem::take(&mut decrypted.messages), page.has_more);
+        self.apply_page(decrypted);
+        self.persist_history()
+    }
+
+    /// Fetches every message after `since`, or the latest page when there is nothing yet.
+    async fn load_messages_since(&mut self, mut since: Option<(u64, Option<ObjectId>)>) -> Result<(), PlasmaError> {
+        let chat = match self.items.get() {
+            Some(chat) => chat.clone(),
+            None => return Ok(()),
+        };
+        loop {
+            let params = match since {
+                Some((timestamp, id)) => MessagesBody::after(chat.id, Cursor::at(timestamp, id)),
+                None => MessagesBody::latest(chat.id),
+            };
+            let page = self.account.messages(&self.api, &params).await?;
+            let mut decrypted = self.decrypt_messages(&chat.user.username, &page.messages).await?;
+            match params.after {
+                Some(_) => self.messages_buffer.merge(std::mem::take(&mut decrypted.messages)),
+                None => self.messages_buffer.prepend(std::mem::take(&mut decrypted.messages), page.has_more),
+            }
+            self.apply_page(decrypted);
+            since = page.messages.last().map(|m| (m.timestamp, m.id)).or(since);
+            if params.after.is_none() || !page.has_more {
+                return Ok(());
+            }
+        }
+    }
+
+    fn apply_page(&mut self, page: DecryptedPage) {
+        for edit in page.edits {
+            self.messages_buffer.apply_edit(edit);
+        }
+        if let Some(up_to) = page.read_up_to {
+            self.messages_buffer.mark_read(up_to);
+        }
+        for reaction in page.reactions {
+            self.messages_buffer.react(reaction);
+        }
+        if let Some(seconds) = page.timer {
+            self.messages_buffer.set_timer(seconds);
+        }
+    }
+
+    /// Decrypts a page of history. Deleted messages are kept as placeholders
+    /// while their edits are dropped.
+    async fn decrypt_messages(&mut self, member: &str, messages: &[response::Message]) -> Result<DecryptedPage, PlasmaError> {
+        let mut page = DecryptedPage::default();
+        for message in messages {
+            let username = match message.sender_id == *self.account.id() {
                 true => self.account.username().clone(),
-                false => chat.user.username.clone(),
+                false => member.to_owned(),
             };
-            let decrypted = self.cipher
-                .as_ref()
-                .expect("Cipher should be some if messages are read")
-                .decrypt(&message.message, message.timestamp)?;
-            self.messages_buffer.push(&username, &decrypted);
+            if message.deleted {
+                if message.edit_of.is_none() {
+                    page.messages.push(self.received(&username, message.id, "", message.timestamp).into_deleted());
+                }
+                continue;
+            }
+            let content = message.payload_for(self.account.device());
+            let envelope = self.decrypt(&username, message.sender_device, content, message.timestamp).await?;
+            let parent = envelope.parent();
+            match (envelope, message.edit_of) {
+                (Envelope::Text(content) | Envelope::Reply { text: content, .. }, Some(original)) => page.edits.push(Edit { original, username, content, timestamp: message.timestamp }),
+                (Envelope::Text(text) | Envelope::Reply { text, .. }, None) => {
+                    let received = self.received(&username, message.id, &text, message.timestamp)
+                        .with_reply_to(parent)
+                        .with_expiry(message.expires_at);
+                    page.messages.push(received);
+                },
+                (Envelope::Read { up_to }, _) if username == member => page.read_up_to = page.read_up_to.max(Some(up_to)),
+                (Envelope::Read { .. }, _) => {},
+                (Envelope::Timer { seconds }, _) => page.timer = Some(seconds),
+                (Envelope::React { target, emoji, add }, _) => {
+                    let target = ObjectId::from_bytes(target);
+                    page.reactions.push(Reaction { target, username, emoji, timestamp: message.timestamp, add });
+                },
+            }
         }
-        Ok(())
+        Ok(page)
+    }
+
+    async fn decrypt(&mut self, sender: &str, sender_device: Option<ObjectId>, content: Option<&[u8]>, timestamp: u64) -> Result<Envelope, PlasmaError> {
+        let unreadable = || Envelope::Text(String::from(UNREADABLE));
+        let session = match self.session.as_mut() {
+            Some(session) => session,
+            None => return Ok(unreadable()),
+        };
+        let decrypted = self.account
+            .decrypt(&self.api, session, sender, sender_device, content, timestamp)
+            .await?;
+        Ok(decrypted.unwrap_or_else(unreadable))
+    }
+
+    /// Back to normal mode, dropping an edit or reply in progress.
+    pub fn escape(&mut self) {
+        if self.editing.take().is_some() {
+            self.message_input.submit();
+        }
+        self.replying = None;
+        self.suggestions.clear();
+        self.messages_buffer.unselect();
+        self.mode = Mode::Normal;
     }
 
     fn handle_evt_normal(&mut self, key: KeyCode) -> Result<bool, PlasmaError> {
@@ -135,6 +541,11 @@ impl App {
             KeyCode::Char('b') => Mode::BrowseChats,
             KeyCode::Char('n') => Mode::NewChat,
             KeyCode::Char('s') => Mode::ChatScroll,
+            KeyCode::Char('/') => {
+                self.search_input.submit();
+                self.search_results = StatefulList::with_items(Vec::new());
+                Mode::Search
+            },
             KeyCode::Char('m') => {
                 match self.items.get() {
                     Some(_) => Mode::Message,
@@ -161,9 +572,22 @@ impl App {
             },
             KeyCode::Char(to_insert) => {
                 input.enter_char(to_insert);
+                match self.mode {
+                    Mode::Message => self.notify_typing().await?,
+                    _ => self.suggest().await?,
+                }
             }
             KeyCode::Backspace => {
                 input.delete_char();
+                if self.mode == Mode::NewChat {
+                    self.suggest().await?;
+                }
+            }
+            KeyCode::Tab if self.mode == Mode::NewChat => {
+                if let Some(suggestion) = self.suggestions.first() {
+                    self.new_chat_input.set(&suggestion.username);
+                    self.suggestions.clear();
+                }
             }
             KeyCode::Left => {
                 input.move_cursor_left();
@@ -178,6 +602,145 @@ impl App {
         return Ok(true);
     }
 
+    /// Offers contacts and users found by the server for the username typed so far.
+    async fn suggest(&mut self) -> Result<(), PlasmaError> {
+        let query = self.new_chat_input.input.trim().to_owned();
+        let found = match query.chars().count() >= USER_SEARCH_MIN_LEN {
+            true => match self.api.search_users(self.account.token(), &query).await {
+                Ok(found) => found,
+                // Typing on is not an error, names the server can't search for have no results.
+                Err(ApiError::Server { .. }) => Vec::new(),
+                Err(err) => return Err(err.into()),
+            },
+            false => Vec::new(),
+        };
+        self.suggestions = suggestions(&query, &self.contacts, &found);
+        Ok(())
+    }
+
+    /// Name shown for a chat, the nickname when the user is a contact that has one.
+    pub fn display_name(&self, user: &UserHandle) -> String {
+        let nickname = self.contacts.iter()
+            .find(|contact| contact.id == user.id)
+            .and_then(|contact| contact.nickname.as_ref());
+        let name = match nickname {
+            Some(nickname) => format!("{} ({})", nickname, user.username),
+            None => user.username.clone(),
+        };
+        match self.is_blocked(&user.id) {
+            true => format!("{} [blocked]", name),
+            false => name,
+        }
+    }
+
+    fn is_blocked(&self, user_id: &ObjectId) -> bool {
+        self.blocked.iter().any(|blocked| blocked.id == *user_id)
+    }
+
+    /// Blocks the user of the highlighted chat, or lifts the block.
+    async fn toggle_block(&mut self) -> Result<(), PlasmaError> {
+        let user = match self.items.highlighted() {
+            Some(chat) => chat.user.clone(),
+            None => return Ok(()),
+        };
+        let token = self.account.token();
+        match self.is_blocked(&user.id) {
+            true => self.api.unblock(token, &user.username).await?,
+            false => self.api.block(token, &user.username).await?,
+        }
+        self.blocked = self.api.blocked(token).await?;
+        self.notice = match self.is_blocked(&user.id) {
+            true => Some(format!("Blocked {}", user.username)),
+            false => Some(format!("Unblocked {}", user.username)),
+        };
+        Ok(())
+    }
+
+    /// Sends the selected peer message with a few before it to moderators.
+    async fn report_selected(&mut self) -> Result<(), PlasmaError> {
+        let chat = match self.items.get() {
+            Some(chat) => chat.clone(),
+            None => return Ok(()),
+        };
+        let excerpt = match self.messages_buffer.excerpt(REPORT_CONTEXT) {
+            Some(excerpt) => excerpt,
+            None => {
+                self.error_message.set("Select a message of the other user to report");
+                return Ok(());
+            },
+        };
+        let params = ReportBody {
+            username: chat.user.username.clone(),
+            reason: String::from("Reported from chat"),
+            excerpt,
+            chat_id: Some(chat.id),
+            message_id: self.messages_buffer.selected().and_then(|m| m.id()),
+            block: false,
+        };
+        self.api.report(self.account.token(), &params).await?;
+        self.notice = Some(format!("Reported {} to moderators", chat.user.username));
+        Ok(())
+    }
+
+    async fn handle_evt_search(&mut self, key: KeyCode) -> Result<bool, PlasmaError> {
+        match key {
+            KeyCode::Enter => self.open_search_result().await?,
+            KeyCode::Down if !self.search_results.items.is_empty() => self.search_results.next(),
+            KeyCode::Up if !self.search_results.items.is_empty() => self.search_results.previous(),
+            KeyCode::Left => self.search_input.move_cursor_left(),
+            KeyCode::Right => self.search_input.move_cursor_right(),
+            KeyCode::Char(to_insert) => {
+                self.search_input.enter_char(to_insert);
+                self.search()?;
+            },
+            KeyCode::Backspace => {
+                self.search_input.delete_char();
+                self.search()?;
+            },
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Looks the query up in the local index, the matching messages are read from the cache.
+    fn search(&mut self) -> Result<(), PlasmaError> {
+        let mut histories: HashMap<ObjectId, CachedHistory> = HashMap::new();
+        let mut results = Vec::new();
+        for hit in self.search_index.search(&self.search_input.input) {
+            let chat = match self.items.items.iter().find(|chat| chat.id == hit.chat_id) {
+                Some(chat) => chat,
+                None => continue,
+            };
+            let history = match histories.entry(hit.chat_id) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(Self::account_store(&self.account).read_history(&hit.chat_id)?),
+            };
+            if let Some(message) = history.messages.iter().find(|m| m.timestamp == hit.timestamp) {
+                let text = format!("{} | @{}: {}", chat.user.username, message.username, message.content);
+                results.push(SearchResult { chat_id: hit.chat_id, timestamp: hit.timestamp, text });
+            }
+        }
+        self.search_results = StatefulList::with_items(results);
+        Ok(())
+    }
+
+    /// Opens chat of the highlighted result and shows the message in context.
+    async fn open_search_result(&mut self) -> Result<(), PlasmaError> {
+        let (chat_id, timestamp) = match self.search_results.highlighted() {
+            Some(result) => (result.chat_id, result.timestamp),
+            None => return Ok(()),
+        };
+        let index = match self.items.items.iter().position(|chat| chat.id == chat_id) {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+        self.items.highlight(index);
+        self.init_message_buffer().await?;
+        self.messages_buffer.focus(timestamp);
+        self.mode = Mode::ChatScroll;
+        Ok(())
+    }
+
     async fn submit(&mut self) -> Result<(), PlasmaError> {
         match self.mode {
             Mode::NewChat => self.submit_new_chat().await?,
@@ -189,10 +752,9 @@ impl App {
 
     async fn submit_new_chat(&mut self) -> Result<(), PlasmaError> {
         let username = self.new_chat_input.submit();
+        self.suggestions.clear();
         self.account.chat(&self.api, &username).await?;
-        let chats = self.account.chats(&self.api).await?.chats;
-        self.items = StatefulList::with_items(chats);
-        Ok(())
+        self.sync_chats().await
     }
 
     async fn submit_message(&mut self) -> Result<(), PlasmaError> {
@@ -203,40 +765,90 @@ impl App {
         let current_chat = self.items
             .get()
             .expect("Not possible to write message when no chat selected");
-        let ws_message = self.make_message(&message, &current_chat.id)?;
-        self.messages_buffer.push(self.account.username(), &message);
-        self.comms.sender.send(ws_message).await.unwrap();
-        Ok(())
+        let envelope = match self.replying {
+            Some(parent) if self.editing.is_none() => Envelope::reply(message.clone(), &parent),
+            _ => Envelope::Text(message.clone()),
+        };
+        let mut ws_message = self.make_message(&envelope, &current_chat.id)?;
+        self.typing_sent = None;
+        if let Some(original) = self.editing.take() {
+            ws_message.edit_of = original.to_hex();
+            let edit = Edit { original, username: self.account.username().clone(), content: message, timestamp: ws_message.timestamp };
+            self.messages_buffer.apply_edit(edit);
+            self.persist_history()?;
+            self.mode = Mode::ChatScroll;
+            return self.send_frame(WsFrame::Message(ws_message)).await;
+        }
+        ws_message.expires_at = session::expiry(ws_message.timestamp, self.messages_buffer.timer());
+        let pushed = Message::new(None, self.account.username(), &message, ws_message.timestamp)
+            .with_status(MessageStatus::Sent)
+            .with_reply_to(self.replying.take())
+            .with_expiry(ws_message.expires_at);
+        self.messages_buffer.push(pushed);
+        self.persist_history()?;
+        self.send_frame(WsFrame::Message(ws_message)).await
     }
 
-    fn make_message(&self, message: &str, chat_id: &ObjectId) -> Result<WsMessage, PlasmaError> {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_micros() as u64;
-        let encrypted = self.cipher
+    fn make_message(&self, envelope: &Envelope, chat_id: &ObjectId) -> Result<WsMessage, PlasmaError> {
+        let timestamp = session::timestamp();
+        let payloads = self.session
             .as_ref()
-            .expect("Cipher should be some if messages are read")
-            .encrypt(&message, timestamp)?;
+            .expect("Session should be some if messages are read")
+            .encrypt(envelope, timestamp)?
+            .into_iter()
+            .map(|(device, content)| WsPayload { device_id: device.to_hex(), content })
+            .collect();
+        // Sender fields are filled in by the server from the authenticated connection.
         let ws_message = WsMessage {
+            id: String::new(),
             chat_id: chat_id.to_string(),
-            sender_id: self.account.id().to_string(),
-            content: encrypted,
+            sender_id: String::new(),
+            sender_device: String::new(),
+            payloads,
             timestamp,
+            edit_of: String::new(),
+            expires_at: None,
         };
         Ok(ws_message)
     }
 
-    fn handle_evt_scroll(&mut self, key: KeyCode) -> Result<bool, PlasmaError> {
+    async fn handle_evt_scroll(&mut self, key: KeyCode) -> Result<bool, PlasmaError> {
+        let deleting = self.deleting.take();
         match key {
             KeyCode::Char('j') | KeyCode::Down => {
                 self.messages_buffer.scroll_down();
             }
             KeyCode::Char('k') | KeyCode::Up =>  {
+                if self.messages_buffer.is_scroll_blocked() && self.messages_buffer.has_older() {
+                    self.load_older_messages().await?;
+                }
                 self.messages_buffer.scroll_up();
             }
+            KeyCode::Char('K') => self.messages_buffer.select_up(),
+            KeyCode::Char('J') => self.messages_buffer.select_down(),
+            KeyCode::Char('e') => self.start_edit(),
+            KeyCode::Char('d') => self.confirm_delete(deleting).await?,
+            KeyCode::Char('x') => self.delete_selected(true).await?,
+            KeyCode::Char('t') => self.cycle_timer().await?,
+            KeyCode::Char('r') => self.start_reply(),
+            KeyCode::Char('w') => self.export_chat().await?,
+            KeyCode::Char('R') => self.report_selected().await?,
+            KeyCode::Char(key @ '1'..='5') => {
+                let emoji = REACTIONS[key as usize - '1' as usize];
+                self.toggle_reaction(emoji).await?;
+            },
+            KeyCode::Char('v') => {
+                match self.messages_buffer.in_thread() {
+                    true => self.messages_buffer.close_thread(),
+                    false => {
+                        self.messages_buffer.open_thread();
+                    },
+                }
+                self.messages_buffer.scroll_reset();
+            },
             KeyCode::Char('q') => {
                 self.messages_buffer.scroll_reset();
+                self.messages_buffer.unselect();
             }
             _ => {
                 return Ok(false);
@@ -244,4 +856,129 @@ impl App {
         }
         return Ok(true);
     }
+
+    /// Switches to the next disappearing messages timer, for the peer as well.
+    async fn cycle_timer(&mut self) -> Result<(), PlasmaError> {
+        let chat_id = match (self.items.get(), &self.session) {
+            (Some(chat), Some(_)) => chat.id,
+            _ => return Ok(()),
+        };
+        let current = TIMERS.iter()
+            .position(|timer| *timer == self.messages_buffer.timer())
+            .unwrap_or(0);
+        let seconds = TIMERS[(current + 1) % TIMERS.len()];
+        let ws_message = self.make_message(&Envelope::Timer { seconds }, &chat_id)?;
+        self.send_frame(WsFrame::Message(ws_message)).await?;
+        self.messages_buffer.set_timer(seconds);
+        self.persist_history()
+    }
+
+    /// Writes decrypted history of the open chat to the home directory, readable
+    /// only by its owner.
+    async fn export_chat(&mut self) -> Result<(), PlasmaError> {
+        let chat = match self.items.get() {
+            Some(chat) => chat.clone(),
+            None => return Ok(()),
+        };
+        let messages = export::collect(&self.api, &self.account, &chat, DateRange::default()).await?;
+        let transcript = export::render(&chat.user.username, &messages, EXPORT_FORMAT)?;
+        let path = home_dir()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Impossible to get home directory."))?
+            .join(format!("plasma-{}.{}", chat.user.username, EXPORT_FORMAT.extension()));
+        write_private(&path, transcript.as_bytes())?;
+        self.notice = Some(format!("Exported to {}", path.display()));
+        Ok(())
+    }
+
+    /// Id of the selected message, only messages the server stored can be changed.
+    fn selected_id(&mut self, own: bool) -> Option<ObjectId> {
+        let selected = match own {
+            true => self.messages_buffer.selected_own(),
+            false => self.messages_buffer.selected(),
+        };
+        let message = selected.filter(|m| !m.is_deleted())?;
+        let id = message.id();
+        if id.is_none() {
+            self.error_message.set("Message is not stored on the server yet");
+        }
+        id
+    }
+
+    /// Reacts to the selected message with `emoji`, or takes the reaction back.
+    async fn toggle_reaction(&mut self, emoji: &str) -> Result<(), PlasmaError> {
+        let (chat_id, target) = match (self.items.get().map(|chat| chat.id), self.selected_id(false)) {
+            (Some(chat_id), Some(target)) => (chat_id, target),
+            _ => return Ok(()),
+        };
+        let add = !self.messages_buffer.has_reacted(&target, emoji);
+        let mut ws_message = self.make_message(&Envelope::react(&target, emoji, add), &chat_id)?;
+        ws_message.expires_at = session::expiry(ws_message.timestamp, self.messages_buffer.timer());
+        let reaction = Reaction {
+            target,
+            username: self.account.username().clone(),
+            emoji: emoji.to_owned(),
+            timestamp: ws_message.timestamp,
+            add,
+        };
+        self.send_frame(WsFrame::Message(ws_message)).await?;
+        self.messages_buffer.react(reaction);
+        self.persist_history()
+    }
+
+    fn start_reply(&mut self) {
+        if let Some(id) = self.selected_id(false) {
+            self.editing = None;
+            self.replying = Some(id);
+            self.mode = Mode::Message;
+        }
+    }
+
+    fn start_edit(&mut self) {
+        let id = match self.selected_id(true) {
+            Some(id) => id,
+            None => return,
+        };
+        let content = self.messages_buffer.selected_own()
+            .map(|m| m.content().to_owned())
+            .unwrap_or_default();
+        self.message_input.set(&content);
+        self.replying = None;
+        self.editing = Some(id);
+        self.mode = Mode::Message;
+    }
+
+    /// Deletes selected message on this device only, or own one for everyone.
+    /// Deleting for everyone can't be undone, so it takes pressing `d` twice on
+    /// the same message.
+    async fn confirm_delete(&mut self, deleting: Option<ObjectId>) -> Result<(), PlasmaError> {
+        let id = match self.selected_id(true) {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        if deleting == Some(id) {
+            return self.delete_selected(false).await;
+        }
+        self.deleting = Some(id);
+        self.notice = Some(String::from("Press d again to delete for everyone"));
+        Ok(())
+    }
+
+    async fn delete_selected(&mut self, only_here: bool) -> Result<(), PlasmaError> {
+        let id = match self.selected_id(!only_here) {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        match only_here {
+            true => self.messages_buffer.hide(id),
+            false => {
+                let chat_id = match self.items.get() {
+                    Some(chat) => chat.id,
+                    None => return Ok(()),
+                };
+                self.account.delete_message(&self.api, &chat_id, &id).await?;
+                self.messages_buffer.mark_deleted(&id);
+            },
+        }
+        self.persist_history()
+    }
 }
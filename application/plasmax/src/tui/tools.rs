@@ -1,4 +1,7 @@
-use std::fmt::Display;
+use std::{collections::{BTreeMap, HashMap, HashSet}, fmt::Display};
+use bson::oid::ObjectId;
+use plasma_client::api::response::{Contact, FoundUser};
+use plasma_client::store::{CachedHistory, CachedMessage, CachedReaction, MessageStatus};
 use ratatui::{widgets::ListState, text::{Span, Line, Text}, style::{Color, Style, Modifier}};
 
 pub struct StatefulList<T> {
@@ -16,6 +19,15 @@ impl<T> StatefulList<T> {
         }
     }
 
+    /// Replaces items keeping the current one selected if it is still present.
+    pub fn set_items(&mut self, items: Vec<T>) where T: PartialEq {
+        let current = self.get()
+            .and_then(|c| items.iter().position(|i| i == c));
+        self.items = items;
+        self.current = current;
+        self.state.select(current.or(Some(0)));
+    }
+
     pub fn select(&mut self) -> bool {
         let next = self.state.selected();
         if next == self.current {
@@ -63,6 +75,16 @@ impl<T> StatefulList<T> {
     pub fn unselect(&mut self) {
         self.state.select(None);
     }
+
+    /// Highlights item at `index`, it becomes current once selected.
+    pub fn highlight(&mut self, index: usize) {
+        self.state.select(Some(index));
+    }
+
+    /// Highlighted item, whether or not it was selected.
+    pub fn highlighted(&self) -> Option<&T> {
+        self.state.selected().and_then(|i| self.items.get(i))
+    }
 }
 
 pub struct UserInput {
@@ -85,6 +107,12 @@ impl UserInput {
         tmp
     }
 
+    /// Replaces the input, e.g. with a message being edited, cursor goes to its end.
+    pub fn set(&mut self, text: &str) {
+        self.input = String::from(text);
+        self.cursor_position = self.input.len();
+    }
+
     pub fn move_cursor_left(&mut self) {
         let cursor_moved_left = self.cursor_position.saturating_sub(1);
         self.cursor_position = self.clamp_cursor(cursor_moved_left);
@@ -131,6 +159,7 @@ pub enum Mode {
     NewChat,
     Message,
     ChatScroll,
+    Search,
 }
 
 impl Display for Mode {
@@ -140,18 +169,188 @@ impl Display for Mode {
             Mode::BrowseChats => write!(f, "Browse"),
             Mode::NewChat | Mode::Message => write!(f, "Input"),
             Mode::ChatScroll => write!(f, "Scroll"),
+            Mode::Search => write!(f, "Search"),
         }
     }
 }
 
-struct Message {
+pub struct Message {
+    id: Option<ObjectId>,
     username: String,
     content: String,
+    timestamp: u64,
+    status: Option<MessageStatus>,
+    edited: bool,
+    deleted: bool,
+    expires_at: Option<u64>,
+    reply_to: Option<ObjectId>,
+}
+
+impl Message {
+    pub fn new(id: Option<ObjectId>, username: &str, content: &str, timestamp: u64) -> Self {
+        Message {
+            id,
+            username: String::from(username),
+            content: String::from(content),
+            timestamp,
+            status: None,
+            edited: false,
+            deleted: false,
+            expires_at: None,
+            reply_to: None,
+        }
+    }
+
+    fn edit(&mut self, edit: &Edit) {
+        if self.username == edit.username && !self.deleted {
+            self.content = edit.content.clone();
+            self.edited = true;
+        }
+    }
+
+    pub fn with_reply_to(mut self, parent: Option<ObjectId>) -> Self {
+        self.reply_to = parent;
+        self
+    }
+
+    pub fn with_expiry(mut self, expires_at: Option<u64>) -> Self {
+        self.expires_at = expires_at;
+        self
+    }
+
+    /// Only messages of this account carry a status.
+    pub fn with_status(mut self, status: MessageStatus) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    /// Placeholder of a message its sender deleted for everyone.
+    pub fn into_deleted(mut self) -> Self {
+        self.content.clear();
+        self.deleted = true;
+        self
+    }
+
+    pub fn id(&self) -> Option<ObjectId> {
+        self.id
+    }
+
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.deleted
+    }
+}
+
+/// Reaction of `username` to message `target`, added or taken back at `timestamp`.
+pub struct Reaction {
+    pub target: ObjectId,
+    pub username: String,
+    pub emoji: String,
+    pub timestamp: u64,
+    pub add: bool,
+}
+
+/// Latest reaction event per message, emoji and user, so replaying history
+/// in any order ends up in the same state.
+type Reactions = BTreeMap<(ObjectId, String, String), (u64, bool)>;
+
+/// Message found by a search, `text` shows the chat and the message itself.
+pub struct SearchResult {
+    pub chat_id: ObjectId,
+    pub timestamp: u64,
+    pub text: String,
+}
+
+/// Most usernames offered while typing into New chat.
+pub const MAX_SUGGESTIONS: usize = 5;
+
+/// Username offered while typing into New chat, contacts carry their nickname.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Suggestion {
+    pub username: String,
+    pub nickname: Option<String>,
+}
+
+impl Suggestion {
+    pub fn label(&self) -> String {
+        match &self.nickname {
+            Some(nickname) => format!("{} ({})", self.username, nickname),
+            None => self.username.clone(),
+        }
+    }
+}
+
+/// Contacts whose username or nickname starts with `input` come first, then
+/// users the server found that aren't contacts yet.
+pub fn suggestions(input: &str, contacts: &[Contact], found: &[FoundUser]) -> Vec<Suggestion> {
+    let input = input.trim().to_lowercase();
+    if input.is_empty() {
+        return Vec::new();
+    }
+    let matches = |name: &str| name.to_lowercase().starts_with(&input);
+    let from_contacts = contacts.iter()
+        .filter(|contact| matches(&contact.username) || contact.nickname.as_deref().is_some_and(matches))
+        .map(|contact| Suggestion { username: contact.username.clone(), nickname: contact.nickname.clone() });
+    let from_search = found.iter()
+        .filter(|user| !contacts.iter().any(|contact| contact.id == user.id))
+        .map(|user| Suggestion { username: user.username.clone(), nickname: None });
+    from_contacts.chain(from_search)
+        .take(MAX_SUGGESTIONS)
+        .collect()
+}
+
+/// Longest part of the answered message quoted above a reply.
+const QUOTE_LEN: usize = 40;
+
+/// Replacement content for an earlier message, only its sender may edit it.
+pub struct Edit {
+    pub original: ObjectId,
+    pub username: String,
+    pub content: String,
+    pub timestamp: u64,
+}
+
+impl From<CachedMessage> for Message {
+    fn from(cached: CachedMessage) -> Self {
+        Message {
+            id: cached.id(),
+            reply_to: cached.reply_to(),
+            username: cached.username,
+            content: cached.content,
+            timestamp: cached.timestamp,
+            status: cached.status,
+            edited: cached.edited,
+            deleted: cached.deleted,
+            expires_at: cached.expires_at,
+        }
+    }
+}
+
+impl From<&Message> for CachedMessage {
+    fn from(message: &Message) -> Self {
+        let mut cached = CachedMessage::new(message.id, &message.username, &message.content, message.timestamp, message.status)
+            .replying_to(message.reply_to);
+        cached.edited = message.edited;
+        cached.deleted = message.deleted;
+        cached.expires_at = message.expires_at;
+        cached
+    }
 }
 
 pub struct MessagesBuffer {
     me: String,
     messages: Vec<Message>,
+    has_older: bool,
+    hidden: HashSet<ObjectId>,
+    timer: Option<u64>,
+    selected: Option<usize>,
+    thread: Option<ObjectId>,
+    reactions: Reactions,
+    pending_edits: HashMap<ObjectId, Edit>,
+    read_sent: Option<u64>,
     scroll_offset: u16,
     scroll_up_block: bool,
 }
@@ -161,29 +360,410 @@ impl MessagesBuffer {
         MessagesBuffer {
             me,
             messages: Vec::new(),
+            has_older: false,
+            hidden: HashSet::new(),
+            timer: None,
+            selected: None,
+            thread: None,
+            reactions: Reactions::new(),
+            pending_edits: HashMap::new(),
+            read_sent: None,
             scroll_offset: 0,
             scroll_up_block: false,
         }
     }
 
-    pub fn push(&mut self, username: &str, message: &str) {
-        let m = Message {
-            username: String::from(username),
-            content: String::from(message),
+    fn is_hidden(&self, message: &Message) -> bool {
+        message.id.is_some_and(|id| self.hidden.contains(&id))
+    }
+
+    pub fn push(&mut self, message: Message) {
+        if !self.is_hidden(&message) {
+            self.messages.push(message);
+        }
+    }
+
+    /// Appends messages fetched from the server, skipping the ones already
+    /// shown, e.g. sent from here or received over the websocket.
+    pub fn merge(&mut self, messages: Vec<Message>) {
+        for message in messages {
+            if self.is_hidden(&message) {
+                continue;
+            }
+            let shown = self.messages
+                .iter_mut()
+                .find(|m| m.timestamp == message.timestamp && m.username == message.username);
+            match shown {
+                Some(shown) if message.deleted => *shown = message,
+                Some(shown) => {
+                    shown.id = shown.id.or(message.id);
+                    shown.status = shown.status.max(message.status);
+                },
+                None => self.messages.push(message),
+            }
+        }
+    }
+
+    /// Sets id the server stored own message under and marks it stored.
+    pub fn stored(&mut self, timestamp: u64, id: Option<ObjectId>) {
+        let me = &self.me;
+        if let Some(message) = self.messages.iter_mut().find(|m| m.timestamp == timestamp && m.username == *me) {
+            message.id = message.id.or(id);
+        }
+        self.set_status(timestamp, MessageStatus::Stored);
+    }
+
+    /// Replaces content of the original message, ignoring edits by anyone but its sender.
+    /// Edits of messages on pages not loaded yet wait until `prepend` brings
+    /// them in, older pages then can't undo them.
+    pub fn apply_edit(&mut self, edit: Edit) {
+        let superseded = self.pending_edits
+            .get(&edit.original)
+            .is_some_and(|newer| newer.username == edit.username && newer.timestamp > edit.timestamp);
+        if superseded {
+            return;
+        }
+        match self.messages.iter_mut().find(|m| m.id == Some(edit.original)) {
+            Some(original) => original.edit(&edit),
+            None => {
+                self.pending_edits.insert(edit.original, edit);
+            },
+        }
+    }
+
+    /// Sender deleted the message for everyone.
+    pub fn mark_deleted(&mut self, id: &ObjectId) {
+        if let Some(message) = self.messages.iter_mut().find(|m| m.id == Some(*id)) {
+            message.content.clear();
+            message.deleted = true;
+        }
+    }
+
+    /// Deletes message on this device only, it stays hidden when synced again.
+    pub fn hide(&mut self, id: ObjectId) {
+        self.messages.retain(|m| m.id != Some(id));
+        self.reactions.retain(|(target, _, _), _| *target != id);
+        self.hidden.insert(id);
+        self.selected = None;
+    }
+
+    /// Applies reaction unless a newer one of the same user and emoji is known.
+    pub fn react(&mut self, reaction: Reaction) {
+        let key = (reaction.target, reaction.emoji, reaction.username);
+        let newer = self.reactions.get(&key).is_some_and(|(timestamp, _)| *timestamp >= reaction.timestamp);
+        if !newer {
+            self.reactions.insert(key, (reaction.timestamp, reaction.add));
+        }
+    }
+
+    /// True when this account currently reacts with `emoji` to message `target`.
+    pub fn has_reacted(&self, target: &ObjectId, emoji: &str) -> bool {
+        let key = (*target, emoji.to_owned(), self.me.clone());
+        self.reactions.get(&key).is_some_and(|(_, added)| *added)
+    }
+
+    /// Emoji used on message `target` with how many users reacted with each,
+    /// and whether this account is one of them.
+    fn reaction_counts(&self, target: &ObjectId) -> BTreeMap<&str, (usize, bool)> {
+        let mut counts = BTreeMap::new();
+        let reactions = self.reactions.iter()
+            .filter(|((id, _, _), (_, added))| id == target && *added);
+        for ((_, emoji, username), _) in reactions {
+            let count: &mut (usize, bool) = counts.entry(emoji.as_str()).or_default();
+            count.0 += 1;
+            count.1 |= *username == self.me;
+        }
+        counts
+    }
+
+    /// Seconds after which messages sent to the chat disappear.
+    pub fn timer(&self) -> Option<u64> {
+        self.timer
+    }
+
+    pub fn set_timer(&mut self, seconds: Option<u64>) {
+        self.timer = seconds;
+    }
+
+    /// Timestamp the last read receipt sent to the peer covered.
+    pub fn read_sent(&self) -> Option<u64> {
+        self.read_sent
+    }
+
+    pub fn set_read_sent(&mut self, up_to: u64) {
+        self.read_sent = Some(up_to);
+    }
+
+    /// Drops messages whose timer ran out by `now`, true when any were dropped.
+    pub fn purge_expired(&mut self, now: u64) -> bool {
+        let expired: HashSet<ObjectId> = self.messages.iter()
+            .filter(|m| m.expires_at.is_some_and(|at| at <= now))
+            .filter_map(|m| m.id)
+            .collect();
+        let before = self.messages.len();
+        self.messages.retain(|m| m.expires_at.is_none_or(|at| at > now));
+        if self.messages.len() == before {
+            return false;
+        }
+        self.reactions.retain(|(target, _, _), _| !expired.contains(target));
+        self.selected = None;
+        true
+    }
+
+    /// Indices of the messages shown, all of them unless a thread is open.
+    fn shown(&self) -> Vec<usize> {
+        let branch = self.thread.map(|root| self.branch(root));
+        (0..self.messages.len())
+            .filter(|i| match &branch {
+                Some(branch) => self.messages[*i].id.is_some_and(|id| branch.contains(&id)),
+                None => true,
+            })
+            .collect()
+    }
+
+    /// `root` and every reply below it, replies always come after their parent.
+    fn branch(&self, root: ObjectId) -> HashSet<ObjectId> {
+        let mut branch = HashSet::from([root]);
+        for message in self.messages.iter() {
+            if let (Some(id), Some(parent)) = (message.id, message.reply_to) {
+                if branch.contains(&parent) {
+                    branch.insert(id);
+                }
+            }
+        }
+        branch
+    }
+
+    /// Moves selection one message up, starting from the newest one.
+    pub fn select_up(&mut self) {
+        let shown = self.shown();
+        let position = self.selected.and_then(|s| shown.iter().position(|i| *i == s));
+        self.selected = match position {
+            Some(p) => Some(shown[p.saturating_sub(1)]),
+            None => shown.last().copied(),
         };
-        self.messages.push(m);
     }
 
-    pub fn text(&self) -> Text {
-        let lines: Vec<Line> = self.messages
+    pub fn select_down(&mut self) {
+        let shown = self.shown();
+        let position = self.selected.and_then(|s| shown.iter().position(|i| *i == s));
+        self.selected = match position {
+            Some(p) if p + 1 < shown.len() => Some(shown[p + 1]),
+            _ => None,
+        };
+    }
+
+    /// Shows only the conversation branch of the selected message, starting
+    /// from the first message it answers. False when nothing is selected.
+    pub fn open_thread(&mut self) -> bool {
+        let mut root = match self.selected().and_then(|m| m.id) {
+            Some(id) => id,
+            None => return false,
+        };
+        while let Some(parent) = self.find(&root).and_then(|m| m.reply_to) {
+            if self.find(&parent).is_none() {
+                break;
+            }
+            root = parent;
+        }
+        self.thread = Some(root);
+        true
+    }
+
+    pub fn close_thread(&mut self) {
+        self.thread = None;
+    }
+
+    pub fn in_thread(&self) -> bool {
+        self.thread.is_some()
+    }
+
+    fn find(&self, id: &ObjectId) -> Option<&Message> {
+        self.messages.iter().find(|m| m.id == Some(*id))
+    }
+
+    pub fn unselect(&mut self) {
+        self.selected = None;
+    }
+
+    pub fn selected(&self) -> Option<&Message> {
+        self.selected.and_then(|i| self.messages.get(i))
+    }
+
+    /// Selected message of the peer with up to `context` shown messages before it,
+    /// one `username: content` line each. This is what a report shares with moderators.
+    pub fn excerpt(&self, context: usize) -> Option<String> {
+        let selected = self.selected.filter(|i| self.messages[*i].username != self.me)?;
+        let shown: Vec<usize> = self.shown()
+            .into_iter()
+            .filter(|i| *i <= selected)
+            .filter(|i| !self.messages[*i].deleted && !self.is_hidden(&self.messages[*i]))
+            .collect();
+        let start = shown.len().saturating_sub(context + 1);
+        let lines: Vec<String> = shown[start..].iter()
+            .map(|i| format!("{}: {}", self.messages[*i].username, self.messages[*i].content))
+            .collect();
+        Some(lines.join("\n"))
+    }
+
+    /// Selected message when it was sent from this account, the only ones that can be changed.
+    pub fn selected_own(&self) -> Option<&Message> {
+        self.selected().filter(|m| m.username == self.me)
+    }
+
+    /// Advances status of the own message sent at `timestamp`, never moving it back.
+    pub fn set_status(&mut self, timestamp: u64, status: MessageStatus) {
+        let me = &self.me;
+        self.messages
+            .iter_mut()
+            .filter(|m| m.timestamp == timestamp && m.username == *me)
+            .for_each(|m| m.status = m.status.max(Some(status)));
+    }
+
+    /// Marks every own message up to `up_to` as read by the peer.
+    pub fn mark_read(&mut self, up_to: u64) {
+        let me = &self.me;
+        self.messages
+            .iter_mut()
+            .filter(|m| m.timestamp <= up_to && m.username == *me)
+            .for_each(|m| m.status = m.status.max(Some(MessageStatus::Read)));
+    }
+
+    /// Timestamp of the newest message sent by the peer, what a read receipt covers.
+    pub fn newest_peer_timestamp(&self) -> Option<u64> {
+        self.messages.iter()
+            .filter(|m| m.username != self.me)
+            .map(|m| m.timestamp)
+            .max()
+    }
+
+    pub fn prepend(&mut self, messages: Vec<Message>, has_older: bool) {
+        let messages: Vec<Message> = messages.into_iter()
+            .filter(|m| !self.is_hidden(m))
+            .collect();
+        self.selected = self.selected.map(|i| i + messages.len());
+        let count = messages.len();
+        self.messages.splice(0..0, messages);
+        for message in self.messages[..count].iter_mut() {
+            if let Some(edit) = message.id.and_then(|id| self.pending_edits.get(&id)) {
+                message.edit(edit);
+            }
+        }
+        self.has_older = has_older;
+    }
+
+    pub fn restore(&mut self, history: CachedHistory) {
+        self.hidden = history.hidden().collect();
+        self.timer = history.timer;
+        self.read_sent = history.read_sent;
+        self.reactions = history.reactions
             .iter()
-            .map(|m| {
-                self.to_line(m)
-            })
+            .map(|r| ((r.target(), r.emoji.clone(), r.username.clone()), (r.timestamp, r.added)))
             .collect();
+        self.messages = history.messages
+            .into_iter()
+            .map(Message::from)
+            .collect();
+        self.has_older = history.has_older;
+    }
+
+    pub fn to_history(&self) -> CachedHistory {
+        let messages = self.messages.iter().map(CachedMessage::from).collect();
+        let mut history = CachedHistory::new(messages, self.has_older, self.hidden.iter().copied());
+        history.timer = self.timer;
+        history.read_sent = self.read_sent;
+        history.reactions = self.reactions
+            .iter()
+            .map(|((target, emoji, username), (timestamp, added))| CachedReaction::new(target, username, emoji, *timestamp, *added))
+            .collect();
+        history
+    }
+
+    /// Selects message sent at `timestamp` and scrolls so it is the last line shown.
+    pub fn focus(&mut self, timestamp: u64) -> bool {
+        let index = match self.messages.iter().position(|m| m.timestamp == timestamp) {
+            Some(index) => index,
+            None => return false,
+        };
+        self.thread = None;
+        self.selected = Some(index);
+        let below: usize = self.messages[index + 1..]
+            .iter()
+            .map(|m| self.height(m))
+            .sum();
+        self.scroll_offset = below as u16;
+        true
+    }
+
+    /// Lines a message takes up, with its quote and reactions.
+    fn height(&self, message: &Message) -> usize {
+        let reactions = message.id.is_some_and(|id| !message.deleted && !self.reaction_counts(&id).is_empty());
+        1 + message.reply_to.is_some() as usize + reactions as usize
+    }
+
+    pub fn has_older(&self) -> bool {
+        self.has_older
+    }
+
+    pub fn oldest_id(&self) -> Option<ObjectId> {
+        self.messages.first().and_then(|m| m.id)
+    }
+
+    /// Timestamp and id of the newest message, where catching up continues from.
+    pub fn newest_position(&self) -> Option<(u64, Option<ObjectId>)> {
+        self.messages.iter().map(|m| (m.timestamp, m.id)).max()
+    }
+
+    pub fn text(&self) -> Text {
+        let mut lines = Vec::new();
+        for i in self.shown() {
+            let message = &self.messages[i];
+            if let Some(parent) = message.reply_to {
+                lines.push(self.quote(&parent));
+            }
+            let mut line = self.to_line(message);
+            if self.selected == Some(i) {
+                line.patch_style(Style::new().bg(Color::DarkGray));
+            }
+            lines.push(line);
+            if let Some(reactions) = message.id.filter(|_| !message.deleted).and_then(|id| self.reactions_line(&id)) {
+                lines.push(reactions);
+            }
+        }
         Text::from(lines)
     }
 
+    /// Reaction counts shown under a message, own reactions stand out.
+    fn reactions_line(&self, target: &ObjectId) -> Option<Line<'static>> {
+        let counts = self.reaction_counts(target);
+        if counts.is_empty() {
+            return None;
+        }
+        let mut spans = vec![Span::raw("   ")];
+        for (emoji, (count, mine)) in counts {
+            let color = if mine { Color::LightBlue } else { Color::DarkGray };
+            spans.push(Span::styled(format!(" {} {}", emoji, count), Style::new().fg(color)));
+        }
+        Some(Line::from(spans))
+    }
+
+    /// Context of the message a reply answers, shortened to a single line.
+    fn quote(&self, parent: &ObjectId) -> Line<'static> {
+        let style = Style::new().fg(Color::DarkGray);
+        let text = match self.find(parent) {
+            Some(parent) if parent.deleted => format!("  ┆ @{}: message deleted", parent.username),
+            Some(parent) => {
+                let mut content: String = parent.content.chars().take(QUOTE_LEN).collect();
+                if parent.content.chars().count() > QUOTE_LEN {
+                    content.push('…');
+                }
+                format!("  ┆ @{}: {}", parent.username, content)
+            },
+            None => String::from("  ┆ earlier message"),
+        };
+        Line::styled(text, style)
+    }
+
     fn to_line(&self, message: &Message) -> Line {
         let color = if self.me == message.username { Color::Green } else { Color::Red };
         let span = Span::styled(
@@ -192,9 +772,18 @@ impl MessagesBuffer {
             .fg(color)
             .add_modifier(Modifier::BOLD),
         );
-        let span2 = Span::raw(message.content.clone());
-        let line = Line::from(vec![span, span2]);
-        line
+        let span2 = match message.deleted {
+            true => Span::styled("message deleted", Style::new().fg(Color::DarkGray).add_modifier(Modifier::ITALIC)),
+            false => Span::raw(message.content.clone()),
+        };
+        let mut spans = vec![span, span2];
+        if message.edited && !message.deleted {
+            spans.push(Span::styled(" (edited)", Style::new().fg(Color::DarkGray)));
+        }
+        if let Some(status) = message.status {
+            spans.push(status_marker(status));
+        }
+        Line::from(spans)
     }
 
     pub fn scroll_get(&self) -> u16 {
@@ -212,6 +801,10 @@ impl MessagesBuffer {
         self.scroll_offset = self.scroll_offset.saturating_sub(1);
     }
 
+    pub fn is_scroll_blocked(&self) -> bool {
+        self.scroll_up_block
+    }
+
     pub fn scroll_block(&mut self) {
         self.scroll_up_block = true;
     }
@@ -225,6 +818,25 @@ impl MessagesBuffer {
     }
 }
 
+/// Disappearing messages timer in its largest whole unit, e.g. `5m` or `1d`.
+pub fn format_timer(seconds: u64) -> String {
+    const UNITS: [(u64, &str); 4] = [(7 * 24 * 60 * 60, "w"), (24 * 60 * 60, "d"), (60 * 60, "h"), (60, "m")];
+    UNITS.iter()
+        .find(|(unit, _)| seconds >= *unit && seconds.is_multiple_of(*unit))
+        .map(|(unit, suffix)| format!("{}{}", seconds / unit, suffix))
+        .unwrap_or_else(|| format!("{}s", seconds))
+}
+
+fn status_marker(status: MessageStatus) -> Span<'static> {
+    let (marker, color) = match status {
+        MessageStatus::Sent => (" ·", Color::DarkGray),
+        MessageStatus::Stored => (" ✓", Color::DarkGray),
+        MessageStatus::Delivered => (" ✓✓", Color::DarkGray),
+        MessageStatus::Read => (" ✓✓", Color::LightBlue),
+    };
+    Span::styled(marker, Style::new().fg(color))
+}
+
 #[derive(Default)]
 pub struct ErrorMessage {
     message: Option<String>,
@@ -246,3 +858,243 @@ impl ErrorMessage {
         self.message.is_some()
     }
 }
+
+#[cfg(test)]
+mod messages_buffer_test {
+    use bson::oid::ObjectId;
+    use plasma_client::store::MessageStatus;
+    use plasma_client::api::response::{Contact, FoundUser};
+    use super::{format_timer, suggestions, Edit, MessagesBuffer, Message, Reaction, Suggestion};
+
+    #[test]
+    fn prepend_keeps_order() {
+        let mut buffer = MessagesBuffer::new(String::from("me"));
+        let older = ObjectId::new();
+        let newer = ObjectId::new();
+        buffer.prepend(vec![Message::new(Some(newer), "me", "2", 2)], true);
+        buffer.push(Message::new(None, "other", "3", 3));
+        buffer.prepend(vec![Message::new(Some(older), "other", "1", 1)], false);
+
+        assert_eq!(buffer.oldest_id(), Some(older));
+        assert_eq!(buffer.newest_position(), Some((3, None)));
+        assert!(!buffer.has_older());
+    }
+
+    #[test]
+    fn merge_skips_shown_messages() {
+        let mut buffer = MessagesBuffer::new(String::from("me"));
+        let id = ObjectId::new();
+        buffer.push(Message::new(None, "me", "sent", 1));
+        buffer.merge(vec![Message::new(Some(id), "me", "sent", 1), Message::new(None, "other", "missed", 2)]);
+
+        assert_eq!(buffer.messages.len(), 2);
+        assert_eq!(buffer.oldest_id(), Some(id));
+        assert_eq!(buffer.newest_position(), Some((2, None)));
+    }
+
+    #[test]
+    fn status_only_moves_forward() {
+        let mut buffer = MessagesBuffer::new(String::from("me"));
+        buffer.push(Message::new(None, "me", "1", 1).with_status(MessageStatus::Sent));
+        buffer.push(Message::new(None, "other", "2", 2));
+        buffer.push(Message::new(None, "me", "3", 3).with_status(MessageStatus::Sent));
+
+        buffer.set_status(1, MessageStatus::Delivered);
+        buffer.mark_read(2);
+        buffer.set_status(1, MessageStatus::Stored);
+        buffer.set_status(2, MessageStatus::Stored);
+
+        let statuses: Vec<_> = buffer.messages.iter().map(|m| m.status).collect();
+        assert_eq!(statuses, vec![Some(MessageStatus::Read), None, Some(MessageStatus::Sent)]);
+        assert_eq!(buffer.newest_peer_timestamp(), Some(2));
+    }
+
+    #[test]
+    fn focus_scrolls_to_message() {
+        let mut buffer = MessagesBuffer::new(String::from("me"));
+        let parent = ObjectId::new();
+        buffer.push(Message::new(Some(parent), "me", "found", 1));
+        buffer.push(Message::new(None, "other", "reply", 2).with_reply_to(Some(parent)));
+        buffer.push(Message::new(None, "me", "last", 3));
+
+        assert!(buffer.focus(1));
+        assert_eq!(buffer.selected().map(|m| m.content()), Some("found"));
+        assert_eq!(buffer.scroll_get(), 3);
+        assert!(!buffer.focus(4));
+    }
+
+    #[test]
+    fn excerpt_ends_at_selected_peer_message() {
+        let mut buffer = MessagesBuffer::new(String::from("me"));
+        let gone = ObjectId::new();
+        buffer.push(Message::new(None, "me", "hi", 1));
+        buffer.push(Message::new(Some(gone), "other", "deleted", 2));
+        buffer.push(Message::new(None, "other", "spam", 3));
+        buffer.push(Message::new(None, "me", "stop", 4));
+        buffer.mark_deleted(&gone);
+
+        buffer.select_up();
+        assert!(buffer.excerpt(5).is_none());
+        buffer.select_up();
+        assert_eq!(buffer.excerpt(5).as_deref(), Some("me: hi\nother: spam"));
+        assert_eq!(buffer.excerpt(0).as_deref(), Some("other: spam"));
+    }
+
+    #[test]
+    fn reactions_keep_latest_event() {
+        let mut buffer = MessagesBuffer::new(String::from("me"));
+        let target = ObjectId::new();
+        let reaction = |username: &str, timestamp, add| Reaction {
+            target,
+            username: String::from(username),
+            emoji: String::from("👍"),
+            timestamp,
+            add,
+        };
+        buffer.react(reaction("me", 2, false));
+        buffer.react(reaction("me", 1, true));
+        buffer.react(reaction("other", 1, true));
+
+        assert!(!buffer.has_reacted(&target, "👍"));
+        assert_eq!(buffer.reaction_counts(&target).get("👍"), Some(&(1, false)));
+
+        let mut restored = MessagesBuffer::new(String::from("me"));
+        restored.restore(buffer.to_history());
+        restored.react(reaction("me", 3, true));
+        assert_eq!(restored.reaction_counts(&target).get("👍"), Some(&(2, true)));
+    }
+
+    #[test]
+    fn thread_follows_replies() {
+        let mut buffer = MessagesBuffer::new(String::from("me"));
+        let (root, other, answer, nested) = (ObjectId::new(), ObjectId::new(), ObjectId::new(), ObjectId::new());
+        buffer.push(Message::new(Some(root), "me", "question", 1));
+        buffer.push(Message::new(Some(other), "other", "unrelated", 2));
+        buffer.push(Message::new(Some(answer), "other", "answer", 3).with_reply_to(Some(root)));
+        buffer.push(Message::new(Some(nested), "me", "thanks", 4).with_reply_to(Some(answer)));
+
+        buffer.select_up();
+        assert!(buffer.open_thread());
+        assert_eq!(buffer.shown(), vec![0, 2, 3]);
+
+        buffer.select_up();
+        buffer.select_up();
+        assert_eq!(buffer.selected().and_then(|m| m.id()), Some(root));
+
+        buffer.close_thread();
+        assert_eq!(buffer.shown().len(), 4);
+    }
+
+    #[test]
+    fn purge_keeps_unexpired() {
+        let mut buffer = MessagesBuffer::new(String::from("me"));
+        buffer.push(Message::new(None, "me", "gone", 1).with_expiry(Some(10)));
+        buffer.push(Message::new(None, "me", "later", 2).with_expiry(Some(30)));
+        buffer.push(Message::new(None, "me", "kept", 3));
+
+        assert!(buffer.purge_expired(20));
+        assert!(!buffer.purge_expired(20));
+        assert_eq!(buffer.messages.len(), 2);
+        assert_eq!(buffer.messages[0].content(), "later");
+    }
+
+    #[test]
+    fn timer_units() {
+        assert_eq!(format_timer(30), "30s");
+        assert_eq!(format_timer(300), "5m");
+        assert_eq!(format_timer(90), "90s");
+        assert_eq!(format_timer(7 * 24 * 60 * 60), "1w");
+    }
+
+    #[test]
+    fn edit_only_by_sender() {
+        let mut buffer = MessagesBuffer::new(String::from("me"));
+        let id = ObjectId::new();
+        buffer.push(Message::new(Some(id), "me", "typo", 1));
+
+        buffer.apply_edit(Edit { original: id, username: String::from("other"), content: String::from("spoofed"), timestamp: 2 });
+        assert_eq!(buffer.messages[0].content(), "typo");
+
+        buffer.apply_edit(Edit { original: id, username: String::from("me"), content: String::from("fixed"), timestamp: 3 });
+        assert_eq!(buffer.messages[0].content(), "fixed");
+        assert!(buffer.messages[0].edited);
+    }
+
+    #[test]
+    fn edit_waits_for_older_page() {
+        let mut buffer = MessagesBuffer::new(String::from("me"));
+        let id = ObjectId::new();
+        buffer.push(Message::new(None, "other", "newer", 5));
+        buffer.apply_edit(Edit { original: id, username: String::from("other"), content: String::from("second"), timestamp: 4 });
+
+        buffer.prepend(vec![Message::new(Some(id), "other", "typo", 1)], false);
+        buffer.apply_edit(Edit { original: id, username: String::from("other"), content: String::from("first"), timestamp: 2 });
+        assert_eq!(buffer.messages[0].content(), "second");
+        assert!(buffer.messages[0].edited);
+    }
+
+    #[test]
+    fn hidden_stays_hidden() {
+        let mut buffer = MessagesBuffer::new(String::from("me"));
+        let (hidden, kept) = (ObjectId::new(), ObjectId::new());
+        buffer.push(Message::new(Some(hidden), "other", "1", 1));
+        buffer.hide(hidden);
+        buffer.merge(vec![Message::new(Some(hidden), "other", "1", 1), Message::new(Some(kept), "other", "2", 2)]);
+
+        let mut restored = MessagesBuffer::new(String::from("me"));
+        restored.restore(buffer.to_history());
+        restored.prepend(vec![Message::new(Some(hidden), "other", "1", 1)], false);
+
+        assert_eq!(restored.messages.len(), 1);
+        assert_eq!(restored.oldest_id(), Some(kept));
+    }
+
+    #[test]
+    fn read_sent_survives_restart() {
+        let mut buffer = MessagesBuffer::new(String::from("me"));
+        buffer.set_read_sent(7);
+
+        let mut restored = MessagesBuffer::new(String::from("me"));
+        restored.restore(buffer.to_history());
+        assert_eq!(restored.read_sent(), Some(7));
+    }
+
+    #[test]
+    fn select_own_messages() {
+        let mut buffer = MessagesBuffer::new(String::from("me"));
+        buffer.push(Message::new(None, "me", "1", 1));
+        buffer.push(Message::new(None, "other", "2", 2));
+
+        buffer.select_up();
+        assert!(buffer.selected_own().is_none());
+        buffer.select_up();
+        assert_eq!(buffer.selected_own().map(|m| m.content()), Some("1"));
+        buffer.select_down();
+        buffer.select_down();
+        assert!(buffer.selected().is_none());
+    }
+
+    #[test]
+    fn empty_buffer_has_no_cursors() {
+        let buffer = MessagesBuffer::new(String::from("me"));
+
+        assert_eq!(buffer.oldest_id(), None);
+        assert_eq!(buffer.newest_position(), None);
+    }
+
+    #[test]
+    fn suggestions_prefer_contacts() {
+        let ally = Contact { id: ObjectId::new(), username: String::from("alice"), nickname: Some(String::from("Ally")) };
+        let bob = Contact { id: ObjectId::new(), username: String::from("bob"), nickname: Some(String::from("Al")) };
+        let found = vec![
+            FoundUser { id: ally.id, username: String::from("alice") },
+            FoundUser { id: ObjectId::new(), username: String::from("alan") },
+        ];
+
+        let offered = suggestions("Al", &[ally, bob], &found);
+        let usernames: Vec<&str> = offered.iter().map(|s| s.username.as_str()).collect();
+        assert_eq!(usernames, vec!["alice", "bob", "alan"]);
+        assert_eq!(offered[0], Suggestion { username: String::from("alice"), nickname: Some(String::from("Ally")) });
+        assert!(suggestions(" ", &[], &found).is_empty());
+    }
+}
@@ -1,6 +1,6 @@
 use ratatui::{prelude::*, widgets::*};
 use itertools::Itertools;
-use super::{app::App, tools::Mode};
+use super::{app::App, tools::{Mode, format_timer}};
 
 pub fn ui<B: ratatui::backend::Backend>(f: &mut Frame<B>, app: &mut App) {
     let chunks = create_layout(f.size());
@@ -15,6 +15,7 @@ pub fn ui<B: ratatui::backend::Backend>(f: &mut Frame<B>, app: &mut App) {
             _ => {},
         }
     }
+    draw_search_popup(f, app);
     draw_error_popup(f, app);
 }
 
@@ -51,7 +52,11 @@ fn draw_chats_widget<B: ratatui::backend::Backend>(f: &mut Frame<B>, app: &mut A
         .items
         .iter()
         .map(|chat| {
-            let lines = vec![Line::from(chat.user.username.clone())];
+            let dot = match app.is_online(&chat.user.id) {
+                true => Span::styled("● ", Style::default().fg(Color::LightGreen)),
+                false => Span::styled("○ ", Style::default().fg(Color::DarkGray)),
+            };
+            let lines = vec![Line::from(vec![dot, Span::raw(app.display_name(&chat.user))])];
             ListItem::new(lines).style(Style::default())
         })
         .collect();
@@ -88,10 +93,27 @@ fn draw_new_chat_input<B: ratatui::backend::Backend>(f: &mut Frame<B>, app: &App
         f.set_cursor(
             area.x + app.new_chat_input.cursor_position as u16 + 1,
             area.y + 1,
-        )
+        );
+        draw_suggestions(f, app, area);
     }
 }
 
+/// Usernames offered for the New chat input, drawn right above it. Tab takes the first one.
+fn draw_suggestions<B: ratatui::backend::Backend>(f: &mut Frame<B>, app: &App, input_area: Rect) {
+    if app.suggestions.is_empty() {
+        return;
+    }
+    let height = (app.suggestions.len() as u16 + 2).min(input_area.y);
+    let area = Rect::new(input_area.x, input_area.y - height, input_area.width, height);
+    let items: Vec<ListItem> = app.suggestions.iter()
+        .map(|suggestion| ListItem::new(suggestion.label()))
+        .collect();
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title("Tab to complete"));
+    f.render_widget(Clear, area);
+    f.render_widget(list, area);
+}
+
 fn print_small_help<B: ratatui::backend::Backend>(f: &mut Frame<B>, app: &App, area: Rect) {
     let text = app.get_small_help();
     let help_message = Paragraph::new(text);
@@ -103,8 +125,13 @@ fn draw_chat_widget<B: ratatui::backend::Backend>(f: &mut Frame<B>, app: &mut Ap
     let scroll = app.calculate_scroll(area.height, text.height() as u16);
     let title = match app.items.get() {
         None => String::from(""),
+        Some(chat) if app.is_typing(&chat.id) => format!("Chat with {} - typing...", chat.user.username),
         Some(chat) => format!("Chat with {}", chat.user.username),
     };
+    let title = match app.messages_buffer.timer() {
+        Some(seconds) if app.items.get().is_some() => format!("{} [disappearing after {}]", title, format_timer(seconds)),
+        _ => title,
+    };
 
     let paragraph = Paragraph::new(text)
         .block(
@@ -142,6 +169,41 @@ fn draw_message_box<B: ratatui::backend::Backend>(f: &mut Frame<B>, app: &App, a
     }
 }
 
+fn draw_search_popup<B: ratatui::backend::Backend>(f: &mut Frame<B>, app: &mut App) {
+    if app.mode != Mode::Search {
+        return;
+    }
+    let area = centered_rect(60, 60, f.size());
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(3), Constraint::Min(0)])
+        .split(area);
+    f.render_widget(Clear, area);
+
+    let input = Paragraph::new(app.search_input.input.as_str())
+        .style(Style::default().fg(Color::LightGreen))
+        .block(Block::default().borders(Borders::ALL).title("Search"));
+    f.render_widget(input, chunks[0]);
+    f.set_cursor(
+        chunks[0].x + app.search_input.cursor_position as u16 + 1,
+        chunks[0].y + 1,
+    );
+
+    let items: Vec<ListItem> = app.search_results
+        .items
+        .iter()
+        .map(|result| ListItem::new(result.text.clone()))
+        .collect();
+    let results = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title("Results"))
+        .highlight_style(
+            Style::default()
+                .bg(Color::Magenta)
+                .add_modifier(Modifier::BOLD),
+        );
+    f.render_stateful_widget(results, chunks[1], &mut app.search_results.state);
+}
+
 pub fn draw_error_popup<B: ratatui::backend::Backend>(f: &mut Frame<B>, app: &App) {
     if !app.error_message.is_err() {
         return;
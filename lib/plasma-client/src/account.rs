@@ -0,0 +1,416 @@
+use std::{marker::PhantomData, io::ErrorKind, sync::Arc};
+use bson::oid::ObjectId;
+use x3dh::{handshake::{RegisterBundle, OneTimePreKeyPublicBundle, InitialMessage, PeerBundle}, keys::{X3dhSharedSecret, IdentityKeyPair, IdentityKeyPublic, KeyPair, SignedPreKeyPair, OneTimeKeyPair, Key, Signature, EphemeralKeyPair}, x3dh_sig, x3dh};
+use crate::{api::{Api, ApiError, ErrorCode, body::{DeleteMessageBody, FindBody, MessagesBody, PayloadBody, SendMessageBody}, response::MessagesResponse}, chats::{Chats, get_non_user_id}, keyring::KeyStore, cipher::Cipher, store::{Store, CachedProfile}, session::{self, ChatSession}, envelope::Envelope};
+use crate::error::PlasmaError;
+
+struct KeyPack {
+    identity: IdentityKeyPair,
+    signed: SignedPreKeyPair,
+    one_time: Vec<OneTimeKeyPair>,
+    signature: Signature,
+}
+
+impl KeyPack {
+    fn generate(first_index: u16) -> Self {
+        let identity = IdentityKeyPair::generate(&mut rand::rngs::OsRng);
+        Self::with_identity(identity, first_index)
+    }
+
+    /// Fresh prekeys signed by an identity the account already has.
+    fn with_identity(identity: IdentityKeyPair, first_index: u16) -> Self {
+        let mut rng = rand::rngs::OsRng::default();
+
+        let signed = SignedPreKeyPair::generate(&mut rng);
+        let signature = identity.sign(&signed.public().key().to_sec1_bytes());
+        let onetime: Vec<OneTimeKeyPair> = (first_index..first_index+50)
+            .map(|index| OneTimeKeyPair::generate(&mut rng).with_index(index))
+            .collect();
+
+        KeyPack { identity, signed, one_time: onetime, signature }
+    }
+
+}
+
+pub struct Authorized;
+pub struct NotAuthorized;
+
+pub struct Account<State = NotAuthorized> {
+    mail: String,
+    username: Option<String>,
+    id: Option<ObjectId>,
+    token: Option<String>,
+    state: PhantomData<State>,
+    keys: Arc<dyn KeyStore>,
+    store: Option<Store>,
+    device: Option<ObjectId>,
+}
+
+const DEFAULT_DEVICE_NAME: &str = "plasmax";
+
+/// Name shown to other devices of the account when linking this one.
+fn device_name() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| String::from(DEFAULT_DEVICE_NAME))
+}
+
+impl Account {
+    pub fn new(mail: String, keys: Arc<dyn KeyStore>) -> Self {
+        Account {
+            mail,
+            username: None,
+            id: None,
+            token: None,
+            state: PhantomData,
+            keys,
+            store: None,
+            device: None,
+        }
+    }
+}
+
+impl Account<NotAuthorized> {
+    pub async fn try_login_token(self, api: &Api) -> Result<Account<Authorized>, PlasmaError> {
+        let token = self.keys.read_token()?;
+        let store = Store::open(self.keys.as_ref())?;
+        let profile = match (Self::fetch_profile(api, &token).await, &store) {
+            (Ok(profile), Some(store)) => {
+                store.save_profile(&profile)?;
+                profile
+            },
+            (Ok(profile), None) => profile,
+            (Err(err), Some(store)) if err.is_offline() => store.read_profile()?.ok_or(err)?,
+            (Err(err), _) => return Err(err),
+        };
+        let mut account = Account {
+            mail: self.mail,
+            username: Some(profile.username.clone()),
+            id: Some(profile.id()),
+            token: Some(token),
+            state: PhantomData,
+            keys: self.keys,
+            store,
+            device: None,
+        };
+        let approved = account.ensure_device(api).await?;
+        account.check_first_login(&api).await?;
+        if !approved {
+            return Err(PlasmaError::DevicePending(*account.device()));
+        }
+        Ok(account)
+    }
+
+    async fn fetch_profile(api: &Api, token: &str) -> Result<CachedProfile, PlasmaError> {
+        let username = api.dashboard(token).await?;
+        let params = FindBody::username(username.clone());
+        let id = api.find(token, params).await?.id;
+        Ok(CachedProfile::new(&username, &id))
+    }
+
+    pub async fn login(self, password: String, api: &Api) -> Result<Account<Authorized>, PlasmaError> {
+        let token = api.login(&self.mail, password).await?;
+        self.keys.save_token(&token)?;
+        self.try_login_token(api).await
+    }
+}
+
+impl Account<Authorized> {
+    /// Changes the password, keeping this session while every other one is signed out.
+    pub async fn change_password(&mut self, api: &Api, password: String, new_password: String) -> Result<(), PlasmaError> {
+        let token = api.change_password(self.token(), password, new_password).await?;
+        self.keys.save_token(&token)?;
+        self.token = Some(token);
+        Ok(())
+    }
+
+    pub fn token(&self) -> &str {
+        self.token.as_ref()
+            .expect("Authorized user has token field")
+    }
+
+    pub fn username(&self) -> &String {
+        self.username.as_ref()
+            .expect("Authorized user has username field")
+    }
+
+    pub fn id(&self) -> &ObjectId {
+        self.id.as_ref()
+            .expect("Authorized user has id field")
+    }
+
+    /// Local cache, missing when the key store keeps no files.
+    pub fn store(&self) -> Option<&Store> {
+        self.store.as_ref()
+    }
+
+    pub fn device(&self) -> &ObjectId {
+        self.device.as_ref()
+            .expect("Authorized user has device field")
+    }
+
+    /// Registers this installation as a device of the account unless the server
+    /// already knows it, returns whether the device is linked.
+    async fn ensure_device(&mut self, api: &Api) -> Result<bool, PlasmaError> {
+        let device = match self.keys.read_device() {
+            Ok(device) => device,
+            Err(err) if err.kind() == ErrorKind::NotFound => return self.register_device(api).await,
+            Err(err) => return Err(err.into()),
+        };
+        self.device = Some(device);
+        match api.devices(self.token()).await {
+            Ok(devices) => match devices.iter().find(|d| d.id == device) {
+                Some(d) => Ok(d.approved),
+                // Unlinked by another device, start over as a new one.
+                None => self.register_device(api).await,
+            },
+            Err(err) => {
+                let err = PlasmaError::from(err);
+                match err.is_offline() {
+                    true => Ok(true),
+                    false => Err(err),
+                }
+            },
+        }
+    }
+
+    async fn register_device(&mut self, api: &Api) -> Result<bool, PlasmaError> {
+        let device = api.register_device(self.token(), &device_name()).await?;
+        self.keys.save_device(&device.id)?;
+        self.device = Some(device.id);
+        self.register_bundle(api).await?;
+        Ok(device.approved)
+    }
+
+    /// Opens sessions with every linked device of `username` and the other devices of this account.
+    pub async fn open_session(&self, api: &Api, username: &str) -> Result<ChatSession, PlasmaError> {
+        let mut session = ChatSession::default();
+        for device in api.user_devices(self.token(), username).await? {
+            self.join_session(api, &mut session, username, &device.id).await?;
+        }
+        for device in api.user_devices(self.token(), self.username()).await? {
+            if device.id != *self.device() {
+                self.join_session(api, &mut session, self.username(), &device.id).await?;
+            }
+        }
+        Ok(session)
+    }
+
+    /// Adds device to the session, devices the server can't start a session with yet are left out.
+    pub async fn join_session(&self, api: &Api, session: &mut ChatSession, username: &str, device: &ObjectId) -> Result<bool, PlasmaError> {
+        match self.ensure_secret(api, username, device).await {
+            Ok(()) => {},
+            Err(PlasmaError::ServerError(ApiError::Server { .. })) => return Ok(false),
+            Err(err) => return Err(err),
+        }
+        session.insert(*device, self.get_cipher(username, device)?);
+        Ok(true)
+    }
+
+    /// Decrypts payload addressed to this device, joining sender device to the session when it's new.
+    /// Returns `None` for messages that weren't encrypted for this device.
+    pub async fn decrypt(&self, api: &Api, session: &mut ChatSession, sender: &str, sender_device: Option<ObjectId>, content: Option<&[u8]>, timestamp: u64) -> Result<Option<Envelope>, PlasmaError> {
+        let (sender_device, content) = match (sender_device, content) {
+            (Some(device), Some(content)) => (device, content),
+            _ => return Ok(None),
+        };
+        if !session.contains(&sender_device)
+            && !self.join_session(api, session, sender, &sender_device).await? {
+            return Ok(None);
+        }
+        Ok(Some(session.decrypt(&sender_device, content, timestamp)?))
+    }
+
+    /// Posts envelope encrypted for every device of the session, returns its timestamp.
+    /// With `edit_of` set it replaces content of that earlier message, an edit
+    /// disappears together with the original so `timer` is ignored for it.
+    pub async fn send(&self, api: &Api, chat_id: &ObjectId, session: &ChatSession, envelope: &Envelope, edit_of: Option<ObjectId>, timer: Option<u64>) -> Result<u64, PlasmaError> {
+        let timestamp = session::timestamp();
+        let payloads = session.encrypt(envelope, timestamp)?
+            .into_iter()
+            .map(|(device_id, content)| PayloadBody { device_id, content })
+            .collect();
+        let expires_at = session::expiry(timestamp, timer);
+        let params = SendMessageBody { chat_id: *chat_id, payloads, timestamp, edit_of, expires_at };
+        api.send_message(self.token(), self.device(), &params).await?;
+        Ok(timestamp)
+    }
+
+    /// Deletes own message for everyone.
+    pub async fn delete_message(&self, api: &Api, chat_id: &ObjectId, message_id: &ObjectId) -> Result<(), PlasmaError> {
+        let params = DeleteMessageBody { chat_id: *chat_id, message_id: *message_id };
+        api.delete_message(self.token(), &params).await?;
+        Ok(())
+    }
+
+    pub async fn ensure_secret(&self, api: &Api, username: &str, device: &ObjectId) -> Result<(), PlasmaError> {
+        if self.keys.read_secret(username, device).is_ok() {
+            return Ok(());
+        }
+        if let Some(message) = api.get_initial_message(self.token(), self.device(), device).await? {
+            return self.make_secret_from_initial_messsage(username, device, message);
+        }
+        let bundle = api.get_peer_bundle(self.token(), username, device).await?;
+        if self.adopt_legacy_secret(username, device, &bundle.identity)? {
+            return Ok(());
+        }
+        let (secret, message) = self.make_secret_from_peer_bundle(bundle, username, device)?;
+        match api.send_initial_message(self.token(), self.device(), device, message).await {
+            Ok(()) => {
+                self.keys.save_secret(username, device, &secret)?;
+                Ok(())
+            },
+            // Other device started a session at the same time, the first one stored wins.
+            Err(err @ ApiError::Server { code: ErrorCode::AlreadyExists, .. }) => {
+                let message = api.get_initial_message(self.token(), self.device(), device).await?
+                    .ok_or(err)?;
+                self.make_secret_from_initial_messsage(username, device, message)
+            },
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn get_cipher(&self, username: &str, device: &ObjectId) -> Result<Cipher, PlasmaError> {
+        let secret = self.keys.read_secret(username, device)?;
+        Ok(Cipher::new(secret))
+    }
+
+    /// Carries the secret made with `username` before devices existed over to their
+    /// device still holding the identity it was made with, both sides do the same so
+    /// no new handshake is needed. The legacy entries stay for other devices restored
+    /// from the same keyring.
+    fn adopt_legacy_secret(&self, username: &str, device: &ObjectId, identity: &IdentityKeyPublic) -> Result<bool, PlasmaError> {
+        let (secret, known) = match self.keys.read_legacy_contact(username) {
+            Ok(legacy) => legacy,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err.into()),
+        };
+        if &known != identity {
+            return Ok(false);
+        }
+        self.keys.save_contact(username, device, identity)?;
+        self.keys.save_secret(username, device, &secret)?;
+        Ok(true)
+    }
+
+    /// Pins identity of a contact device on first use, refusing a secret with a changed one.
+    fn trust_contact(&self, member: &str, device: &ObjectId, identity: &IdentityKeyPublic) -> Result<(), PlasmaError> {
+        match self.keys.read_contact(member, device) {
+            Ok(known) if &known == identity => Ok(()),
+            Ok(_) => Err(PlasmaError::IdentityChanged(member.to_owned())),
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                self.keys.save_contact(member, device, identity)?;
+                Ok(())
+            },
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn make_secret_from_initial_messsage(&self, member: &str, device: &ObjectId, message: InitialMessage) -> Result<(), PlasmaError> {
+        let identity = self.keys.read_identity()?;
+        let signed = self.keys.read_signed()?;
+        let onetime = self.keys.read_onetime(message.one_time_idx)?;
+        let secret = x3dh(
+            &message.identity,
+            &signed,
+            &message.ephemeral,
+            &identity,
+            &onetime
+            );
+        self.trust_contact(member, device, &message.identity)?;
+        self.keys.save_secret(member, device, &secret)?;
+        Ok(())
+    }
+
+    fn make_secret_from_peer_bundle(&self, bundle: PeerBundle, member: &str, device: &ObjectId) -> Result<(X3dhSharedSecret, InitialMessage), PlasmaError> {
+        let identity = self.keys.read_identity()?;
+        let mut rng = rand::rngs::OsRng::default();
+        let ephemeral = EphemeralKeyPair::generate(&mut rng);
+        let secret = x3dh_sig(
+            &bundle.signature, 
+            &identity, 
+            &bundle.signed_pre, 
+            &ephemeral, 
+            &bundle.identity, 
+            &bundle.one_time_pre.key()
+            )?;
+        self.trust_contact(member, device, &bundle.identity)?;
+        let message = InitialMessage {
+            identity: identity.public().clone(),
+            ephemeral: ephemeral.public().clone(),
+            one_time_idx: bundle.one_time_pre.index(),
+        };
+        Ok((secret, message))
+    }
+
+    pub async fn chats(&self, api: &Api) -> Result<Chats, PlasmaError> {
+        let chats = api.chats(self.token()).await?;
+        let mut usernames: Vec<String> = Vec::new();
+        for chat in chats.iter() {
+            let id = get_non_user_id(&chat.users, self.id())
+                .expect("Chats without self not supported");
+            let params = FindBody::id(id);
+            let un = api.find(self.token(), params).await?.username;
+            usernames.push(un);
+        }
+        let chats = Chats::new(chats, usernames, self.id());
+
+        Ok(chats)
+    }
+
+    pub async fn chat(&self, api: &Api, username: &str) -> Result<ObjectId, PlasmaError> {
+        let chat_id = api.chat(self.token(), username).await?;
+        Ok(chat_id)
+    }
+
+    pub async fn messages(&self, api: &Api, params: &MessagesBody) -> Result<MessagesResponse, PlasmaError> {
+        let page = api.messages(self.token(), params).await?;
+        Ok(page)
+    }
+
+    pub async fn check_first_login(&self, api: &Api) -> Result<(), PlasmaError> {
+        match self.keys.read_identity() {
+            Ok(_) => Ok(()),
+            Err(_) => self.register_bundle(&api).await
+        }
+    }
+
+    /// Uploads prekeys of this device, keeping the identity when the keyring already
+    /// has one so contacts and sessions from before the device was registered stay valid.
+    pub async fn register_bundle(&self, api: &Api) -> Result<(), PlasmaError> {
+        let key_pack = match self.keys.read_identity() {
+            Ok(identity) => KeyPack::with_identity(identity, 0),
+            Err(err) if err.kind() == ErrorKind::NotFound => KeyPack::generate(0),
+            Err(err) => return Err(err.into()),
+        };
+        self.save_key_pack(&key_pack)?;
+        self.upload_bundle(api, key_pack).await?;
+        Ok(())
+    }
+
+    fn save_key_pack(&self, key_pack: &KeyPack) -> Result<(), PlasmaError> {
+        self.keys.save_identity(&key_pack.identity)?;
+        self.keys.save_signed(&key_pack.signed)?;
+        for key in key_pack.one_time.iter() {
+            self.keys.save_onetime(key)?;
+        }
+        Ok(())
+    }
+
+    async fn upload_bundle(&self, api: &Api, key_pack: KeyPack) -> Result<(), PlasmaError> {
+        let bundle = RegisterBundle {
+            identity: key_pack.identity.public().clone(),
+            signed_pre: key_pack.signed.public().clone(),
+            signature: key_pack.signature,
+            one_time_pres: key_pack.one_time.iter()
+                .map(|key| OneTimePreKeyPublicBundle::from_pair(key))
+                .collect(),
+        };
+        api.send_bundle(self.token(), self.device(), &bundle).await?;
+
+        Ok(())
+    }
+}
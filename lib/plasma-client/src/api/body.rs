@@ -0,0 +1,196 @@
+use serde::Serialize;
+use bson::oid::ObjectId;
+use x3dh::handshake;
+
+#[derive(Serialize)]
+pub struct RegisterBody {
+    pub email: String,
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Serialize)]
+pub struct LoginBody {
+    pub email: String,
+    pub password: String,
+}
+
+#[derive(Serialize)]
+pub struct FindBody {
+    pub id: Option<ObjectId>,
+    pub email: Option<String>,
+    pub username: Option<String>,
+}
+
+impl FindBody {
+    pub fn id(id: ObjectId) -> Self {
+        FindBody { id: Some(id), email: None, username: None, }
+    }
+    pub fn username(username: String) -> Self {
+        FindBody { id: None, email: None, username: Some(username) }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ChatBody {
+    pub member: String,
+}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Cursor {
+    Id(ObjectId),
+    Timestamp(u64),
+    Position { timestamp: u64, id: ObjectId },
+}
+
+impl Cursor {
+    /// Right at a message, timestamps alone can be shared by several messages.
+    pub fn at(timestamp: u64, id: Option<ObjectId>) -> Self {
+        match id {
+            Some(id) => Cursor::Position { timestamp, id },
+            None => Cursor::Timestamp(timestamp),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct MessagesBody {
+    pub chat_id: ObjectId,
+    pub before: Option<Cursor>,
+    pub after: Option<Cursor>,
+    pub limit: Option<i64>,
+}
+
+impl MessagesBody {
+    pub fn latest(chat_id: ObjectId) -> Self {
+        MessagesBody { chat_id, before: None, after: None, limit: None }
+    }
+    pub fn before(chat_id: ObjectId, cursor: Cursor) -> Self {
+        MessagesBody { chat_id, before: Some(cursor), after: None, limit: None }
+    }
+    pub fn after(chat_id: ObjectId, cursor: Cursor) -> Self {
+        MessagesBody { chat_id, before: None, after: Some(cursor), limit: None }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PayloadBody {
+    pub device_id: ObjectId,
+    pub content: Vec<u8>,
+}
+
+#[derive(Serialize)]
+pub struct SendMessageBody {
+    pub chat_id: ObjectId,
+    pub payloads: Vec<PayloadBody>,
+    pub timestamp: u64,
+    pub edit_of: Option<ObjectId>,
+    pub expires_at: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct DeleteMessageBody {
+    pub chat_id: ObjectId,
+    pub message_id: ObjectId,
+}
+
+#[derive(Serialize)]
+pub struct SendInitialMessageBody {
+    pub to_device: ObjectId,
+    pub message: handshake::InitialMessageBinary,
+}
+
+#[derive(Serialize)]
+pub struct GetInitialMessageBody {
+    pub from_device: ObjectId,
+}
+
+#[derive(Serialize)]
+pub struct PeerBundleBody {
+    pub username: String,
+    pub device_id: ObjectId,
+}
+
+#[derive(Serialize)]
+pub struct RegisterDeviceBody {
+    pub name: String,
+}
+
+#[derive(Serialize)]
+pub struct UserDevicesBody {
+    pub username: String,
+}
+
+#[derive(Serialize)]
+pub struct TargetDeviceBody {
+    pub device_id: ObjectId,
+}
+
+#[derive(Serialize)]
+pub struct PresenceBody {
+    pub hidden: bool,
+}
+
+#[derive(Serialize)]
+pub struct DiscoveryBody {
+    pub hidden: bool,
+}
+
+#[derive(Serialize)]
+pub struct SearchUsersBody {
+    pub query: String,
+}
+
+#[derive(Serialize)]
+pub struct AddContactBody {
+    pub username: String,
+    pub nickname: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct RemoveContactBody {
+    pub username: String,
+}
+
+#[derive(Serialize)]
+pub struct VerifyBody {
+    pub token: String,
+}
+
+#[derive(Serialize)]
+pub struct ChangePasswordBody {
+    pub password: String,
+    pub new_password: String,
+}
+
+#[derive(Serialize)]
+pub struct ForgotPasswordBody {
+    pub email: String,
+}
+
+#[derive(Serialize)]
+pub struct ResetPasswordBody {
+    pub token: String,
+    pub password: String,
+}
+
+#[derive(Serialize)]
+pub struct ConfirmPasswordBody {
+    pub password: String,
+}
+
+#[derive(Serialize)]
+pub struct BlockBody {
+    pub username: String,
+}
+
+#[derive(Serialize)]
+pub struct ReportBody {
+    pub username: String,
+    pub reason: String,
+    pub excerpt: String,
+    pub chat_id: Option<ObjectId>,
+    pub message_id: Option<ObjectId>,
+    pub block: bool,
+}
@@ -0,0 +1,87 @@
+use serde::Deserialize;
+
+/// Error codes returned by plasma-server in the `error` field of failed responses.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    BadRequest,
+    MissingField,
+    InvalidId,
+    InvalidUsername,
+    InvalidEmail,
+    WeakPassword,
+    Unauthorized,
+    InvalidCredentials,
+    Forbidden,
+    NotFound,
+    UserNotFound,
+    ChatNotFound,
+    BundleNotFound,
+    DeviceNotFound,
+    DeviceNotApproved,
+    Blocked,
+    EmailNotVerified,
+    EmailTaken,
+    UsernameTaken,
+    AlreadyExists,
+    PrekeysExhausted,
+    RateLimited,
+    InvalidToken,
+    MailFailed,
+    #[serde(rename = "internal_error")]
+    Internal,
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Deserialize)]
+pub struct ErrorResponse {
+    pub error: ErrorCode,
+    pub message: String,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ApiError {
+    #[error(transparent)]
+    ReqwestError( #[from] reqwest::Error ),
+    #[error("{message}")]
+    Server { code: ErrorCode, message: String },
+}
+
+#[cfg(test)]
+mod error_test {
+    use super::{ErrorResponse, ErrorCode};
+
+    #[test]
+    fn decode_known_code() {
+        let body = r#"{"error": "username_taken", "message": "Username already in use"}"#;
+        let err: ErrorResponse = serde_json::from_str(body).unwrap();
+
+        assert_eq!(err.error, ErrorCode::UsernameTaken);
+        assert_eq!(err.message, "Username already in use");
+    }
+
+    #[test]
+    fn decode_internal_code() {
+        let body = r#"{"error": "internal_error", "message": "Internal server error"}"#;
+        let err: ErrorResponse = serde_json::from_str(body).unwrap();
+
+        assert_eq!(err.error, ErrorCode::Internal);
+    }
+
+    #[test]
+    fn decode_unverified_code() {
+        let body = r#"{"error": "email_not_verified", "message": "Verify your email address before starting chats"}"#;
+        let err: ErrorResponse = serde_json::from_str(body).unwrap();
+
+        assert_eq!(err.error, ErrorCode::EmailNotVerified);
+    }
+
+    #[test]
+    fn decode_unknown_code() {
+        let body = r#"{"error": "something_new", "message": "Something new"}"#;
+        let err: ErrorResponse = serde_json::from_str(body).unwrap();
+
+        assert_eq!(err.error, ErrorCode::Unknown);
+    }
+}
@@ -0,0 +1,668 @@
+pub mod body;
+pub mod response;
+pub mod ws;
+pub mod error;
+
+use bson::oid::ObjectId;
+use reqwest::{Client, Response};
+use serde::de::DeserializeOwned;
+use url::Url;
+use x3dh::handshake;
+pub use error::{ApiError, ErrorCode};
+
+/// Header naming the device a request is made from.
+pub const DEVICE_HEADER: &str = "x-device-id";
+
+#[derive(Clone)]
+pub struct Api {
+    client: Client,
+    base: Url,
+}
+
+impl Api {
+    pub fn new(base: &Url) -> Self {
+        Api {
+            client: Client::new(),
+            base: base.clone(),
+        }
+    }
+
+    fn api_path(&self, endpoint: &str) -> Url {
+        self.base
+            .join(endpoint)
+            .expect("Hardcoded enpoint")
+    }
+
+    async fn parse<T: DeserializeOwned>(response: Result<Response, reqwest::Error>) -> Result<T, ApiError> {
+        let response = response?;
+        let status = response.status();
+        if !status.is_success() {
+            let err = match response.json::<error::ErrorResponse>().await {
+                Ok(err) => ApiError::Server { code: err.error, message: err.message },
+                Err(_) => ApiError::Server {
+                    code: ErrorCode::Unknown,
+                    message: format!("Server responded with {}", status),
+                },
+            };
+            return Err(err);
+        }
+        let data = response
+            .json::<response::OkResponse<T>>().await?
+            .data;
+        Ok(data)
+    }
+
+    pub async fn login(&self, email: &str, password: String) -> Result<String, ApiError> {
+        let url = self.api_path("login");
+        let params = body::LoginBody {
+            email: String::from(email),
+            password: String::from(password),
+        };
+
+        let response = self.client
+            .post(url)
+            .json(&params)
+            .send()
+            .await;
+
+        let jwt = Self::parse::<response::LoginResponse>(response).await?
+            .jwtoken
+            .clone();
+
+        Ok(jwt)
+    }
+
+    pub async fn register(&self, email: &str, username: &str, password: String) -> Result<bool, ApiError> {
+        let url = self.api_path("register");
+        let params = body::RegisterBody {
+            email: String::from(email),
+            username: String::from(username),
+            password: String::from(password),
+        };
+
+        let response = self.client
+            .post(url)
+            .json(&params)
+            .send()
+            .await;
+
+        let message = Self::parse::<response::RegisterResponse>(response).await?
+            .message;
+
+        Ok(message.eq("success"))
+    }
+
+    pub async fn dashboard(&self, token: &str) -> Result<String, ApiError> {
+        let url = self.api_path("dashboard");
+
+        let response = self.client
+            .get(url)
+            .bearer_auth(token)
+            .send()
+            .await;
+
+        let username = Self::parse::<response::DashboardResponse>(response).await?
+            .username;
+
+        Ok(username)
+    }
+
+    pub async fn find(&self, token: &str, params: body::FindBody) -> Result<response::User, ApiError> {
+        let url = self.api_path("user");
+
+        let response = self.client
+            .post(url)
+            .bearer_auth(token)
+            .json(&params)
+            .send()
+            .await;
+
+        let user = Self::parse::<response::FindResponse>(response).await?
+            .user;
+
+        Ok(user)
+    }
+
+    pub async fn chats(&self, token: &str) -> Result<Vec<response::Chat>, ApiError> {
+        let url = self.api_path("chats");
+
+        let response = self.client
+            .get(url)
+            .bearer_auth(token)
+            .send()
+            .await;
+
+        let chats = Self::parse::<response::ChatsResponse>(response).await?
+            .chats;
+
+        Ok(chats)
+    }
+
+    pub async fn chat(&self, token: &str, member: &str) -> Result<ObjectId, ApiError> {
+        let url = self.api_path("chat");
+
+        let params = body::ChatBody {
+            member: String::from(member),
+        };
+
+        let response = self.client
+            .post(url)
+            .bearer_auth(token)
+            .json(&params)
+            .send()
+            .await;
+
+        let chat = Self::parse::<response::ChatResponse>(response).await?
+            .chatid;
+
+        Ok(chat)
+    }
+
+    pub async fn messages(&self, token: &str, params: &body::MessagesBody) -> Result<response::MessagesResponse, ApiError> {
+        let url = self.api_path("messages");
+
+        let response = self.client
+            .post(url)
+            .json(params)
+            .bearer_auth(token)
+            .send()
+            .await;
+
+        let page = Self::parse::<response::MessagesResponse>(response).await?;
+
+        Ok(page)
+    }
+
+    pub async fn send_message(&self, token: &str, device: &ObjectId, params: &body::SendMessageBody) -> Result<(), ApiError> {
+        let url = self.api_path("message");
+
+        let response = self.client
+            .post(url)
+            .json(params)
+            .bearer_auth(token)
+            .header(DEVICE_HEADER, device.to_hex())
+            .send()
+            .await;
+
+        Self::parse::<response::SendMessageResponse>(response).await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_message(&self, token: &str, params: &body::DeleteMessageBody) -> Result<(), ApiError> {
+        let url = self.api_path("message/delete");
+
+        let response = self.client
+            .post(url)
+            .json(params)
+            .bearer_auth(token)
+            .send()
+            .await;
+
+        Self::parse::<response::DeleteMessageResponse>(response).await?;
+
+        Ok(())
+    }
+
+    pub async fn send_bundle(&self, token: &str, device: &ObjectId, bundle: &handshake::RegisterBundle) -> Result<String, ApiError> {
+        let url = self.api_path("bundle");
+
+        let response = self.client
+            .post(url)
+            .json(&bundle.serialize())
+            .bearer_auth(token)
+            .header(DEVICE_HEADER, device.to_hex())
+            .send()
+            .await;
+
+        let response = Self::parse::<response::SendBundleResponse>(response).await?
+            .bundle;
+
+        Ok(response)
+    }
+
+    pub async fn get_peer_bundle(&self, token: &str, username: &str, device: &ObjectId) -> Result<handshake::PeerBundle, ApiError> {
+        let url = self.api_path("peer_bundle");
+
+        let params = body::PeerBundleBody {
+            username: String::from(username),
+            device_id: *device,
+        };
+
+        let response = self.client
+            .post(url)
+            .json(&params)
+            .bearer_auth(token)
+            .send()
+            .await;
+
+        let bundle = Self::parse::<response::PeerBundleResponse>(response).await?
+            .bundle
+            .deserialize();
+
+        Ok(bundle)
+    }
+
+    pub async fn send_initial_message(&self, token: &str, device: &ObjectId, to_device: &ObjectId, message: handshake::InitialMessage) -> Result<(), ApiError> {
+        let url = self.api_path("initial_message");
+
+        let params = body::SendInitialMessageBody {
+            to_device: *to_device,
+            message: message.serialize(),
+        };
+
+        let response = self.client
+            .post(url)
+            .json(&params)
+            .bearer_auth(token)
+            .header(DEVICE_HEADER, device.to_hex())
+            .send()
+            .await;
+
+        Self::parse::<response::InitialMessageResponse>(response).await?;
+
+        Ok(())
+    }
+
+    pub async fn get_initial_message(&self, token: &str, device: &ObjectId, from_device: &ObjectId) -> Result<Option<handshake::InitialMessage>, ApiError> {
+        let url = self.api_path("get_initial_message");
+
+        let params = body::GetInitialMessageBody {
+            from_device: *from_device,
+        };
+
+        let response = self.client
+            .post(url)
+            .json(&params)
+            .bearer_auth(token)
+            .header(DEVICE_HEADER, device.to_hex())
+            .send()
+            .await;
+
+        let message = Self::parse::<response::GetInitialMesssageResponse>(response).await?
+            .message;
+
+        let message = message.map(|m| m.deserialize());
+
+        Ok(message)
+    }
+
+    pub async fn register_device(&self, token: &str, name: &str) -> Result<response::Device, ApiError> {
+        let url = self.api_path("device");
+
+        let params = body::RegisterDeviceBody {
+            name: String::from(name),
+        };
+
+        let response = self.client
+            .post(url)
+            .json(&params)
+            .bearer_auth(token)
+            .send()
+            .await;
+
+        let device = Self::parse::<response::DeviceResponse>(response).await?
+            .device;
+
+        Ok(device)
+    }
+
+    /// All devices of the logged in account, including ones waiting for approval.
+    pub async fn devices(&self, token: &str) -> Result<Vec<response::Device>, ApiError> {
+        let url = self.api_path("devices");
+
+        let response = self.client
+            .get(url)
+            .bearer_auth(token)
+            .send()
+            .await;
+
+        let devices = Self::parse::<response::DevicesResponse>(response).await?
+            .devices;
+
+        Ok(devices)
+    }
+
+    /// Linked devices of a user, every one of them gets its own copy of a message.
+    pub async fn user_devices(&self, token: &str, username: &str) -> Result<Vec<response::Device>, ApiError> {
+        let url = self.api_path("user_devices");
+
+        let params = body::UserDevicesBody {
+            username: String::from(username),
+        };
+
+        let response = self.client
+            .post(url)
+            .json(&params)
+            .bearer_auth(token)
+            .send()
+            .await;
+
+        let devices = Self::parse::<response::DevicesResponse>(response).await?
+            .devices;
+
+        Ok(devices)
+    }
+
+    pub async fn approve_device(&self, token: &str, device: &ObjectId, target: &ObjectId) -> Result<(), ApiError> {
+        self.device_action("device/approve", token, device, target).await
+    }
+
+    pub async fn remove_device(&self, token: &str, device: &ObjectId, target: &ObjectId) -> Result<(), ApiError> {
+        self.device_action("device/remove", token, device, target).await
+    }
+
+    /// Hides online state and last seen from every contact, or shows them again.
+    pub async fn set_presence_hidden(&self, token: &str, hidden: bool) -> Result<(), ApiError> {
+        let url = self.api_path("presence");
+        let params = body::PresenceBody { hidden };
+
+        let response = self.client
+            .put(url)
+            .json(&params)
+            .bearer_auth(token)
+            .send()
+            .await;
+
+        Self::parse::<response::PresenceResponse>(response).await?;
+
+        Ok(())
+    }
+
+    /// Leaves the account out of user search, or lists it again.
+    pub async fn set_search_hidden(&self, token: &str, hidden: bool) -> Result<(), ApiError> {
+        let url = self.api_path("discovery");
+        let params = body::DiscoveryBody { hidden };
+
+        let response = self.client
+            .put(url)
+            .json(&params)
+            .bearer_auth(token)
+            .send()
+            .await;
+
+        Self::parse::<response::PresenceResponse>(response).await?;
+
+        Ok(())
+    }
+
+    /// Users whose name starts with `query` or contains its letters in order, best matches first.
+    pub async fn search_users(&self, token: &str, query: &str) -> Result<Vec<response::FoundUser>, ApiError> {
+        let url = self.api_path("users/search");
+
+        let params = body::SearchUsersBody {
+            query: String::from(query),
+        };
+
+        let response = self.client
+            .post(url)
+            .json(&params)
+            .bearer_auth(token)
+            .send()
+            .await;
+
+        let users = Self::parse::<response::SearchUsersResponse>(response).await?
+            .users;
+
+        Ok(users)
+    }
+
+    pub async fn contacts(&self, token: &str) -> Result<Vec<response::Contact>, ApiError> {
+        let url = self.api_path("contacts");
+
+        let response = self.client
+            .get(url)
+            .bearer_auth(token)
+            .send()
+            .await;
+
+        let contacts = Self::parse::<response::ContactsResponse>(response).await?
+            .contacts;
+
+        Ok(contacts)
+    }
+
+    /// Adds user to the contacts, or changes the nickname of one already there.
+    pub async fn add_contact(&self, token: &str, username: &str, nickname: Option<&str>) -> Result<response::Contact, ApiError> {
+        let url = self.api_path("contact");
+
+        let params = body::AddContactBody {
+            username: String::from(username),
+            nickname: nickname.map(String::from),
+        };
+
+        let response = self.client
+            .post(url)
+            .json(&params)
+            .bearer_auth(token)
+            .send()
+            .await;
+
+        Self::parse::<response::Contact>(response).await
+    }
+
+    pub async fn remove_contact(&self, token: &str, username: &str) -> Result<(), ApiError> {
+        let url = self.api_path("contact/remove");
+
+        let params = body::RemoveContactBody {
+            username: String::from(username),
+        };
+
+        let response = self.client
+            .post(url)
+            .json(&params)
+            .bearer_auth(token)
+            .send()
+            .await;
+
+        Self::parse::<response::PresenceResponse>(response).await?;
+
+        Ok(())
+    }
+
+    /// Users the logged in account blocked.
+    pub async fn blocked(&self, token: &str) -> Result<Vec<response::BlockedUser>, ApiError> {
+        let url = self.api_path("blocks");
+
+        let response = self.client
+            .get(url)
+            .bearer_auth(token)
+            .send()
+            .await;
+
+        let blocked = Self::parse::<response::BlockedResponse>(response).await?
+            .blocked;
+
+        Ok(blocked)
+    }
+
+    /// Stops `username` from starting chats, messaging or fetching keys of this account, and the other way around.
+    pub async fn block(&self, token: &str, username: &str) -> Result<(), ApiError> {
+        self.block_action("block", token, username).await
+    }
+
+    pub async fn unblock(&self, token: &str, username: &str) -> Result<(), ApiError> {
+        self.block_action("block/remove", token, username).await
+    }
+
+    /// Sends decrypted `params.excerpt` to moderators, returns id of the report.
+    pub async fn report(&self, token: &str, params: &body::ReportBody) -> Result<ObjectId, ApiError> {
+        let url = self.api_path("report");
+
+        let response = self.client
+            .post(url)
+            .json(params)
+            .bearer_auth(token)
+            .send()
+            .await;
+
+        let report = Self::parse::<response::ReportResponse>(response).await?
+            .report;
+
+        Ok(report)
+    }
+
+    /// Verifies the account email with the token mailed on registration.
+    pub async fn verify_email(&self, token: &str) -> Result<(), ApiError> {
+        let url = self.api_path("verify");
+        let params = body::VerifyBody {
+            token: String::from(token),
+        };
+
+        let response = self.client
+            .post(url)
+            .json(&params)
+            .send()
+            .await;
+
+        Self::parse::<response::PresenceResponse>(response).await?;
+
+        Ok(())
+    }
+
+    /// Mails a new verification token, false when the account is verified already.
+    pub async fn resend_verification(&self, token: &str) -> Result<bool, ApiError> {
+        let url = self.api_path("verify/resend");
+
+        let response = self.client
+            .post(url)
+            .bearer_auth(token)
+            .send()
+            .await;
+
+        let sent = Self::parse::<response::VerificationResponse>(response).await?
+            .sent;
+
+        Ok(sent)
+    }
+
+    /// Changes the password, signing out every other session. Returns the token
+    /// replacing `token`, which stops working.
+    pub async fn change_password(&self, token: &str, password: String, new_password: String) -> Result<String, ApiError> {
+        let url = self.api_path("password");
+        let params = body::ChangePasswordBody { password, new_password };
+
+        let response = self.client
+            .put(url)
+            .json(&params)
+            .bearer_auth(token)
+            .send()
+            .await;
+
+        let jwt = Self::parse::<response::LoginResponse>(response).await?
+            .jwtoken;
+
+        Ok(jwt)
+    }
+
+    /// Asks the server to mail a reset token, succeeds for unknown addresses too.
+    pub async fn forgot_password(&self, email: &str) -> Result<(), ApiError> {
+        let url = self.api_path("password/forgot");
+        let params = body::ForgotPasswordBody {
+            email: String::from(email),
+        };
+
+        let response = self.client
+            .post(url)
+            .json(&params)
+            .send()
+            .await;
+
+        Self::parse::<response::PresenceResponse>(response).await?;
+
+        Ok(())
+    }
+
+    /// Sets a new password with a mailed reset token.
+    pub async fn reset_password(&self, token: &str, password: String) -> Result<(), ApiError> {
+        let url = self.api_path("password/reset");
+        let params = body::ResetPasswordBody {
+            token: String::from(token),
+            password,
+        };
+
+        let response = self.client
+            .post(url)
+            .json(&params)
+            .send()
+            .await;
+
+        Self::parse::<response::PresenceResponse>(response).await?;
+
+        Ok(())
+    }
+
+    /// Everything the server keeps about the account, as sent by the server.
+    pub async fn export_account(&self, token: &str, password: String) -> Result<serde_json::Value, ApiError> {
+        let url = self.api_path("account/export");
+        let params = body::ConfirmPasswordBody { password };
+
+        let response = self.client
+            .post(url)
+            .json(&params)
+            .bearer_auth(token)
+            .send()
+            .await;
+
+        let account = Self::parse::<response::AccountExportResponse>(response).await?
+            .account;
+
+        Ok(account)
+    }
+
+    /// Deletes the account on the server, the token stops working afterwards.
+    pub async fn delete_account(&self, token: &str, password: String) -> Result<(), ApiError> {
+        let url = self.api_path("account/delete");
+        let params = body::ConfirmPasswordBody { password };
+
+        let response = self.client
+            .post(url)
+            .json(&params)
+            .bearer_auth(token)
+            .send()
+            .await;
+
+        Self::parse::<response::PresenceResponse>(response).await?;
+
+        Ok(())
+    }
+
+    async fn block_action(&self, endpoint: &str, token: &str, username: &str) -> Result<(), ApiError> {
+        let url = self.api_path(endpoint);
+
+        let params = body::BlockBody {
+            username: String::from(username),
+        };
+
+        let response = self.client
+            .post(url)
+            .json(&params)
+            .bearer_auth(token)
+            .send()
+            .await;
+
+        Self::parse::<response::PresenceResponse>(response).await?;
+
+        Ok(())
+    }
+
+    async fn device_action(&self, endpoint: &str, token: &str, device: &ObjectId, target: &ObjectId) -> Result<(), ApiError> {
+        let url = self.api_path(endpoint);
+
+        let params = body::TargetDeviceBody {
+            device_id: *target,
+        };
+
+        let response = self.client
+            .post(url)
+            .json(&params)
+            .bearer_auth(token)
+            .header(DEVICE_HEADER, device.to_hex())
+            .send()
+            .await;
+
+        Self::parse::<response::DeviceActionResponse>(response).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,201 @@
+use bson::oid::ObjectId;
+use serde::Deserialize;
+use x3dh::handshake;
+
+#[derive(Deserialize)]
+pub struct OkResponse<T> {
+    pub data: T,
+}
+
+#[derive(Deserialize)]
+pub struct RegisterResponse {
+    pub message: String,
+}
+
+#[derive(Deserialize)]
+pub struct LoginResponse {
+    pub jwtoken: String,
+}
+
+#[derive(Deserialize)]
+pub struct DashboardResponse {
+    pub username: String,
+}
+
+#[derive(Deserialize)]
+pub struct User {
+    pub id: ObjectId,
+    pub email: String,
+    pub username: String,
+}
+
+#[derive(Deserialize)]
+pub struct FindResponse {
+    pub user: User,
+}
+
+#[derive(Deserialize)]
+pub struct Chat {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub users: Vec<ObjectId>,
+}
+
+#[derive(Deserialize)]
+pub struct ChatsResponse {
+    pub chats: Vec<Chat>,
+}
+
+#[derive(Deserialize)]
+pub struct ChatResponse {
+    pub chatid: ObjectId,
+}
+
+#[derive(Deserialize)]
+pub struct Payload {
+    pub device_id: ObjectId,
+    pub content: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+pub struct Message {
+    #[serde(rename = "_id")]
+    pub id: Option<ObjectId>,
+    pub chat_id: ObjectId,
+    pub sender_id: ObjectId,
+    pub sender_device: Option<ObjectId>,
+    #[serde(default)]
+    pub payloads: Vec<Payload>,
+    pub timestamp: u64,
+    #[serde(default)]
+    pub edit_of: Option<ObjectId>,
+    #[serde(default)]
+    pub deleted: bool,
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+}
+
+impl Message {
+    /// Content encrypted for `device`, missing when sent before the device was linked.
+    pub fn payload_for(&self, device: &ObjectId) -> Option<&[u8]> {
+        self.payloads.iter()
+            .find(|p| p.device_id == *device)
+            .map(|p| p.content.as_slice())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct MessagesResponse {
+    pub messages: Vec<Message>,
+    pub has_more: bool,
+}
+
+#[derive(Deserialize)]
+pub struct SendBundleResponse {
+    pub bundle: String,
+}
+
+#[derive(Deserialize)]
+pub struct PeerBundleResponse {
+    pub bundle: handshake::PeerBundleBinary,
+}
+
+#[derive(Deserialize)]
+pub struct SendMessageResponse {
+    #[serde(rename = "send message")]
+    pub status: String,
+}
+
+#[derive(Deserialize)]
+pub struct InitialMessageResponse {
+    pub message: String,
+}
+
+#[derive(Deserialize)]
+pub struct GetInitialMesssageResponse {
+    pub message: Option<handshake::InitialMessageBinary>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Device {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub name: String,
+    pub approved: bool,
+}
+
+#[derive(Deserialize)]
+pub struct DeviceResponse {
+    pub device: Device,
+}
+
+#[derive(Deserialize)]
+pub struct DevicesResponse {
+    pub devices: Vec<Device>,
+}
+
+#[derive(Deserialize)]
+pub struct DeviceActionResponse {
+    pub device: String,
+}
+
+#[derive(Deserialize)]
+pub struct PresenceResponse {
+    pub message: String,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct FoundUser {
+    pub id: ObjectId,
+    pub username: String,
+}
+
+#[derive(Deserialize)]
+pub struct SearchUsersResponse {
+    pub users: Vec<FoundUser>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Contact {
+    pub id: ObjectId,
+    pub username: String,
+    #[serde(default)]
+    pub nickname: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ContactsResponse {
+    pub contacts: Vec<Contact>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct BlockedUser {
+    pub id: ObjectId,
+    pub username: String,
+}
+
+#[derive(Deserialize)]
+pub struct BlockedResponse {
+    pub blocked: Vec<BlockedUser>,
+}
+
+#[derive(Deserialize)]
+pub struct ReportResponse {
+    pub report: ObjectId,
+}
+
+#[derive(Deserialize)]
+pub struct VerificationResponse {
+    pub sent: bool,
+}
+
+#[derive(Deserialize)]
+pub struct AccountExportResponse {
+    pub account: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+pub struct DeleteMessageResponse {
+    #[serde(rename = "delete message")]
+    pub status: String,
+}
@@ -0,0 +1,323 @@
+use std::{collections::VecDeque, fmt::Display, time::Duration};
+use bson::oid::ObjectId;
+use http::{Uri, StatusCode};
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::{net::TcpStream, sync::{mpsc::{Sender, Receiver, self}, watch}};
+use tokio_tungstenite::{connect_async, tungstenite::{self, protocol::Message}, MaybeTlsStream, WebSocketStream};
+use url::Url;
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Deserialize)]
+pub struct WsPayload {
+    pub device_id: String,
+    pub content: Vec<u8>,
+}
+
+/// Message exchanged with the server, id and sender fields are filled in by the
+/// server. `edit_of` is empty unless the message replaces an earlier one, the
+/// server drops the message at `expires_at` when it is set.
+#[derive(Serialize, Deserialize)]
+pub struct WsMessage {
+    pub id: String,
+    pub chat_id: String,
+    pub sender_id: String,
+    pub sender_device: String,
+    pub payloads: Vec<WsPayload>,
+    pub timestamp: u64,
+    pub edit_of: String,
+    pub expires_at: Option<u64>,
+}
+
+impl WsMessage {
+    /// Content encrypted for `device`, missing when the sender didn't know the device yet.
+    pub fn payload_for(&self, device: &ObjectId) -> Option<&[u8]> {
+        let device = device.to_hex();
+        self.payloads.iter()
+            .find(|p| p.device_id == device)
+            .map(|p| p.content.as_slice())
+    }
+
+    pub fn id(&self) -> Option<ObjectId> {
+        ObjectId::parse_str(&self.id).ok()
+    }
+
+    pub fn edit_of(&self) -> Option<ObjectId> {
+        ObjectId::parse_str(&self.edit_of).ok()
+    }
+}
+
+/// Frames exchanged with the server.
+#[derive(Serialize, Deserialize)]
+pub enum WsFrame {
+    Message(WsMessage),
+    /// Server stored the message sent from this device under `id`.
+    Stored { chat_id: String, id: String, timestamp: u64 },
+    /// A device of another member received the message. Sent back for every
+    /// arriving message, the server forwards it to the devices of the sender.
+    Delivered { chat_id: String, sender_id: String, timestamp: u64 },
+    /// Member is typing, relayed by the server without storing it.
+    Typing { chat_id: String, sender_id: String },
+    /// Contact came online or went offline, last seen is in seconds since the epoch.
+    Presence { user_id: String, online: bool, last_seen: Option<u64> },
+    /// Sender deleted the message for everyone.
+    Deleted { chat_id: String, message_id: String },
+}
+
+impl WsFrame {
+    /// Sender is filled in by the server.
+    pub fn typing(chat_id: &ObjectId) -> WsFrame {
+        WsFrame::Typing { chat_id: chat_id.to_hex(), sender_id: String::new() }
+    }
+
+    /// Acknowledgement of a message that arrived on this device.
+    fn ack(message: &WsMessage) -> WsFrame {
+        WsFrame::Delivered {
+            chat_id: message.chat_id.clone(),
+            sender_id: message.sender_id.clone(),
+            timestamp: message.timestamp,
+        }
+    }
+}
+
+pub struct ThreadComm<T> {
+    pub sender: Sender<T>,
+    pub receiver: Receiver<T>,
+    pub state: watch::Receiver<ConnectionState>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    /// Connection dropped, next attempt is made after `retry_in`.
+    Reconnecting { attempt: u32, retry_in: Duration },
+    /// Server refused the credentials, no further attempts are made.
+    Closed,
+}
+
+impl ConnectionState {
+    pub fn is_connected(&self) -> bool {
+        *self == ConnectionState::Connected
+    }
+}
+
+impl Display for ConnectionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionState::Connecting => write!(f, "Connecting"),
+            ConnectionState::Connected => write!(f, "Online"),
+            ConnectionState::Reconnecting { attempt, retry_in } => {
+                write!(f, "Reconnecting in {}s (attempt {})", retry_in.as_secs().max(1), attempt)
+            },
+            ConnectionState::Closed => write!(f, "Disconnected"),
+        }
+    }
+}
+
+/// Exponential reconnect delay, doubling from `INITIAL_BACKOFF` up to `MAX_BACKOFF`.
+#[derive(Debug, Default)]
+pub struct Backoff {
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let factor = 2u32.saturating_pow(self.attempt);
+        self.attempt = self.attempt.saturating_add(1);
+        INITIAL_BACKOFF.saturating_mul(factor).min(MAX_BACKOFF)
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+/// Why a single connection ended.
+enum Closed {
+    /// The owner dropped its channels, the supervisor stops.
+    Local,
+    Remote,
+}
+
+fn generate_key() -> String {
+    let k: [u8; 16] = rand::random();
+    data_encoding::BASE64.encode(&k)
+}
+
+#[derive(Clone)]
+pub struct Ws {
+    url: Uri,
+    token: String,
+    device: ObjectId,
+}
+
+impl Ws {
+    pub fn new(url: &Url, token: &str, device: &ObjectId) -> Self {
+        Ws {
+            url: url.as_str().parse()
+                .expect("Url is already validated"),
+            token: String::from(token),
+            device: *device,
+        }
+    }
+
+    /// Spawns the connection supervisor. Outgoing frames are queued while
+    /// the server is unreachable and sent once it reconnects.
+    pub async fn run(&self) -> ThreadComm<WsFrame> {
+        let (tx, rx) = mpsc::channel::<WsFrame>(1000);
+        let (tx2, rx2) = mpsc::channel::<WsFrame>(1000);
+        let (state_tx, state_rx) = watch::channel(ConnectionState::Connecting);
+
+        tokio::spawn(self.clone().supervise(tx2, rx, state_tx));
+
+        ThreadComm {
+            sender: tx,
+            receiver: rx2,
+            state: state_rx,
+        }
+    }
+
+    async fn supervise(self, sender: Sender<WsFrame>, mut receiver: Receiver<WsFrame>, state: watch::Sender<ConnectionState>) {
+        let mut queue = VecDeque::new();
+        let mut backoff = Backoff::default();
+        loop {
+            let req = self.make_request()
+                .expect("Url is already validated");
+            match connect_async(req).await {
+                Ok((stream, _)) => {
+                    backoff.reset();
+                    state.send_replace(ConnectionState::Connected);
+                    if let Closed::Local = Self::serve(stream, &sender, &mut receiver, &mut queue).await {
+                        return;
+                    }
+                },
+                Err(tungstenite::Error::Http(res))
+                    if res.status() == StatusCode::UNAUTHORIZED || res.status() == StatusCode::FORBIDDEN => {
+                    state.send_replace(ConnectionState::Closed);
+                    return;
+                },
+                Err(_) => {},
+            }
+
+            let retry_in = backoff.next_delay();
+            state.send_replace(ConnectionState::Reconnecting { attempt: backoff.attempt(), retry_in });
+            let sleep = tokio::time::sleep(retry_in);
+            tokio::pin!(sleep);
+            loop {
+                tokio::select! {
+                    _ = &mut sleep => break,
+                    frame = receiver.recv() => match frame {
+                        Some(frame) => queue.push_back(frame),
+                        None => return,
+                    },
+                }
+            }
+            state.send_replace(ConnectionState::Connecting);
+        }
+    }
+
+    /// Pumps frames both ways until either side closes, a queued frame is
+    /// only dropped once it was written to the socket.
+    async fn serve(stream: WebSocketStream<MaybeTlsStream<TcpStream>>, sender: &Sender<WsFrame>, receiver: &mut Receiver<WsFrame>, queue: &mut VecDeque<WsFrame>) -> Closed {
+        let (mut write, mut read) = stream.split();
+        loop {
+            if let Some(frame) = queue.front() {
+                let data = bincode::serialize(frame)
+                    .expect("Frame is always serializable");
+                if write.send(Message::binary(data)).await.is_err() {
+                    return Closed::Remote;
+                }
+                queue.pop_front();
+                continue;
+            }
+            tokio::select! {
+                frame = read.next() => match frame {
+                    Some(Ok(Message::Binary(data))) => {
+                        let frame = match bincode::deserialize(&data) {
+                            Ok(frame) => frame,
+                            Err(_) => continue,
+                        };
+                        if let WsFrame::Message(message) = &frame {
+                            queue.push_back(WsFrame::ack(message));
+                        }
+                        if sender.send(frame).await.is_err() {
+                            return Closed::Local;
+                        }
+                    },
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return Closed::Remote,
+                    Some(Ok(_)) => {},
+                },
+                frame = receiver.recv() => match frame {
+                    Some(frame) => queue.push_back(frame),
+                    None => {
+                        let _ = write.close().await;
+                        return Closed::Local;
+                    },
+                },
+            }
+        }
+    }
+
+    fn make_request(&self) -> Option<http::Request<()>> {
+        let authority = self.url.authority().unwrap().as_str();
+        let host = authority
+            .find('@')
+            .map(|idx| authority.split_at(idx + 1).1)
+            .unwrap_or_else(|| authority);
+
+        if host.is_empty() {
+            return None;
+        }
+
+        let req = http::Request::builder()
+            .method("GET")
+            .header("Host", host)
+            .header("Connection", "Upgrade")
+            .header("Upgrade", "websocket")
+            .header("Sec-WebSocket-Version", "13")
+            .header("Sec-WebSocket-Key", generate_key())
+            .header("Authorization", format!("Bearer {}", self.token))
+            .header(super::DEVICE_HEADER, self.device.to_hex())
+            .uri(&self.url)
+            .body(())
+            .unwrap();
+        Some(req)
+    }
+}
+
+#[cfg(test)]
+mod ws_test {
+    use std::time::Duration;
+    use super::{Backoff, MAX_BACKOFF};
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let mut backoff = Backoff::default();
+
+        assert_eq!(backoff.next_delay(), Duration::from_millis(500));
+        assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+        assert_eq!(backoff.next_delay(), Duration::from_secs(2));
+        for _ in 0..40 {
+            backoff.next_delay();
+        }
+        assert_eq!(backoff.next_delay(), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn backoff_reset() {
+        let mut backoff = Backoff::default();
+        backoff.next_delay();
+        backoff.next_delay();
+        backoff.reset();
+
+        assert_eq!(backoff.attempt(), 0);
+        assert_eq!(backoff.next_delay(), Duration::from_millis(500));
+    }
+}
@@ -0,0 +1,127 @@
+use std::{path::Path, fs};
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+use crate::{keyring::{self, KeyringEntries}, cipher::{SealingKey, KdfParams, CipherError}};
+
+const MAGIC: &[u8] = b"PLASMAXB";
+const VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+
+#[derive(Error, Debug)]
+pub enum BackupError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Encoding(#[from] bincode::Error),
+    #[error(transparent)]
+    Cipher(#[from] CipherError),
+    #[error("File is not a plasmax backup")]
+    NotAnArchive,
+    #[error("Wrong backup passphrase")]
+    WrongPassphrase,
+    #[error("Backup belongs to {0}")]
+    AccountMismatch(String),
+}
+
+/// Unencrypted part of the archive, everything needed to derive the key.
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    salt: [u8; SALT_LEN],
+    params: KdfParams,
+    sealed: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Payload {
+    mail: String,
+    entries: KeyringEntries,
+}
+
+/// Writes keyring entries of `mail` into a passphrase protected archive.
+pub fn write(path: &Path, mail: &str, entries: KeyringEntries, passphrase: &str) -> Result<(), BackupError> {
+    let archive = seal(mail, entries, passphrase, KdfParams::default())?;
+    keyring::write_private(path, &archive)?;
+    Ok(())
+}
+
+/// Reads keyring entries of `mail` back from an archive.
+pub fn read(path: &Path, mail: &str, passphrase: &str) -> Result<KeyringEntries, BackupError> {
+    let archive = fs::read(path)?;
+    open(&archive, mail, passphrase)
+}
+
+fn seal(mail: &str, entries: KeyringEntries, passphrase: &str, params: KdfParams) -> Result<Vec<u8>, BackupError> {
+    let salt: [u8; SALT_LEN] = rand::random();
+    let key = SealingKey::derive(passphrase, &salt, &params)?;
+    let payload = Payload { mail: mail.to_owned(), entries };
+    let envelope = Envelope {
+        salt,
+        params,
+        sealed: key.seal(&bincode::serialize(&payload)?)?,
+    };
+    let mut archive = MAGIC.to_vec();
+    archive.push(VERSION);
+    archive.append(&mut bincode::serialize(&envelope)?);
+    Ok(archive)
+}
+
+fn open(archive: &[u8], mail: &str, passphrase: &str) -> Result<KeyringEntries, BackupError> {
+    let envelope = match archive.strip_prefix(MAGIC) {
+        Some([VERSION, envelope @ ..]) => envelope,
+        _ => return Err(BackupError::NotAnArchive),
+    };
+    let envelope: Envelope = bincode::deserialize(envelope)?;
+    let key = SealingKey::derive(passphrase, &envelope.salt, &envelope.params)?;
+    let payload = key.open(&envelope.sealed)
+        .map_err(|_| BackupError::WrongPassphrase)?;
+    let payload: Payload = bincode::deserialize(&payload)?;
+    if payload.mail != mail {
+        return Err(BackupError::AccountMismatch(payload.mail));
+    }
+    Ok(payload.entries)
+}
+
+#[cfg(test)]
+mod backup_test {
+    use crate::{cipher::KdfParams, keyring::KeyringEntries};
+    use super::{seal, open, BackupError};
+
+    const CHEAP: KdfParams = KdfParams { memory_kib: 64, iterations: 1, parallelism: 1 };
+
+    fn entries() -> KeyringEntries {
+        KeyringEntries {
+            keys: vec![(String::from("identity"), vec![1u8; 32])],
+            secrets: vec![(String::from("bob"), vec![2u8; 32])],
+            contacts: vec![(String::from("bob"), vec![3u8; 33])],
+        }
+    }
+
+    #[test]
+    fn seal_open_correct() {
+        let archive = seal("mail", entries(), "passphrase", CHEAP).unwrap();
+
+        assert_eq!(open(&archive, "mail", "passphrase").unwrap(), entries());
+    }
+
+    #[test]
+    fn open_wrong_passphrase() {
+        let archive = seal("mail", entries(), "passphrase", CHEAP).unwrap();
+
+        let result = open(&archive, "mail", "other");
+        assert!(matches!(result, Err(BackupError::WrongPassphrase)));
+    }
+
+    #[test]
+    fn open_other_account() {
+        let archive = seal("mail", entries(), "passphrase", CHEAP).unwrap();
+
+        let result = open(&archive, "other", "passphrase");
+        assert!(matches!(result, Err(BackupError::AccountMismatch(_))));
+    }
+
+    #[test]
+    fn open_not_archive() {
+        let result = open(b"plain text", "mail", "passphrase");
+        assert!(matches!(result, Err(BackupError::NotAnArchive)));
+    }
+}
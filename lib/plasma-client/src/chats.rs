@@ -2,13 +2,13 @@ use std::iter::zip;
 use bson::oid::ObjectId;
 use crate::api::response;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct UserHandle {
     pub id: ObjectId,
     pub username: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Chat {
     pub id: ObjectId,
     pub user: UserHandle,
@@ -0,0 +1,219 @@
+use std::string::FromUtf8Error;
+use argon2::{Argon2, Algorithm, Version, Params};
+use serde::{Serialize, Deserialize};
+use x3dh::keys::X3dhSharedSecret;
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, generic_array::GenericArray},
+    ChaCha20Poly1305
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CipherError {
+    #[error("Encryption error: {0}")]
+    EncryptionError(chacha20poly1305::aead::Error),
+    #[error("Decryption error: {0}")]
+    DecryptionError(chacha20poly1305::aead::Error),
+    #[error(transparent)]
+    ConversionError(#[from] FromUtf8Error),
+    #[error("Sealed data too short")]
+    Truncated,
+    #[error("No session with sender device")]
+    NoSession,
+    #[error("Key derivation error: {0}")]
+    KeyDerivationError(argon2::Error),
+}
+
+pub struct Cipher {
+    secret: X3dhSharedSecret,
+}
+
+impl Cipher {
+    pub fn new(secret: X3dhSharedSecret) -> Self {
+        Cipher {
+            secret,
+        }
+    }
+
+    pub fn encrypt(&self, message: &[u8], timestamp: u64) -> Result<Vec<u8>, CipherError> {
+        let secret = self.secret.to_bytes();
+        let secret = GenericArray::from_slice(&secret);
+        let mut timestamp = timestamp.to_le_bytes().to_vec();
+        timestamp.resize(12, 0);
+
+        let cipher = ChaCha20Poly1305::new(&secret);
+        let nonce = GenericArray::from_slice(&timestamp[0..12]);
+
+        cipher
+            .encrypt(&nonce, message)
+            .map_err(|e| CipherError::EncryptionError(e))
+    }
+
+    pub fn decrypt(&self, message_bytes: &[u8], timestamp: u64) -> Result<Vec<u8>, CipherError> {
+        let secret = self.secret.to_bytes();
+        let secret = GenericArray::from_slice(&secret);
+        let mut timestamp = timestamp.to_le_bytes().to_vec();
+        timestamp.resize(12, 0);
+
+        let cipher = ChaCha20Poly1305::new(&secret);
+        let nonce = GenericArray::from_slice(&timestamp[0..12]);
+
+        cipher.decrypt(&nonce, message_bytes)
+            .map_err(|e| CipherError::DecryptionError(e))
+    }
+}
+
+const SEAL_NONCE_LEN: usize = 12;
+
+/// Argon2id cost, stored next to the salt so it can be raised without breaking old data.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+/// Symmetric key for data kept at rest, each sealed blob carries its own random nonce.
+#[derive(Clone)]
+pub struct SealingKey([u8; 32]);
+
+impl SealingKey {
+    pub fn generate() -> Self {
+        SealingKey(rand::random())
+    }
+
+    /// Derives key from a passphrase with Argon2id.
+    pub fn derive(passphrase: &str, salt: &[u8], params: &KdfParams) -> Result<Self, CipherError> {
+        let params = Params::new(params.memory_kib, params.iterations, params.parallelism, Some(32))
+            .map_err(CipherError::KeyDerivationError)?;
+        let mut key = [0u8; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(CipherError::KeyDerivationError)?;
+        Ok(SealingKey(key))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&bytes[0..32]);
+        SealingKey(key)
+    }
+
+    pub fn to_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>, CipherError> {
+        let cipher = ChaCha20Poly1305::new(GenericArray::from_slice(&self.0));
+        let nonce: [u8; SEAL_NONCE_LEN] = rand::random();
+        let mut sealed = nonce.to_vec();
+        let mut encrypted = cipher
+            .encrypt(GenericArray::from_slice(&nonce), plaintext)
+            .map_err(CipherError::EncryptionError)?;
+        sealed.append(&mut encrypted);
+        Ok(sealed)
+    }
+
+    pub fn open(&self, sealed: &[u8]) -> Result<Vec<u8>, CipherError> {
+        if sealed.len() < SEAL_NONCE_LEN {
+            return Err(CipherError::Truncated);
+        }
+        let (nonce, encrypted) = sealed.split_at(SEAL_NONCE_LEN);
+        let cipher = ChaCha20Poly1305::new(GenericArray::from_slice(&self.0));
+        cipher
+            .decrypt(GenericArray::from_slice(nonce), encrypted)
+            .map_err(CipherError::DecryptionError)
+    }
+}
+
+#[cfg(test)]
+mod cipher_tests {
+    use rand::Rng;
+    use x3dh::keys::X3dhSharedSecret;
+    use super::{Cipher, SealingKey};
+
+    fn cipher_random_key() -> Cipher {
+        let bytes = rand::thread_rng().gen::<[u8; 32]>();
+        Cipher::new(X3dhSharedSecret::from_bytes(&bytes))
+    }
+
+    #[test]
+    fn enc_dec_correct() {
+        let cipher = cipher_random_key();
+        let message = b"message";
+        let timestamp = 0u64;
+
+        let encrypted = cipher.encrypt(message, timestamp)
+            .unwrap();
+        let decrypted = cipher.decrypt(&encrypted, timestamp)
+            .unwrap();
+
+        assert_eq!(message.as_slice(), decrypted);
+    }
+
+    #[test]
+    fn enc_dec_different_timestamp() {
+        let cipher = cipher_random_key();
+        let message = b"message";
+        let timestamp1 = 0u64;
+        let timestamp2 = 1u64;
+
+        let encrypted = cipher.encrypt(message, timestamp1)
+            .unwrap();
+        let decrypted_result = cipher.decrypt(&encrypted, timestamp2);
+
+        assert!(decrypted_result.is_err());
+    }
+
+    #[test]
+    fn enc_dec_different_secret_key() {
+        let cipher1 = cipher_random_key();
+        let cipher2 = cipher_random_key();
+        let message = b"message";
+        let timestamp = 0u64;
+
+        let encrypted = cipher1.encrypt(message, timestamp)
+            .unwrap();
+        let decrypted_result = cipher2.decrypt(&encrypted, timestamp);
+
+        assert!(decrypted_result.is_err());
+    }
+
+    #[test]
+    fn seal_open_correct() {
+        let key = SealingKey::generate();
+        let data = b"cached data";
+
+        let sealed = key.seal(data).unwrap();
+        let opened = key.open(&sealed).unwrap();
+
+        assert_eq!(data.to_vec(), opened);
+    }
+
+    #[test]
+    fn seal_open_different_key() {
+        let key1 = SealingKey::generate();
+        let key2 = SealingKey::generate();
+
+        let sealed = key1.seal(b"cached data").unwrap();
+
+        assert!(key2.open(&sealed).is_err());
+    }
+
+    #[test]
+    fn seal_open_truncated() {
+        let key = SealingKey::generate();
+
+        assert!(key.open(&[0u8; 4]).is_err());
+    }
+}
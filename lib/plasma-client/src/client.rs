@@ -0,0 +1,396 @@
+use std::{collections::{HashMap, VecDeque}, sync::Arc};
+use bson::oid::ObjectId;
+use crate::{account::{Account, Authorized}, api::{Api, body::{MessagesBody, Cursor, ReportBody}, response::{BlockedUser, Contact, FoundUser}, ws::{ConnectionState, ThreadComm, Ws, WsFrame, WsMessage}}, chats::Chat, envelope::Envelope, error::PlasmaError, keyring::KeyStore, server::Server, session::{self, ChatSession}};
+
+/// High level client for bots and integrations, hides sessions and ciphers
+/// behind chats that send and receive plain text.
+pub struct Client {
+    server: Server,
+    api: Api,
+    account: Account<Authorized>,
+}
+
+/// Chat together with the sessions of every device taking part in it. The
+/// disappearing messages timer is known once its history was read.
+pub struct OpenChat {
+    pub chat: Chat,
+    session: ChatSession,
+    timer: Option<u64>,
+}
+
+impl OpenChat {
+    /// Seconds after which messages sent to the chat disappear.
+    pub fn timer(&self) -> Option<u64> {
+        self.timer
+    }
+}
+
+/// Decrypted message, `content` is `None` when it wasn't encrypted for this device.
+/// With `edit_of` set the content replaces that of an earlier message, with
+/// `reply_to` it answers one.
+#[derive(Debug, Clone)]
+pub struct IncomingMessage {
+    pub id: Option<ObjectId>,
+    pub edit_of: Option<ObjectId>,
+    pub reply_to: Option<ObjectId>,
+    pub expires_at: Option<u64>,
+    pub chat_id: ObjectId,
+    pub sender: String,
+    pub timestamp: u64,
+    pub content: Option<String>,
+}
+
+/// Messages arriving over the websocket, decrypted as they come. After a
+/// reconnect the history of chats seen so far is fetched to fill the gap.
+pub struct Incoming<'a> {
+    client: &'a Client,
+    comms: ThreadComm<WsFrame>,
+    chats: HashMap<String, OpenChat>,
+    only: Option<String>,
+    newest: HashMap<String, (u64, Option<ObjectId>)>,
+    missed: VecDeque<IncomingMessage>,
+    connection: ConnectionState,
+}
+
+impl Client {
+    /// Logs in with the token kept in `keys`, falling back to `password` when there is none or it expired.
+    pub async fn login(server: Server, mail: &str, password: Option<String>, keys: Arc<dyn KeyStore>) -> Result<Client, PlasmaError> {
+        let api = Api::new(server.api_url());
+        let account = match Account::new(mail.to_owned(), keys.clone()).try_login_token(&api).await {
+            Ok(account) => account,
+            Err(err @ PlasmaError::DevicePending(_)) => return Err(err),
+            Err(err) => match password {
+                Some(password) => Account::new(mail.to_owned(), keys).login(password, &api).await?,
+                None => return Err(err),
+            },
+        };
+        Ok(Client::new(server, api, account))
+    }
+
+    pub fn new(server: Server, api: Api, account: Account<Authorized>) -> Client {
+        Client { server, api, account }
+    }
+
+    pub fn server(&self) -> &Server {
+        &self.server
+    }
+
+    pub fn api(&self) -> &Api {
+        &self.api
+    }
+
+    pub fn account(&self) -> &Account<Authorized> {
+        &self.account
+    }
+
+    /// Lower level parts, for consumers driving sessions themselves.
+    pub fn into_parts(self) -> (Server, Api, Account<Authorized>) {
+        (self.server, self.api, self.account)
+    }
+
+    /// Hides online state and last seen from contacts, or shows them again.
+    pub async fn set_presence_hidden(&self, hidden: bool) -> Result<(), PlasmaError> {
+        Ok(self.api.set_presence_hidden(self.account.token(), hidden).await?)
+    }
+
+    /// Leaves the account out of user search, or lists it again.
+    pub async fn set_search_hidden(&self, hidden: bool) -> Result<(), PlasmaError> {
+        Ok(self.api.set_search_hidden(self.account.token(), hidden).await?)
+    }
+
+    pub async fn search_users(&self, query: &str) -> Result<Vec<FoundUser>, PlasmaError> {
+        Ok(self.api.search_users(self.account.token(), query).await?)
+    }
+
+    pub async fn contacts(&self) -> Result<Vec<Contact>, PlasmaError> {
+        Ok(self.api.contacts(self.account.token()).await?)
+    }
+
+    pub async fn add_contact(&self, username: &str, nickname: Option<&str>) -> Result<Contact, PlasmaError> {
+        Ok(self.api.add_contact(self.account.token(), username, nickname).await?)
+    }
+
+    pub async fn remove_contact(&self, username: &str) -> Result<(), PlasmaError> {
+        Ok(self.api.remove_contact(self.account.token(), username).await?)
+    }
+
+    pub async fn blocked(&self) -> Result<Vec<BlockedUser>, PlasmaError> {
+        Ok(self.api.blocked(self.account.token()).await?)
+    }
+
+    pub async fn block(&self, username: &str) -> Result<(), PlasmaError> {
+        Ok(self.api.block(self.account.token(), username).await?)
+    }
+
+    pub async fn unblock(&self, username: &str) -> Result<(), PlasmaError> {
+        Ok(self.api.unblock(self.account.token(), username).await?)
+    }
+
+    /// Reports `username` to moderators with `excerpt` of the conversation,
+    /// blocking the user too when `block` is set.
+    pub async fn report(&self, username: &str, reason: &str, excerpt: &str, block: bool) -> Result<ObjectId, PlasmaError> {
+        let params = ReportBody {
+            username: username.to_owned(),
+            reason: reason.to_owned(),
+            excerpt: excerpt.to_owned(),
+            chat_id: None,
+            message_id: None,
+            block,
+        };
+        Ok(self.api.report(self.account.token(), &params).await?)
+    }
+
+    /// Mails a new verification token, false when the account is verified already.
+    pub async fn resend_verification(&self) -> Result<bool, PlasmaError> {
+        Ok(self.api.resend_verification(self.account.token()).await?)
+    }
+
+    /// Changes the password, other sessions of the account are signed out.
+    pub async fn change_password(&mut self, password: &str, new_password: &str) -> Result<(), PlasmaError> {
+        self.account.change_password(&self.api, password.to_owned(), new_password.to_owned()).await
+    }
+
+    /// Everything the server keeps about the account, as JSON.
+    pub async fn export_account(&self, password: &str) -> Result<serde_json::Value, PlasmaError> {
+        Ok(self.api.export_account(self.account.token(), password.to_owned()).await?)
+    }
+
+    /// Deletes the account on the server, local keys are left to the caller.
+    pub async fn delete_account(self, password: &str) -> Result<(), PlasmaError> {
+        Ok(self.api.delete_account(self.account.token(), password.to_owned()).await?)
+    }
+
+    pub async fn chats(&self) -> Result<Vec<Chat>, PlasmaError> {
+        Ok(self.account.chats(&self.api).await?.chats)
+    }
+
+    /// Opens chat with `username`, starting it when there is none yet.
+    pub async fn open_chat(&self, username: &str) -> Result<OpenChat, PlasmaError> {
+        let chat_id = self.account.chat(&self.api, username).await?;
+        let chat = self.find_chat(|chat| chat.id == chat_id).await?
+            .ok_or_else(|| PlasmaError::NoChat(username.to_owned()))?;
+        self.open(chat).await
+    }
+
+    /// Opens existing chat with `username` without starting a new one.
+    pub async fn existing_chat(&self, username: &str) -> Result<OpenChat, PlasmaError> {
+        let chat = self.find_chat(|chat| chat.user.username == username).await?
+            .ok_or_else(|| PlasmaError::NoChat(username.to_owned()))?;
+        self.open(chat).await
+    }
+
+    async fn find_chat(&self, predicate: impl Fn(&Chat) -> bool) -> Result<Option<Chat>, PlasmaError> {
+        let chat = self.chats().await?
+            .into_iter()
+            .find(predicate);
+        Ok(chat)
+    }
+
+    async fn open(&self, chat: Chat) -> Result<OpenChat, PlasmaError> {
+        let session = self.account.open_session(&self.api, &chat.user.username).await?;
+        Ok(OpenChat { chat, session, timer: None })
+    }
+
+    /// Sends message to every device in the chat, returns its timestamp.
+    pub async fn send(&self, chat: &OpenChat, message: &str) -> Result<u64, PlasmaError> {
+        let envelope = Envelope::Text(message.to_owned());
+        self.account.send(&self.api, &chat.chat.id, &chat.session, &envelope, None, chat.timer).await
+    }
+
+    /// Sends answer to message `parent`, returns its timestamp.
+    pub async fn reply(&self, chat: &OpenChat, parent: &ObjectId, message: &str) -> Result<u64, PlasmaError> {
+        let envelope = Envelope::reply(message.to_owned(), parent);
+        self.account.send(&self.api, &chat.chat.id, &chat.session, &envelope, None, chat.timer).await
+    }
+
+    /// Adds reaction `emoji` to message `target`, or removes it again.
+    pub async fn react(&self, chat: &OpenChat, target: &ObjectId, emoji: &str, add: bool) -> Result<(), PlasmaError> {
+        let envelope = Envelope::react(target, emoji, add);
+        self.account.send(&self.api, &chat.chat.id, &chat.session, &envelope, None, chat.timer).await?;
+        Ok(())
+    }
+
+    /// Sets the disappearing messages timer for both members, `None` turns it off.
+    pub async fn set_timer(&self, chat: &mut OpenChat, seconds: Option<u64>) -> Result<(), PlasmaError> {
+        let envelope = Envelope::Timer { seconds };
+        self.account.send(&self.api, &chat.chat.id, &chat.session, &envelope, None, None).await?;
+        chat.timer = seconds;
+        Ok(())
+    }
+
+    /// Replaces content of own message `message_id` for everyone, returns timestamp of the edit.
+    pub async fn edit(&self, chat: &OpenChat, message_id: &ObjectId, message: &str) -> Result<u64, PlasmaError> {
+        let envelope = Envelope::Text(message.to_owned());
+        self.account.send(&self.api, &chat.chat.id, &chat.session, &envelope, Some(*message_id), None).await
+    }
+
+    /// Deletes own message and its edits for everyone.
+    pub async fn delete(&self, chat: &OpenChat, message_id: &ObjectId) -> Result<(), PlasmaError> {
+        self.account.delete_message(&self.api, &chat.chat.id, message_id).await
+    }
+
+    /// Tells the peer every message sent up to `up_to` was read.
+    pub async fn mark_read(&self, chat: &OpenChat, up_to: u64) -> Result<(), PlasmaError> {
+        let envelope = Envelope::Read { up_to };
+        self.account.send(&self.api, &chat.chat.id, &chat.session, &envelope, None, None).await?;
+        Ok(())
+    }
+
+    /// Every message newer than `since`, or the latest page when not given.
+    /// Receipts, reactions and timer changes are not messages and are left out, as are
+    /// deleted and expired ones.
+    pub async fn history(&self, chat: &mut OpenChat, since: Option<u64>) -> Result<Vec<IncomingMessage>, PlasmaError> {
+        self.history_after(chat, since.map(Cursor::Timestamp)).await
+    }
+
+    async fn history_after(&self, chat: &mut OpenChat, after: Option<Cursor>) -> Result<Vec<IncomingMessage>, PlasmaError> {
+        let mut messages = Vec::new();
+        let mut newest = after;
+        loop {
+            let params = match newest {
+                Some(cursor) => MessagesBody::after(chat.chat.id, cursor),
+                None => MessagesBody::latest(chat.chat.id),
+            };
+            let page = self.account.messages(&self.api, &params).await?;
+            let now = session::timestamp();
+            for message in page.messages.iter() {
+                newest = Some(Cursor::at(message.timestamp, message.id));
+                if message.deleted || message.expires_at.is_some_and(|at| at <= now) {
+                    continue;
+                }
+                let content = message.payload_for(self.account.device());
+                let decrypted = self.decrypt(chat, &message.sender_id, message.sender_device, content, message.timestamp).await?
+                    .map(|decrypted| IncomingMessage { id: message.id, edit_of: message.edit_of, expires_at: message.expires_at, ..decrypted });
+                messages.extend(decrypted);
+            }
+            if params.after.is_none() || !page.has_more || page.messages.is_empty() {
+                return Ok(messages);
+            }
+        }
+    }
+
+    /// Connects to the server and receives messages of every chat.
+    pub async fn incoming(&self) -> Incoming<'_> {
+        self.listen(HashMap::new(), None).await
+    }
+
+    /// Connects to the server and receives messages of a single chat.
+    pub async fn follow(&self, chat: OpenChat) -> Incoming<'_> {
+        let chat_id = chat.chat.id.to_hex();
+        let chats = HashMap::from([(chat_id.clone(), chat)]);
+        self.listen(chats, Some(chat_id)).await
+    }
+
+    async fn listen(&self, chats: HashMap<String, OpenChat>, only: Option<String>) -> Incoming<'_> {
+        let comms = Ws::new(&self.server.ws_url(), self.account.token(), self.account.device())
+            .run()
+            .await;
+        let connection = *comms.state.borrow();
+        Incoming { client: self, comms, chats, only, newest: HashMap::new(), missed: VecDeque::new(), connection }
+    }
+
+    /// Decrypted text message, `None` for control envelopes like receipts.
+    /// Timer changes are applied to the chat.
+    async fn decrypt(&self, chat: &mut OpenChat, sender_id: &ObjectId, sender_device: Option<ObjectId>, content: Option<&[u8]>, timestamp: u64) -> Result<Option<IncomingMessage>, PlasmaError> {
+        let sender = match sender_id == self.account.id() {
+            true => self.account.username().clone(),
+            false => chat.chat.user.username.clone(),
+        };
+        let (content, reply_to) = match self.account.decrypt(&self.api, &mut chat.session, &sender, sender_device, content, timestamp).await? {
+            Some(Envelope::Text(text)) => (Some(text), None),
+            Some(Envelope::Reply { text, parent }) => (Some(text), Some(ObjectId::from_bytes(parent))),
+            Some(Envelope::Timer { seconds }) => {
+                chat.timer = seconds;
+                return Ok(None);
+            },
+            Some(Envelope::Read { .. } | Envelope::React { .. }) => return Ok(None),
+            None => (None, None),
+        };
+        Ok(Some(IncomingMessage { id: None, edit_of: None, reply_to, expires_at: None, chat_id: chat.chat.id, sender, timestamp, content }))
+    }
+}
+
+impl Incoming<'_> {
+    pub fn connection(&self) -> ConnectionState {
+        self.connection
+    }
+
+    /// Next decrypted message, `None` once the connection is closed for good.
+    pub async fn next(&mut self) -> Option<Result<IncomingMessage, PlasmaError>> {
+        loop {
+            if let Some(message) = self.missed.pop_front() {
+                return Some(Ok(self.seen(message)));
+            }
+            tokio::select! {
+                biased;
+                frame = self.comms.receiver.recv() => {
+                    let message = match frame? {
+                        WsFrame::Message(message) => message,
+                        _ => continue,
+                    };
+                    if self.only.as_ref().is_some_and(|chat_id| *chat_id != message.chat_id) {
+                        continue;
+                    }
+                    if self.newest.get(&message.chat_id).is_some_and(|newest| (message.timestamp, message.id()) <= *newest) {
+                        continue;
+                    }
+                    match self.decrypt(message).await {
+                        Ok(Some(message)) => return Some(Ok(self.seen(message))),
+                        Ok(None) => continue,
+                        Err(err) => return Some(Err(err)),
+                    }
+                },
+                changed = self.comms.state.changed() => {
+                    if changed.is_err() {
+                        continue;
+                    }
+                    if let Err(err) = self.on_connection_change().await {
+                        return Some(Err(err));
+                    }
+                },
+            }
+        }
+    }
+
+    fn seen(&mut self, message: IncomingMessage) -> IncomingMessage {
+        let newest = self.newest.entry(message.chat_id.to_hex()).or_default();
+        *newest = (message.timestamp, message.id).max(*newest);
+        message
+    }
+
+    async fn on_connection_change(&mut self) -> Result<(), PlasmaError> {
+        let state = *self.comms.state.borrow_and_update();
+        let was = std::mem::replace(&mut self.connection, state);
+        if !state.is_connected() || !matches!(was, ConnectionState::Reconnecting { .. } | ConnectionState::Connecting) {
+            return Ok(());
+        }
+        for (chat_id, chat) in self.chats.iter_mut() {
+            let since = match self.newest.get(chat_id) {
+                Some((timestamp, id)) => Cursor::at(*timestamp, *id),
+                None => continue,
+            };
+            let missed = self.client.history_after(chat, Some(since)).await?;
+            self.missed.extend(missed);
+        }
+        Ok(())
+    }
+
+    async fn decrypt(&mut self, message: WsMessage) -> Result<Option<IncomingMessage>, PlasmaError> {
+        if !self.chats.contains_key(&message.chat_id) {
+            let chat = self.client.find_chat(|chat| chat.id.to_hex() == message.chat_id).await?
+                .ok_or_else(|| PlasmaError::NoChat(message.chat_id.clone()))?;
+            let chat = self.client.open(chat).await?;
+            self.chats.insert(message.chat_id.clone(), chat);
+        }
+        let chat = self.chats
+            .get_mut(&message.chat_id)
+            .expect("Chat was opened above");
+        let sender_id = ObjectId::parse_str(&message.sender_id)
+            .unwrap_or(chat.chat.user.id);
+        let sender_device = ObjectId::parse_str(&message.sender_device).ok();
+        let content = message.payload_for(self.client.account.device());
+        let decrypted = self.client.decrypt(chat, &sender_id, sender_device, content, message.timestamp).await?
+            .map(|decrypted| IncomingMessage { id: message.id(), edit_of: message.edit_of(), expires_at: message.expires_at, ..decrypted });
+        Ok(decrypted)
+    }
+}
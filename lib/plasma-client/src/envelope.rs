@@ -0,0 +1,100 @@
+use bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use crate::cipher::CipherError;
+
+/// Plaintext of every encrypted payload. Control messages travel exactly like
+/// text so the server can't tell them apart.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Envelope {
+    Text(String),
+    /// Every message of the peer sent up to `up_to` was read.
+    Read { up_to: u64 },
+    /// Disappearing messages timer in seconds for both members of the chat, `None` turns it off.
+    Timer { seconds: Option<u64> },
+    /// Text answering an earlier message, the server only sees it as text.
+    Reply { text: String, parent: [u8; 12] },
+    /// Adds or removes a reaction of the sender to an earlier message.
+    React { target: [u8; 12], emoji: String, add: bool },
+}
+
+impl Envelope {
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self)
+            .expect("Envelope is always serializable")
+    }
+
+    /// Payloads sent before envelopes existed are plain utf-8 text, which
+    /// never starts with the nul bytes of an encoded variant.
+    pub fn decode(bytes: &[u8]) -> Result<Envelope, CipherError> {
+        match bincode::deserialize(bytes) {
+            Ok(envelope) => Ok(envelope),
+            Err(_) => Ok(Envelope::Text(String::from_utf8(bytes.to_vec())?)),
+        }
+    }
+
+    pub fn reply(text: String, parent: &ObjectId) -> Envelope {
+        Envelope::Reply { text, parent: parent.bytes() }
+    }
+
+    pub fn react(target: &ObjectId, emoji: &str, add: bool) -> Envelope {
+        Envelope::React { target: target.bytes(), emoji: emoji.to_owned(), add }
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        match self {
+            Envelope::Text(text) | Envelope::Reply { text, .. } => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Message this one answers.
+    pub fn parent(&self) -> Option<ObjectId> {
+        match self {
+            Envelope::Reply { parent, .. } => Some(ObjectId::from_bytes(*parent)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod envelope_test {
+    use bson::oid::ObjectId;
+    use super::Envelope;
+
+    #[test]
+    fn encode_decode() {
+        let text = Envelope::Text(String::from("message"));
+        let read = Envelope::Read { up_to: 7 };
+        let timer = Envelope::Timer { seconds: Some(60) };
+
+        assert_eq!(Envelope::decode(&text.encode()).unwrap(), text);
+        assert_eq!(Envelope::decode(&read.encode()).unwrap(), read);
+        assert_eq!(Envelope::decode(&timer.encode()).unwrap(), timer);
+    }
+
+    #[test]
+    fn reply_keeps_parent() {
+        let parent = ObjectId::new();
+        let reply = Envelope::decode(&Envelope::reply(String::from("answer"), &parent).encode()).unwrap();
+
+        assert_eq!(reply.text(), Some("answer"));
+        assert_eq!(reply.parent(), Some(parent));
+        assert_eq!(Envelope::Text(String::new()).parent(), None);
+    }
+
+    #[test]
+    fn reaction_is_not_text() {
+        let react = Envelope::react(&ObjectId::new(), "👍", true);
+
+        assert_eq!(Envelope::decode(&react.encode()).unwrap(), react);
+        assert_eq!(react.text(), None);
+    }
+
+    #[test]
+    fn decode_legacy_text() {
+        let envelope = Envelope::decode(b"plain message").unwrap();
+
+        assert_eq!(envelope.text(), Some("plain message"));
+        assert!(Envelope::decode(&[0xff, 0xfe, 0xfd, 0xfc, 0xfb]).is_err());
+    }
+}
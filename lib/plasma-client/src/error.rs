@@ -0,0 +1,38 @@
+use bson::oid::ObjectId;
+use thiserror::Error;
+use x3dh::error::X3dhError;
+use crate::{api::ApiError, cipher::CipherError, store::StoreError, backup::BackupError};
+
+#[derive(Error, Debug)]
+pub enum PlasmaError {
+    #[error(transparent)]
+    IoError( #[from] std::io::Error ),
+    #[error(transparent)]
+    ServerError( #[from] ApiError),
+    #[error(transparent)]
+    MessageCipherError( #[from] CipherError),
+    #[error(transparent)]
+    X3dhLibError( #[from] X3dhError),
+    #[error(transparent)]
+    LocalStoreError( #[from] StoreError),
+    #[error(transparent)]
+    KeyBackupError( #[from] BackupError),
+    #[error("Identity key of {0} changed since the chat started")]
+    IdentityChanged(String),
+    #[error("This device ({0}) is waiting for approval from another device of the account")]
+    DevicePending(ObjectId),
+    #[error("No chat with {0}")]
+    NoChat(String),
+    #[error("Server closed the connection, log in again")]
+    Disconnected,
+}
+
+impl PlasmaError {
+    /// True when the server could not be reached at all.
+    pub fn is_offline(&self) -> bool {
+        match self {
+            PlasmaError::ServerError(ApiError::ReqwestError(err)) => err.is_connect() || err.is_timeout(),
+            _ => false,
+        }
+    }
+}
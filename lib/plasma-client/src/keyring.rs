@@ -0,0 +1,609 @@
+use bson::oid::ObjectId;
+use std::{collections::HashMap, path::{Path, PathBuf}, io::{Error, ErrorKind, Write}, fs::{create_dir_all, self, File}, fmt::Display, sync::Mutex};
+
+use home::home_dir;
+use serde::{Serialize, Deserialize};
+use x3dh::keys::{X3dhSharedSecret, IdentityKeyPair, IdentityKeyPublic, Key, KeyPair, SignedPreKeyPair, OneTimeKeyPair};
+use crate::{cipher::{SealingKey, KdfParams}, server::Server};
+
+const BASE_PATH: &'static str = ".plasmax";
+const ROOT_DIR: &str = "";
+const TOKEN_FILENAME: &'static str = "token";
+const KEYS_DIR: &'static str = "keys";
+const SECRET_DIR: &'static str = "chat_secret";
+const CONTACTS_DIR: &str = "contacts";
+const CACHE_KEY_FILENAME: &str = "cache";
+const DEVICE_FILENAME: &str = "device";
+const CACHE_DIR: &str = "cache";
+const HEADER_FILENAME: &str = "keyring";
+const HEADER_CHECK: &[u8] = b"plasmax keyring";
+const SALT_LEN: usize = 16;
+
+const DIR_MODE: u32 = 0o700;
+const FILE_MODE: u32 = 0o600;
+
+enum KeyType {
+    Identity,
+    Signed,
+    OneTime(u16),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    salt: [u8; SALT_LEN],
+    params: KdfParams,
+    /// Known plaintext sealed with the derived key, opens only with the right passphrase.
+    check: Vec<u8>,
+    /// False until plaintext entries of an older keyring have been sealed.
+    migrated: bool,
+}
+
+/// Raw keyring entries by file name, as moved between machines by backups.
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+pub struct KeyringEntries {
+    pub keys: Vec<(String, Vec<u8>)>,
+    pub secrets: Vec<(String, Vec<u8>)>,
+    pub contacts: Vec<(String, Vec<u8>)>,
+}
+
+/// Storage for the secrets of one account. Entries are opaque bytes addressed by a
+/// directory and a name, the typed accessors are built on top of them.
+pub trait KeyStore: Send + Sync {
+    fn read_entry(&self, dir: &str, name: &str) -> Result<Vec<u8>, Error>;
+
+    fn write_entry(&self, dir: &str, name: &str, bytes: &[u8]) -> Result<(), Error>;
+
+    /// Removing a missing entry is not an error.
+    fn remove_entry(&self, dir: &str, name: &str) -> Result<(), Error>;
+
+    /// Directory for the local message cache, stores without one don't cache.
+    fn cache_dir(&self) -> Option<PathBuf> {
+        None
+    }
+
+    fn read_token(&self) -> Result<String, Error> {
+        let token = self.read_entry(ROOT_DIR, TOKEN_FILENAME)?;
+        String::from_utf8(token).map_err(invalid_data)
+    }
+
+    fn save_token(&self, token: &str) -> Result<(), Error> {
+        self.write_entry(ROOT_DIR, TOKEN_FILENAME, token.as_bytes())
+    }
+
+    /// Forgets the session token, keys stay so the next login keeps the same device.
+    fn remove_token(&self) -> Result<(), Error> {
+        self.remove_entry(ROOT_DIR, TOKEN_FILENAME)
+    }
+
+    fn read_identity(&self) -> Result<IdentityKeyPair, Error> {
+        let buffer = self.read_entry(KEYS_DIR, &key_name(KeyType::Identity))?;
+        Ok(IdentityKeyPair::from_bytes(&buffer))
+    }
+
+    fn save_identity(&self, key: &IdentityKeyPair) -> Result<(), Error> {
+        self.write_entry(KEYS_DIR, &key_name(KeyType::Identity), &key.to_bytes())
+    }
+
+    fn read_signed(&self) -> Result<SignedPreKeyPair, Error> {
+        let buffer = self.read_entry(KEYS_DIR, &key_name(KeyType::Signed))?;
+        Ok(SignedPreKeyPair::from_bytes(&buffer))
+    }
+
+    fn save_signed(&self, key: &SignedPreKeyPair) -> Result<(), Error> {
+        self.write_entry(KEYS_DIR, &key_name(KeyType::Signed), &key.to_bytes())
+    }
+
+    fn read_onetime(&self, idx: u16) -> Result<OneTimeKeyPair, Error> {
+        let buffer = self.read_entry(KEYS_DIR, &key_name(KeyType::OneTime(idx)))?;
+        Ok(OneTimeKeyPair::from_bytes(&buffer))
+    }
+
+    fn save_onetime(&self, key: &OneTimeKeyPair) -> Result<(), Error> {
+        self.write_entry(KEYS_DIR, &key_name(KeyType::OneTime(key.index())), &key.to_bytes())
+    }
+
+    fn read_cache_key(&self) -> Result<SealingKey, Error> {
+        let buffer = self.read_entry(KEYS_DIR, CACHE_KEY_FILENAME)?;
+        if buffer.len() != 32 {
+            return Err(Error::new(ErrorKind::InvalidData, "Malformed cache key."));
+        }
+        Ok(SealingKey::from_bytes(&buffer))
+    }
+
+    fn save_cache_key(&self, key: &SealingKey) -> Result<(), Error> {
+        self.write_entry(KEYS_DIR, CACHE_KEY_FILENAME, key.to_bytes())
+    }
+
+    /// Id the server gave this installation of the account.
+    fn read_device(&self) -> Result<ObjectId, Error> {
+        let buffer = self.read_entry(KEYS_DIR, DEVICE_FILENAME)?;
+        let bytes: [u8; 12] = buffer.try_into()
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "Malformed device id."))?;
+        Ok(ObjectId::from_bytes(bytes))
+    }
+
+    fn save_device(&self, device: &ObjectId) -> Result<(), Error> {
+        self.write_entry(KEYS_DIR, DEVICE_FILENAME, &device.bytes())
+    }
+
+    fn read_secret(&self, username: &str, device: &ObjectId) -> Result<X3dhSharedSecret, Error> {
+        let buffer = self.read_entry(SECRET_DIR, &device_entry(username, device))?;
+        Ok(X3dhSharedSecret::from_bytes(&buffer))
+    }
+
+    fn save_secret(&self, username: &str, device: &ObjectId, secret: &X3dhSharedSecret) -> Result<(), Error> {
+        self.write_entry(SECRET_DIR, &device_entry(username, device), secret.to_bytes())
+    }
+
+    /// Identity key a contact device had when the first secret with it was made.
+    fn read_contact(&self, username: &str, device: &ObjectId) -> Result<IdentityKeyPublic, Error> {
+        let buffer = self.read_entry(CONTACTS_DIR, &device_entry(username, device))?;
+        Ok(IdentityKeyPublic::from_bytes(&buffer))
+    }
+
+    fn save_contact(&self, username: &str, device: &ObjectId, identity: &IdentityKeyPublic) -> Result<(), Error> {
+        self.write_entry(CONTACTS_DIR, &device_entry(username, device), &identity.to_bytes())
+    }
+
+    /// Secret and pinned identity of a contact from before devices existed, kept
+    /// under the bare username.
+    fn read_legacy_contact(&self, username: &str) -> Result<(X3dhSharedSecret, IdentityKeyPublic), Error> {
+        let secret = self.read_entry(SECRET_DIR, username)?;
+        let identity = self.read_entry(CONTACTS_DIR, username)?;
+        Ok((X3dhSharedSecret::from_bytes(&secret), IdentityKeyPublic::from_bytes(&identity)))
+    }
+}
+
+/// Key store living only as long as the process, for bots and tests that
+/// register a fresh device on every start.
+#[derive(Default)]
+pub struct MemoryKeyStore {
+    entries: Mutex<HashMap<(String, String), Vec<u8>>>,
+}
+
+impl KeyStore for MemoryKeyStore {
+    fn read_entry(&self, dir: &str, name: &str) -> Result<Vec<u8>, Error> {
+        self.entries.lock()
+            .map_err(|_| Error::other("Key store lock poisoned."))?
+            .get(&(dir.to_owned(), name.to_owned()))
+            .cloned()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "No such key store entry."))
+    }
+
+    fn write_entry(&self, dir: &str, name: &str, bytes: &[u8]) -> Result<(), Error> {
+        self.entries.lock()
+            .map_err(|_| Error::other("Key store lock poisoned."))?
+            .insert((dir.to_owned(), name.to_owned()), bytes.to_vec());
+        Ok(())
+    }
+
+    fn remove_entry(&self, dir: &str, name: &str) -> Result<(), Error> {
+        self.entries.lock()
+            .map_err(|_| Error::other("Key store lock poisoned."))?
+            .remove(&(dir.to_owned(), name.to_owned()));
+        Ok(())
+    }
+}
+
+/// Account secrets on disk, every entry sealed with a key derived from the passphrase.
+#[derive(Clone)]
+pub struct Keyring {
+    path: PathBuf,
+    key: SealingKey,
+}
+
+impl Keyring {
+    /// True when the account keyring already has a passphrase set.
+    pub fn is_initialized(server: &Server, mail: &str) -> Result<bool, Error> {
+        adopt_legacy_dir(server, mail)?;
+        let exists = account_dir(server, mail)?
+            .join(HEADER_FILENAME)
+            .exists();
+        Ok(exists)
+    }
+
+    /// Derives the keyring key, setting up the passphrase if the keyring has none yet.
+    pub fn unlock(server: &Server, mail: &str, passphrase: &str) -> Result<Keyring, Error> {
+        let base = base_dir()?;
+        create_private_dir(&base)?;
+        create_private_dir(&base.join(server.namespace()))?;
+        Self::unlock_at(account_dir(server, mail)?, passphrase, KdfParams::default())
+    }
+
+    /// Removes every local trace of the account: keys, secrets, token and cache.
+    pub fn wipe(server: &Server, mail: &str) -> Result<(), Error> {
+        adopt_legacy_dir(server, mail)?;
+        Self::wipe_at(&account_dir(server, mail)?)
+    }
+
+    fn wipe_at(path: &Path) -> Result<(), Error> {
+        match fs::remove_dir_all(path) {
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    fn unlock_at(path: PathBuf, passphrase: &str, params: KdfParams) -> Result<Keyring, Error> {
+        create_private_dir(&path)?;
+        let header_path = path.join(HEADER_FILENAME);
+        let mut header: Header = match fs::read(&header_path) {
+            Ok(bytes) => bincode::deserialize(&bytes).map_err(invalid_data)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                let salt: [u8; SALT_LEN] = rand::random();
+                let key = derive_key(passphrase, &salt, &params)?;
+                let header = Header {
+                    salt,
+                    params,
+                    check: key.seal(HEADER_CHECK).map_err(invalid_data)?,
+                    migrated: false,
+                };
+                write_private(&header_path, &bincode::serialize(&header).map_err(invalid_data)?)?;
+                header
+            },
+            Err(err) => return Err(err),
+        };
+        let key = derive_key(passphrase, &header.salt, &header.params)?;
+        if key.open(&header.check).is_err() {
+            return Err(Error::new(ErrorKind::PermissionDenied, "Wrong keyring passphrase."));
+        }
+        let keyring = Keyring { path, key };
+        if !header.migrated {
+            keyring.migrate_plaintext()?;
+            header.migrated = true;
+            write_private(&header_path, &bincode::serialize(&header).map_err(invalid_data)?)?;
+        }
+        Ok(keyring)
+    }
+
+    /// Seals entries written by versions that kept the keyring in plaintext.
+    fn migrate_plaintext(&self) -> Result<(), Error> {
+        let mut entries = vec![self.path.join(TOKEN_FILENAME)];
+        for dir in [KEYS_DIR, SECRET_DIR, CONTACTS_DIR] {
+            let dir = self.path.join(dir);
+            if !dir.is_dir() {
+                continue;
+            }
+            create_private_dir(&dir)?;
+            for entry in fs::read_dir(dir)? {
+                entries.push(entry?.path());
+            }
+        }
+        for path in entries.into_iter().filter(|p| p.is_file()) {
+            let bytes = fs::read(&path)?;
+            // Entries sealed before an interrupted migration are left as they are.
+            if self.key.open(&bytes).is_err() {
+                self.write_sealed(&path, &bytes)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Everything needed to restore the account elsewhere. The cache key and device
+    /// id stay local, a restored machine registers as a device of its own.
+    pub fn export_entries(&self) -> Result<KeyringEntries, Error> {
+        let keys = self.read_dir_entries(KEYS_DIR)?
+            .into_iter()
+            .filter(|(name, _)| !is_local_key(name))
+            .collect();
+        Ok(KeyringEntries {
+            keys,
+            secrets: self.read_dir_entries(SECRET_DIR)?,
+            contacts: self.read_dir_entries(CONTACTS_DIR)?,
+        })
+    }
+
+    pub fn import_entries(&self, entries: &KeyringEntries) -> Result<(), Error> {
+        let dirs = [
+            (KEYS_DIR, &entries.keys),
+            (SECRET_DIR, &entries.secrets),
+            (CONTACTS_DIR, &entries.contacts),
+        ];
+        for (dir_name, entries) in dirs {
+            let dir = self.path.join(dir_name);
+            create_private_dir(&dir)?;
+            for (name, bytes) in entries.iter() {
+                if !is_entry_name(name) {
+                    return Err(Error::new(ErrorKind::InvalidData, "Malformed keyring entry name."));
+                }
+                // Backups made by older versions still carry them.
+                if dir_name == KEYS_DIR && is_local_key(name) {
+                    continue;
+                }
+                self.write_sealed(&dir.join(name), bytes)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_dir_entries(&self, dir: &str) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        let dir = self.path.join(dir);
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) if is_entry_name(name) && path.is_file() => name.to_owned(),
+                _ => continue,
+            };
+            entries.push((name, self.read_sealed(&path)?));
+        }
+        entries.sort();
+        Ok(entries)
+    }
+
+    fn read_sealed(&self, path: &Path) -> Result<Vec<u8>, Error> {
+        let sealed = fs::read(path)?;
+        self.key.open(&sealed)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "Keyring entry is corrupted."))
+    }
+
+    fn write_sealed(&self, path: &Path, bytes: &[u8]) -> Result<(), Error> {
+        let sealed = self.key.seal(bytes).map_err(invalid_data)?;
+        write_private(path, &sealed)
+    }
+}
+
+impl KeyStore for Keyring {
+    fn read_entry(&self, dir: &str, name: &str) -> Result<Vec<u8>, Error> {
+        self.read_sealed(&self.path.join(dir).join(name))
+    }
+
+    fn write_entry(&self, dir: &str, name: &str, bytes: &[u8]) -> Result<(), Error> {
+        let dir = self.path.join(dir);
+        create_private_dir(&dir)?;
+        self.write_sealed(&dir.join(name), bytes)
+    }
+
+    fn remove_entry(&self, dir: &str, name: &str) -> Result<(), Error> {
+        match fs::remove_file(self.path.join(dir).join(name)) {
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    fn cache_dir(&self) -> Option<PathBuf> {
+        Some(self.path.join(CACHE_DIR))
+    }
+}
+
+fn base_dir() -> Result<PathBuf, Error> {
+    let path = home_dir()
+        .ok_or(Error::new(ErrorKind::NotFound, "Impossible to get home directory."))?
+        .join(BASE_PATH);
+    Ok(path)
+}
+
+/// Keyrings are kept per server, so one mail can have accounts on several servers.
+fn account_dir(server: &Server, mail: &str) -> Result<PathBuf, Error> {
+    let path = base_dir()?
+        .join(server.namespace())
+        .join(mail);
+    Ok(path)
+}
+
+/// Moves a keyring from before servers were configurable under the local server.
+fn adopt_legacy_dir(server: &Server, mail: &str) -> Result<(), Error> {
+    let legacy = base_dir()?.join(mail);
+    let path = account_dir(server, mail)?;
+    if !server.is_local() || !legacy.is_dir() || path.exists() {
+        return Ok(());
+    }
+    create_private_dir(&base_dir()?.join(server.namespace()))?;
+    fs::rename(legacy, path)
+}
+
+/// Usernames never contain a dot, so the entry name stays unambiguous.
+fn device_entry(username: &str, device: &ObjectId) -> String {
+    format!("{}.{}", username, device.to_hex())
+}
+
+fn key_name(key_type: KeyType) -> String {
+    match key_type {
+        KeyType::Identity => String::from("identity"),
+        KeyType::Signed => String::from("signed"),
+        KeyType::OneTime(idx) => format!("onetime_{}", idx),
+    }
+}
+
+/// Entry names are usernames or key names, never paths or leftover temporaries.
+fn is_entry_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && !name.ends_with(".tmp")
+        && !name.contains(['/', '\\'])
+}
+
+/// Keys tied to this machine, left out of backups.
+fn is_local_key(name: &str) -> bool {
+    name == CACHE_KEY_FILENAME || name == DEVICE_FILENAME
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], params: &KdfParams) -> Result<SealingKey, Error> {
+    SealingKey::derive(passphrase, salt, params).map_err(invalid_data)
+}
+
+fn invalid_data(err: impl Display) -> Error {
+    Error::new(ErrorKind::InvalidData, err.to_string())
+}
+
+/// Creates directory readable only by the owner, tightening it if it already exists.
+pub fn create_private_dir(path: &Path) -> Result<(), Error> {
+    create_dir_all(path)?;
+    restrict(path, DIR_MODE)
+}
+
+/// Atomically replaces file with one readable only by the owner. The temporary
+/// file keeps the whole name, entries differ only in what follows the dot.
+pub fn write_private(path: &Path, bytes: &[u8]) -> Result<(), Error> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut file = File::create(&tmp)?;
+    restrict(&tmp, FILE_MODE)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(tmp, path)
+}
+
+#[cfg(unix)]
+fn restrict(path: &Path, mode: u32) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn restrict(_path: &Path, _mode: u32) -> Result<(), Error> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod keyring_test {
+    use std::{fs, io::ErrorKind, path::PathBuf};
+    use crate::cipher::KdfParams;
+    use bson::oid::ObjectId;
+    use super::{write_private, Keyring, KeyringEntries, KeyStore, MemoryKeyStore, TOKEN_FILENAME, KEYS_DIR, DEVICE_FILENAME};
+
+    const CHEAP: KdfParams = KdfParams { memory_kib: 64, iterations: 1, parallelism: 1 };
+
+    fn temp_account() -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("plasmax-keyring-{}", rand::random::<u64>()))
+    }
+
+    #[test]
+    fn unlock_roundtrip() {
+        let path = temp_account();
+        let keyring = Keyring::unlock_at(path.clone(), "passphrase", CHEAP).unwrap();
+        keyring.save_token("token").unwrap();
+
+        let stored = fs::read(path.join(TOKEN_FILENAME)).unwrap();
+        assert_ne!(stored, b"token");
+
+        let keyring = Keyring::unlock_at(path.clone(), "passphrase", CHEAP).unwrap();
+        assert_eq!(keyring.read_token().unwrap(), "token");
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn wipe_forgets_passphrase() {
+        let path = temp_account();
+        let keyring = Keyring::unlock_at(path.clone(), "passphrase", CHEAP).unwrap();
+        keyring.save_token("token").unwrap();
+
+        Keyring::wipe_at(&path).unwrap();
+        assert!(!path.exists());
+        Keyring::wipe_at(&path).unwrap();
+
+        let keyring = Keyring::unlock_at(path.clone(), "other", CHEAP).unwrap();
+        assert_eq!(keyring.read_token().unwrap_err().kind(), ErrorKind::NotFound);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn unlock_wrong_passphrase() {
+        let path = temp_account();
+        Keyring::unlock_at(path.clone(), "passphrase", CHEAP).unwrap();
+
+        let err = Keyring::unlock_at(path.clone(), "other", CHEAP).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn migrates_plaintext() {
+        let path = temp_account();
+        fs::create_dir_all(path.join(KEYS_DIR)).unwrap();
+        fs::write(path.join(TOKEN_FILENAME), b"token").unwrap();
+        fs::write(path.join(KEYS_DIR).join("signed"), [7u8; 32]).unwrap();
+
+        let keyring = Keyring::unlock_at(path.clone(), "passphrase", CHEAP).unwrap();
+
+        assert_eq!(keyring.read_token().unwrap(), "token");
+        assert_eq!(keyring.read_sealed(&path.join(KEYS_DIR).join("signed")).unwrap(), [7u8; 32]);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(path.join(TOKEN_FILENAME)).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn entries_roundtrip() {
+        let source_path = temp_account();
+        let target_path = temp_account();
+        let source = Keyring::unlock_at(source_path.clone(), "passphrase", CHEAP).unwrap();
+        let target = Keyring::unlock_at(target_path.clone(), "other", CHEAP).unwrap();
+        let entries = KeyringEntries {
+            keys: vec![(String::from("identity"), vec![1u8; 32])],
+            secrets: vec![(String::from("bob"), vec![2u8; 32])],
+            contacts: Vec::new(),
+        };
+        source.import_entries(&entries).unwrap();
+
+        target.import_entries(&source.export_entries().unwrap()).unwrap();
+
+        assert_eq!(target.export_entries().unwrap(), entries);
+        fs::remove_dir_all(source_path).unwrap();
+        fs::remove_dir_all(target_path).unwrap();
+    }
+
+    #[test]
+    fn entries_leave_out_device() {
+        let source_path = temp_account();
+        let target_path = temp_account();
+        let source = Keyring::unlock_at(source_path.clone(), "passphrase", CHEAP).unwrap();
+        let target = Keyring::unlock_at(target_path.clone(), "other", CHEAP).unwrap();
+        source.save_device(&ObjectId::new()).unwrap();
+        let mut entries = source.export_entries().unwrap();
+        assert!(entries.keys.is_empty());
+
+        entries.keys.push((String::from(DEVICE_FILENAME), ObjectId::new().bytes().to_vec()));
+        target.import_entries(&entries).unwrap();
+
+        assert_eq!(target.read_device().unwrap_err().kind(), ErrorKind::NotFound);
+        fs::remove_dir_all(source_path).unwrap();
+        fs::remove_dir_all(target_path).unwrap();
+    }
+
+    #[test]
+    fn write_keeps_whole_name() {
+        let path = temp_account();
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join("session.tmp"), b"other").unwrap();
+        write_private(&path.join("session.abc"), b"entry").unwrap();
+
+        assert_eq!(fs::read(path.join("session.abc")).unwrap(), b"entry");
+        assert_eq!(fs::read(path.join("session.tmp")).unwrap(), b"other");
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn memory_store_entries() {
+        let store = MemoryKeyStore::default();
+        let device = ObjectId::new();
+        store.save_device(&device).unwrap();
+        store.save_token("token").unwrap();
+
+        assert_eq!(store.read_device().unwrap(), device);
+        store.remove_token().unwrap();
+        assert_eq!(store.read_token().err().unwrap().kind(), ErrorKind::NotFound);
+        assert!(store.cache_dir().is_none());
+    }
+
+    #[test]
+    fn import_rejects_paths() {
+        let path = temp_account();
+        let keyring = Keyring::unlock_at(path.clone(), "passphrase", CHEAP).unwrap();
+        let entries = KeyringEntries {
+            secrets: vec![(String::from("../token"), vec![0u8])],
+            ..Default::default()
+        };
+
+        assert!(keyring.import_entries(&entries).is_err());
+        fs::remove_dir_all(path).unwrap();
+    }
+}
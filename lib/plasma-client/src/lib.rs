@@ -0,0 +1,19 @@
+pub mod account;
+pub mod api;
+pub mod backup;
+pub mod chats;
+pub mod cipher;
+pub mod client;
+pub mod envelope;
+pub mod error;
+pub mod keyring;
+pub mod search;
+pub mod server;
+pub mod session;
+pub mod store;
+
+pub use client::{Client, OpenChat, Incoming, IncomingMessage};
+pub use envelope::Envelope;
+pub use error::PlasmaError;
+pub use keyring::{KeyStore, Keyring, MemoryKeyStore};
+pub use server::Server;
@@ -0,0 +1,123 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use crate::store::CachedHistory;
+
+/// Most hits returned by a single search.
+pub const MAX_HITS: usize = 50;
+
+/// Message found by a search, newest hits come first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    pub chat_id: ObjectId,
+    pub timestamp: u64,
+}
+
+/// Full-text index of decrypted messages, kept sealed in the local store.
+/// Every chat has its own words mapped to the timestamps of the messages
+/// containing them, so a chat is reindexed without touching the others.
+#[derive(Serialize, Deserialize, Default)]
+pub struct SearchIndex {
+    chats: HashMap<[u8; 12], BTreeMap<String, BTreeSet<u64>>>,
+}
+
+/// Lowercase words of `text`, anything but letters and digits separates them.
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+impl SearchIndex {
+    /// Replaces the entries of a chat with its current history, deleted
+    /// messages are left out.
+    pub fn index_chat(&mut self, chat_id: &ObjectId, history: &CachedHistory) {
+        let mut index: BTreeMap<String, BTreeSet<u64>> = BTreeMap::new();
+        for message in history.messages.iter().filter(|m| !m.deleted) {
+            for word in words(&message.content) {
+                index.entry(word).or_default().insert(message.timestamp);
+            }
+        }
+        self.chats.insert(chat_id.bytes(), index);
+    }
+
+    pub fn has_chat(&self, chat_id: &ObjectId) -> bool {
+        self.chats.contains_key(&chat_id.bytes())
+    }
+
+    pub fn remove_chat(&mut self, chat_id: &ObjectId) {
+        self.chats.remove(&chat_id.bytes());
+    }
+
+    /// Messages containing every word of `query`, each one may be the start
+    /// of a longer word.
+    pub fn search(&self, query: &str) -> Vec<SearchHit> {
+        let query: Vec<String> = words(query).collect();
+        if query.is_empty() {
+            return Vec::new();
+        }
+        let mut hits: Vec<SearchHit> = self.chats.iter()
+            .flat_map(|(chat_id, index)| {
+                Self::matching(index, &query)
+                    .into_iter()
+                    .map(|timestamp| SearchHit { chat_id: ObjectId::from_bytes(*chat_id), timestamp })
+            })
+            .collect();
+        hits.sort_by_key(|hit| std::cmp::Reverse(hit.timestamp));
+        hits.truncate(MAX_HITS);
+        hits
+    }
+
+    fn matching(index: &BTreeMap<String, BTreeSet<u64>>, query: &[String]) -> BTreeSet<u64> {
+        let mut matching: Option<BTreeSet<u64>> = None;
+        for word in query {
+            let found: BTreeSet<u64> = index.range(word.clone()..)
+                .take_while(|(indexed, _)| indexed.starts_with(word.as_str()))
+                .flat_map(|(_, timestamps)| timestamps.iter().copied())
+                .collect();
+            matching = Some(match matching {
+                Some(matching) => matching.intersection(&found).copied().collect(),
+                None => found,
+            });
+        }
+        matching.unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod search_test {
+    use bson::oid::ObjectId;
+    use crate::store::{CachedHistory, CachedMessage};
+    use super::{SearchHit, SearchIndex};
+
+    fn history(messages: &[(&str, u64)]) -> CachedHistory {
+        let messages = messages.iter()
+            .map(|(content, timestamp)| CachedMessage::new(None, "user", content, *timestamp, None))
+            .collect();
+        CachedHistory::new(messages, false, std::iter::empty())
+    }
+
+    #[test]
+    fn every_word_must_match() {
+        let mut index = SearchIndex::default();
+        let chat = ObjectId::new();
+        index.index_chat(&chat, &history(&[("Meet at the Station", 1), ("station closed", 2), ("meeting later", 3)]));
+
+        assert_eq!(index.search("station"), vec![SearchHit { chat_id: chat, timestamp: 2 }, SearchHit { chat_id: chat, timestamp: 1 }]);
+        assert_eq!(index.search("meet stat"), vec![SearchHit { chat_id: chat, timestamp: 1 }]);
+        assert!(index.search("  ").is_empty());
+    }
+
+    #[test]
+    fn reindex_replaces_chat() {
+        let mut index = SearchIndex::default();
+        let (chat, other) = (ObjectId::new(), ObjectId::new());
+        index.index_chat(&chat, &history(&[("hello", 1)]));
+        index.index_chat(&other, &history(&[("hello there", 2)]));
+        index.index_chat(&chat, &history(&[("bye", 1)]));
+
+        assert_eq!(index.search("hello"), vec![SearchHit { chat_id: other, timestamp: 2 }]);
+        index.remove_chat(&other);
+        assert!(index.search("hello").is_empty());
+    }
+}
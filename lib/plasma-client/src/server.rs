@@ -0,0 +1,102 @@
+use thiserror::Error;
+use url::Url;
+
+const LOCAL_URL: &str = "http://localhost:8000";
+const WS_ENDPOINT: &str = "chat";
+
+#[derive(Error, Debug)]
+#[error("Invalid server url {0}, expected http or https")]
+pub struct InvalidServerUrl(pub String);
+
+/// Server a client talks to, both the REST api and the websocket hang off its base url.
+#[derive(Clone, Debug)]
+pub struct Server {
+    name: String,
+    base: Url,
+}
+
+impl Server {
+    pub fn new(name: &str, url: &str) -> Result<Server, InvalidServerUrl> {
+        let mut base = Url::parse(url)
+            .map_err(|_| InvalidServerUrl(url.to_owned()))?;
+        if !matches!(base.scheme(), "http" | "https") || base.cannot_be_a_base() {
+            return Err(InvalidServerUrl(url.to_owned()));
+        }
+        // Without the trailing slash endpoints would replace the last path segment.
+        if !base.path().ends_with('/') {
+            let path = format!("{}/", base.path());
+            base.set_path(&path);
+        }
+        Ok(Server { name: name.to_owned(), base })
+    }
+
+    /// Server running on this machine, as used during development.
+    pub fn local(name: &str) -> Server {
+        Server::new(name, LOCAL_URL)
+            .expect("Hardcoded local url")
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn api_url(&self) -> &Url {
+        &self.base
+    }
+
+    /// Websocket endpoint on the same host, `wss` when the api is served over https.
+    pub fn ws_url(&self) -> Url {
+        let mut url = self.base.join(WS_ENDPOINT)
+            .expect("Endpoint is a valid relative url");
+        let scheme = match self.base.scheme() {
+            "https" => "wss",
+            _ => "ws",
+        };
+        url.set_scheme(scheme)
+            .expect("Websocket schemes are valid for http urls");
+        url
+    }
+
+    /// Directory name keeping keyrings of the same mail on different servers apart.
+    pub fn namespace(&self) -> String {
+        let host = self.base.host_str().unwrap_or_default();
+        match self.base.port() {
+            Some(port) => format!("{}-{}", host, port),
+            None => host.to_owned(),
+        }
+    }
+
+    /// Keyrings from before namespacing were all made against the local server.
+    pub fn is_local(&self) -> bool {
+        self.base.as_str() == Server::local("").base.as_str()
+    }
+}
+
+#[cfg(test)]
+mod server_test {
+    use super::Server;
+
+    #[test]
+    fn urls_from_base() {
+        let server = Server::new("home", "https://chat.example.org/plasma").unwrap();
+
+        assert_eq!(server.api_url().join("login").unwrap().as_str(), "https://chat.example.org/plasma/login");
+        assert_eq!(server.ws_url().as_str(), "wss://chat.example.org/plasma/chat");
+        assert_eq!(server.namespace(), "chat.example.org");
+    }
+
+    #[test]
+    fn local_server() {
+        let server = Server::local("local");
+
+        assert!(server.is_local());
+        assert_eq!(server.ws_url().as_str(), "ws://localhost:8000/chat");
+        assert_eq!(server.namespace(), "localhost-8000");
+    }
+
+    #[test]
+    fn rejects_other_schemes() {
+        assert!(Server::new("files", "ftp://files.org").is_err());
+        assert!(Server::new("none", "not a url").is_err());
+    }
+}
@@ -0,0 +1,85 @@
+use std::{collections::HashMap, time::{SystemTime, UNIX_EPOCH}};
+use bson::oid::ObjectId;
+use crate::{cipher::{Cipher, CipherError}, envelope::Envelope};
+
+/// Shown in place of messages sent before this device was linked.
+pub const UNREADABLE: &str = "[message not encrypted for this device]";
+
+/// Ciphers shared with every other device taking part in a chat, keyed by device.
+#[derive(Default)]
+pub struct ChatSession {
+    ciphers: HashMap<ObjectId, Cipher>,
+}
+
+impl ChatSession {
+    pub fn contains(&self, device: &ObjectId) -> bool {
+        self.ciphers.contains_key(device)
+    }
+
+    pub fn insert(&mut self, device: ObjectId, cipher: Cipher) {
+        self.ciphers.insert(device, cipher);
+    }
+
+    /// Encrypts envelope separately for every device of the session.
+    pub fn encrypt(&self, envelope: &Envelope, timestamp: u64) -> Result<Vec<(ObjectId, Vec<u8>)>, CipherError> {
+        let plaintext = envelope.encode();
+        self.ciphers.iter()
+            .map(|(device, cipher)| Ok((*device, cipher.encrypt(&plaintext, timestamp)?)))
+            .collect()
+    }
+
+    pub fn decrypt(&self, sender_device: &ObjectId, content: &[u8], timestamp: u64) -> Result<Envelope, CipherError> {
+        let plaintext = self.ciphers.get(sender_device)
+            .ok_or(CipherError::NoSession)?
+            .decrypt(content, timestamp)?;
+        Envelope::decode(&plaintext)
+    }
+}
+
+/// Message timestamps are microseconds since the epoch, each one is bound into its ciphertext.
+pub fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_micros() as u64
+}
+
+/// When a message sent at `timestamp` disappears under a timer of `seconds`.
+pub fn expiry(timestamp: u64, seconds: Option<u64>) -> Option<u64> {
+    seconds.map(|seconds| timestamp + seconds * 1_000_000)
+}
+
+#[cfg(test)]
+mod session_test {
+    use bson::oid::ObjectId;
+    use x3dh::keys::X3dhSharedSecret;
+    use crate::{cipher::Cipher, envelope::Envelope};
+    use super::ChatSession;
+
+    fn cipher(byte: u8) -> Cipher {
+        Cipher::new(X3dhSharedSecret::from_bytes(&[byte; 32]))
+    }
+
+    #[test]
+    fn encrypt_for_every_device() {
+        let (device1, device2) = (ObjectId::new(), ObjectId::new());
+        let mut session = ChatSession::default();
+        session.insert(device1, cipher(1));
+        session.insert(device2, cipher(2));
+
+        let envelope = Envelope::Text(String::from("message"));
+        let payloads = session.encrypt(&envelope, 7).unwrap();
+
+        assert_eq!(payloads.len(), 2);
+        for (device, content) in payloads {
+            assert_eq!(session.decrypt(&device, &content, 7).unwrap(), envelope);
+        }
+    }
+
+    #[test]
+    fn decrypt_unknown_device() {
+        let session = ChatSession::default();
+
+        assert!(session.decrypt(&ObjectId::new(), b"content", 7).is_err());
+    }
+}
@@ -0,0 +1,263 @@
+use std::{path::PathBuf, fs, io::ErrorKind};
+use bson::oid::ObjectId;
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use thiserror::Error;
+use crate::{keyring::{self, KeyStore}, cipher::{SealingKey, CipherError}, chats::{Chat, UserHandle}, search::SearchIndex};
+
+const HISTORY_DIR: &str = "history";
+const PROFILE_FILENAME: &str = "profile";
+const CHATS_FILENAME: &str = "chats";
+const INDEX_FILENAME: &str = "index";
+
+#[derive(Error, Debug)]
+pub enum StoreError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Encoding(#[from] bincode::Error),
+    #[error("Local cache is corrupted: {0}")]
+    Cipher(#[from] CipherError),
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CachedProfile {
+    pub username: String,
+    id: [u8; 12],
+}
+
+impl CachedProfile {
+    pub fn new(username: &str, id: &ObjectId) -> Self {
+        CachedProfile { username: username.to_owned(), id: id.bytes() }
+    }
+
+    pub fn id(&self) -> ObjectId {
+        ObjectId::from_bytes(self.id)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedChat {
+    id: [u8; 12],
+    user_id: [u8; 12],
+    username: String,
+}
+
+/// Progress of a message sent from this account, each state implies the previous ones.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MessageStatus {
+    Sent,
+    Stored,
+    Delivered,
+    Read,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CachedMessage {
+    id: Option<[u8; 12]>,
+    pub username: String,
+    pub content: String,
+    pub timestamp: u64,
+    pub status: Option<MessageStatus>,
+    pub edited: bool,
+    pub deleted: bool,
+    pub expires_at: Option<u64>,
+    reply_to: Option<[u8; 12]>,
+}
+
+impl CachedMessage {
+    pub fn new(id: Option<ObjectId>, username: &str, content: &str, timestamp: u64, status: Option<MessageStatus>) -> Self {
+        CachedMessage {
+            id: id.map(|id| id.bytes()),
+            username: username.to_owned(),
+            content: content.to_owned(),
+            timestamp,
+            status,
+            edited: false,
+            deleted: false,
+            expires_at: None,
+            reply_to: None,
+        }
+    }
+
+    pub fn replying_to(mut self, parent: Option<ObjectId>) -> Self {
+        self.reply_to = parent.map(|id| id.bytes());
+        self
+    }
+
+    pub fn id(&self) -> Option<ObjectId> {
+        self.id.map(ObjectId::from_bytes)
+    }
+
+    pub fn reply_to(&self) -> Option<ObjectId> {
+        self.reply_to.map(ObjectId::from_bytes)
+    }
+}
+
+/// Latest reaction event of a user with an emoji on a message.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CachedReaction {
+    target: [u8; 12],
+    pub username: String,
+    pub emoji: String,
+    pub timestamp: u64,
+    pub added: bool,
+}
+
+impl CachedReaction {
+    pub fn new(target: &ObjectId, username: &str, emoji: &str, timestamp: u64, added: bool) -> Self {
+        CachedReaction {
+            target: target.bytes(),
+            username: username.to_owned(),
+            emoji: emoji.to_owned(),
+            timestamp,
+            added,
+        }
+    }
+
+    pub fn target(&self) -> ObjectId {
+        ObjectId::from_bytes(self.target)
+    }
+}
+
+/// Decrypted history of a chat together with its sync cursors, the messages
+/// deleted only on this device so syncing doesn't bring them back, the
+/// disappearing messages timer agreed on in the chat, its reactions and how
+/// far the peer was told its messages were read.
+#[derive(Serialize, Deserialize, Default)]
+pub struct CachedHistory {
+    pub messages: Vec<CachedMessage>,
+    pub has_older: bool,
+    hidden: Vec<[u8; 12]>,
+    pub timer: Option<u64>,
+    pub reactions: Vec<CachedReaction>,
+    pub read_sent: Option<u64>,
+}
+
+impl CachedHistory {
+    pub fn new(messages: Vec<CachedMessage>, has_older: bool, hidden: impl Iterator<Item = ObjectId>) -> Self {
+        CachedHistory {
+            messages,
+            has_older,
+            hidden: hidden.map(|id| id.bytes()).collect(),
+            timer: None,
+            reactions: Vec::new(),
+            read_sent: None,
+        }
+    }
+
+    pub fn hidden(&self) -> impl Iterator<Item = ObjectId> + '_ {
+        self.hidden.iter().map(|id| ObjectId::from_bytes(*id))
+    }
+}
+
+/// Per account local data, sealed with a key kept in the keyring.
+pub struct Store {
+    path: PathBuf,
+    key: SealingKey,
+}
+
+impl Store {
+    /// Opens the cache of an account, `None` when its key store keeps no local files.
+    pub fn open(keys: &dyn KeyStore) -> Result<Option<Store>, StoreError> {
+        let path = match keys.cache_dir() {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let key = match keys.read_cache_key() {
+            Ok(key) => key,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                let key = SealingKey::generate();
+                keys.save_cache_key(&key)?;
+                key
+            },
+            Err(err) => return Err(err.into()),
+        };
+        keyring::create_private_dir(&path)?;
+        keyring::create_private_dir(&path.join(HISTORY_DIR))?;
+        Ok(Some(Store { path, key }))
+    }
+
+    pub fn read_profile(&self) -> Result<Option<CachedProfile>, StoreError> {
+        self.read(self.path.join(PROFILE_FILENAME))
+    }
+
+    pub fn save_profile(&self, profile: &CachedProfile) -> Result<(), StoreError> {
+        self.write(self.path.join(PROFILE_FILENAME), profile)
+    }
+
+    pub fn read_chats(&self) -> Result<Vec<Chat>, StoreError> {
+        let chats: Vec<CachedChat> = self.read(self.path.join(CHATS_FILENAME))?
+            .unwrap_or_default();
+        let chats = chats.into_iter()
+            .map(|c| Chat {
+                id: ObjectId::from_bytes(c.id),
+                user: UserHandle {
+                    id: ObjectId::from_bytes(c.user_id),
+                    username: c.username,
+                },
+            })
+            .collect();
+        Ok(chats)
+    }
+
+    pub fn save_chats(&self, chats: &[Chat]) -> Result<(), StoreError> {
+        let chats: Vec<CachedChat> = chats.iter()
+            .map(|c| CachedChat {
+                id: c.id.bytes(),
+                user_id: c.user.id.bytes(),
+                username: c.user.username.clone(),
+            })
+            .collect();
+        self.write(self.path.join(CHATS_FILENAME), &chats)
+    }
+
+    /// History cached by an older version is dropped and fetched again.
+    pub fn read_history(&self, chat_id: &ObjectId) -> Result<CachedHistory, StoreError> {
+        match self.read(self.history_path(chat_id)) {
+            Ok(history) => Ok(history.unwrap_or_default()),
+            Err(StoreError::Encoding(_)) => Ok(CachedHistory::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn save_history(&self, chat_id: &ObjectId, history: &CachedHistory) -> Result<(), StoreError> {
+        self.write(self.history_path(chat_id), history)
+    }
+
+    /// Index built by an older version is dropped, histories are indexed again as they are saved.
+    pub fn read_index(&self) -> Result<SearchIndex, StoreError> {
+        match self.read(self.path.join(INDEX_FILENAME)) {
+            Ok(index) => Ok(index.unwrap_or_default()),
+            Err(StoreError::Encoding(_)) => Ok(SearchIndex::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn save_index(&self, index: &SearchIndex) -> Result<(), StoreError> {
+        self.write(self.path.join(INDEX_FILENAME), index)
+    }
+
+    fn history_path(&self, chat_id: &ObjectId) -> PathBuf {
+        self.path
+            .join(HISTORY_DIR)
+            .join(chat_id.to_hex())
+    }
+
+    fn read<T: DeserializeOwned>(&self, path: PathBuf) -> Result<Option<T>, StoreError> {
+        let sealed = match fs::read(path) {
+            Ok(sealed) => sealed,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let bytes = self.key.open(&sealed)?;
+        let value = bincode::deserialize(&bytes)?;
+        Ok(Some(value))
+    }
+
+    fn write<T: Serialize>(&self, path: PathBuf, value: &T) -> Result<(), StoreError> {
+        let bytes = bincode::serialize(value)?;
+        let sealed = self.key.seal(&bytes)?;
+        keyring::write_private(&path, &sealed)?;
+        Ok(())
+    }
+}